@@ -0,0 +1,336 @@
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use rusqlite::{Connection, params};
+use serde::{Deserialize, Serialize};
+
+use crate::{container::ExecOutput, error::BoaError};
+
+/// Records that are returned when a query does not pass a limit
+const DEFAULT_QUERY_LIMIT: u32 = 100;
+const MAX_QUERY_LIMIT: u32 = 10_000;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS execs (
+    exec_id       TEXT PRIMARY KEY,
+    connection_id TEXT NOT NULL,
+    remote_addr   TEXT,
+    container_id  TEXT NOT NULL,
+    image         TEXT NOT NULL,
+    kind          TEXT NOT NULL,
+    path          TEXT NOT NULL,
+    code_sha256   TEXT,
+    started_at    INTEGER NOT NULL,
+    finished_at   INTEGER,
+    outcome       TEXT NOT NULL,
+    exit_code     INTEGER,
+    kill_reason   TEXT,
+    error         TEXT,
+    stdout_bytes  INTEGER NOT NULL DEFAULT 0,
+    stderr_bytes  INTEGER NOT NULL DEFAULT 0
+);
+CREATE INDEX IF NOT EXISTS execs_started_at ON execs (started_at);
+CREATE INDEX IF NOT EXISTS execs_connection_id ON execs (connection_id);
+CREATE INDEX IF NOT EXISTS execs_container_id ON execs (container_id);
+";
+
+/// Applied in order on top of `SCHEMA`, the database `user_version` counts the applied ones
+const MIGRATIONS: &[&str] = &["
+ALTER TABLE execs ADD COLUMN identity TEXT;
+CREATE INDEX IF NOT EXISTS execs_identity ON execs (identity);
+"];
+
+/// How an exec ended, `Running` until it does
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExecOutcome {
+    Running,
+    Finished,
+    TimedOut,
+    Killed,
+    Failed,
+}
+
+impl ExecOutcome {
+    fn as_str(self) -> &'static str {
+        match self {
+            ExecOutcome::Running => "running",
+            ExecOutcome::Finished => "finished",
+            ExecOutcome::TimedOut => "timed_out",
+            ExecOutcome::Killed => "killed",
+            ExecOutcome::Failed => "failed",
+        }
+    }
+
+    fn parse(outcome: &str) -> ExecOutcome {
+        match outcome {
+            "finished" => ExecOutcome::Finished,
+            "timed_out" => ExecOutcome::TimedOut,
+            "killed" => ExecOutcome::Killed,
+            "failed" => ExecOutcome::Failed,
+            _ => ExecOutcome::Running,
+        }
+    }
+}
+
+/// One exec, times are unix milliseconds
+#[derive(Debug, Clone, Serialize)]
+pub struct ExecRecord {
+    pub exec_id: String,
+    pub connection_id: String,
+    pub remote_addr: Option<String>,
+    pub identity: Option<String>,
+    pub container_id: String,
+    pub image: String,
+    pub kind: String,
+    pub path: String,
+    pub code_sha256: Option<String>,
+    pub started_at: i64,
+    pub finished_at: Option<i64>,
+    pub outcome: ExecOutcome,
+    pub exit_code: Option<i64>,
+    pub kill_reason: Option<String>,
+    pub error: Option<String>,
+    pub stdout_bytes: u64,
+    pub stderr_bytes: u64,
+}
+
+/// The end of an exec, written over its start record
+pub struct ExecFinish {
+    pub outcome: ExecOutcome,
+    pub exit_code: Option<i64>,
+    pub kill_reason: Option<String>,
+    pub error: Option<String>,
+    pub stdout_bytes: u64,
+    pub stderr_bytes: u64,
+}
+
+impl ExecFinish {
+    pub fn new(
+        result: &Result<ExecOutput, BoaError>,
+        timeout_secs: u64,
+        kill_reason: Option<String>,
+    ) -> ExecFinish {
+        match result {
+            Ok(output) => ExecFinish {
+                outcome: if kill_reason.is_some() {
+                    ExecOutcome::Killed
+                } else if output.result(timeout_secs).is_err() {
+                    ExecOutcome::TimedOut
+                } else {
+                    ExecOutcome::Finished
+                },
+                exit_code: Some(output.exit_code),
+                error: output.result(timeout_secs).err().map(|e| e.to_string()),
+                kill_reason,
+                stdout_bytes: output.stdout_bytes,
+                stderr_bytes: output.stderr_bytes,
+            },
+            Err(e) => ExecFinish {
+                outcome: if kill_reason.is_some() {
+                    ExecOutcome::Killed
+                } else {
+                    ExecOutcome::Failed
+                },
+                exit_code: None,
+                kill_reason,
+                error: Some(e.to_string()),
+                stdout_bytes: 0,
+                stderr_bytes: 0,
+            },
+        }
+    }
+}
+
+/// Filters for the audit query api, every filter is optional
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuditQuery {
+    pub connection_id: Option<String>,
+    pub container_id: Option<String>,
+    pub remote_addr: Option<String>,
+    pub identity: Option<String>,
+    pub code_sha256: Option<String>,
+    pub outcome: Option<ExecOutcome>,
+    /// Only execs started at or after this unix millisecond
+    pub since: Option<i64>,
+    /// Only execs started before this unix millisecond
+    pub until: Option<i64>,
+    pub limit: Option<u32>,
+}
+
+/// Append only record of every exec, kept in sqlite so it outlives the containers
+#[derive(Clone)]
+pub struct AuditLog {
+    connection: Arc<Mutex<Connection>>,
+}
+
+pub fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .ok()
+        .and_then(|now| i64::try_from(now.as_millis()).ok())
+        .unwrap_or_default()
+}
+
+fn migrate(connection: &Connection) -> rusqlite::Result<()> {
+    let version: usize = connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+
+    for (applied, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        connection.execute_batch(&format!(
+            "BEGIN; {migration} PRAGMA user_version = {}; COMMIT;",
+            applied + 1
+        ))?;
+    }
+
+    Ok(())
+}
+
+impl AuditLog {
+    pub fn open(path: &Path) -> Result<AuditLog, String> {
+        let connection = Connection::open(path)
+            .map_err(|e| format!("failed to open audit log {}: {e}", path.display()))?;
+
+        connection
+            .execute_batch(SCHEMA)
+            .map_err(|e| format!("failed to create audit log schema: {e}"))?;
+
+        migrate(&connection).map_err(|e| format!("failed to migrate audit log: {e}"))?;
+
+        Ok(AuditLog {
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    /// Runs `f` on the connection without blocking the async runtime
+    async fn with_connection<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&Connection) -> rusqlite::Result<T> + Send + 'static,
+    ) -> Result<T, BoaError> {
+        let connection = Arc::clone(&self.connection);
+
+        tokio::task::spawn_blocking(move || {
+            let connection = connection
+                .lock()
+                .map_err(|_| BoaError::Failed("audit log connection is poisoned".to_string()))?;
+
+            f(&connection).map_err(|e| BoaError::Failed(format!("audit log query failed: {e}")))
+        })
+        .await
+        .map_err(|e| BoaError::Failed(format!("audit log task failed: {e}")))?
+    }
+
+    pub async fn record_start(&self, record: ExecRecord) -> Result<(), BoaError> {
+        self.with_connection(move |connection| {
+            connection.execute(
+                "INSERT INTO execs (exec_id, connection_id, remote_addr, identity, container_id,
+                                    image, kind, path, code_sha256, started_at, outcome)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+                params![
+                    record.exec_id,
+                    record.connection_id,
+                    record.remote_addr,
+                    record.identity,
+                    record.container_id,
+                    record.image,
+                    record.kind,
+                    record.path,
+                    record.code_sha256,
+                    record.started_at,
+                    record.outcome.as_str(),
+                ],
+            )
+        })
+        .await
+        .map(|_| ())
+    }
+
+    pub async fn record_finish(&self, exec_id: String, finish: ExecFinish) -> Result<(), BoaError> {
+        self.with_connection(move |connection| {
+            connection.execute(
+                "UPDATE execs
+                 SET finished_at = ?2, outcome = ?3, exit_code = ?4, kill_reason = ?5, error = ?6,
+                     stdout_bytes = ?7, stderr_bytes = ?8
+                 WHERE exec_id = ?1",
+                params![
+                    exec_id,
+                    now_millis(),
+                    finish.outcome.as_str(),
+                    finish.exit_code,
+                    finish.kill_reason,
+                    finish.error,
+                    finish.stdout_bytes,
+                    finish.stderr_bytes,
+                ],
+            )
+        })
+        .await
+        .map(|_| ())
+    }
+
+    /// Newest first
+    pub async fn query(&self, query: AuditQuery) -> Result<Vec<ExecRecord>, BoaError> {
+        self.with_connection(move |connection| {
+            let mut statement = connection.prepare(
+                "SELECT exec_id, connection_id, remote_addr, container_id, image, kind, path,
+                        code_sha256, started_at, finished_at, outcome, exit_code, kill_reason,
+                        error, stdout_bytes, stderr_bytes, identity
+                 FROM execs
+                 WHERE (?1 IS NULL OR connection_id = ?1)
+                   AND (?2 IS NULL OR container_id = ?2)
+                   AND (?3 IS NULL OR remote_addr = ?3)
+                   AND (?4 IS NULL OR code_sha256 = ?4)
+                   AND (?5 IS NULL OR outcome = ?5)
+                   AND (?6 IS NULL OR started_at >= ?6)
+                   AND (?7 IS NULL OR started_at < ?7)
+                   AND (?9 IS NULL OR identity = ?9)
+                 ORDER BY started_at DESC
+                 LIMIT ?8",
+            )?;
+
+            let rows = statement.query_map(
+                params![
+                    query.connection_id,
+                    query.container_id,
+                    query.remote_addr,
+                    query.code_sha256,
+                    query.outcome.map(ExecOutcome::as_str),
+                    query.since,
+                    query.until,
+                    query
+                        .limit
+                        .unwrap_or(DEFAULT_QUERY_LIMIT)
+                        .min(MAX_QUERY_LIMIT),
+                    query.identity,
+                ],
+                |row| {
+                    Ok(ExecRecord {
+                        exec_id: row.get(0)?,
+                        connection_id: row.get(1)?,
+                        remote_addr: row.get(2)?,
+                        identity: row.get(16)?,
+                        container_id: row.get(3)?,
+                        image: row.get(4)?,
+                        kind: row.get(5)?,
+                        path: row.get(6)?,
+                        code_sha256: row.get(7)?,
+                        started_at: row.get(8)?,
+                        finished_at: row.get(9)?,
+                        outcome: ExecOutcome::parse(&row.get::<_, String>(10)?),
+                        exit_code: row.get(11)?,
+                        kill_reason: row.get(12)?,
+                        error: row.get(13)?,
+                        stdout_bytes: row.get(14)?,
+                        stderr_bytes: row.get(15)?,
+                    })
+                },
+            )?;
+
+            rows.collect()
+        })
+        .await
+    }
+}
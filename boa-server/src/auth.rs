@@ -0,0 +1,390 @@
+use std::{collections::BTreeMap, fmt, fs, str::FromStr};
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use hmac::{Hmac, Mac};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use subtle::ConstantTimeEq;
+
+use crate::{
+    audit,
+    config::{AuthConfig, JwtConfig},
+};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// How a connection proved who it is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthMethod {
+    /// No authentication is configured
+    Anonymous,
+    ApiKey,
+    Hmac,
+    Jwt,
+}
+
+/// Who is on the other end of a connection
+#[derive(Debug, Clone, Serialize)]
+pub struct Identity {
+    pub name: String,
+    pub method: AuthMethod,
+}
+
+impl Identity {
+    fn anonymous() -> Identity {
+        Identity {
+            name: "anonymous".to_string(),
+            method: AuthMethod::Anonymous,
+        }
+    }
+}
+
+impl fmt::Display for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)
+    }
+}
+
+/// Payload of a token signed with `auth.hmac_secret`
+#[derive(Serialize, Deserialize)]
+struct HmacClaims {
+    sub: String,
+    /// Unix seconds
+    exp: i64,
+}
+
+#[derive(Deserialize)]
+struct JwtClaims {
+    sub: String,
+}
+
+#[derive(Clone)]
+struct JwtVerifier {
+    key: DecodingKey,
+    validation: Validation,
+}
+
+/// Checks the token a client upgrades to a WebSocket with, built from the `auth` config section
+#[derive(Clone)]
+pub struct Authenticator {
+    api_keys: BTreeMap<String, String>,
+    hmac_secret: Option<String>,
+    jwt: Option<JwtVerifier>,
+}
+
+impl Authenticator {
+    pub fn new(config: &AuthConfig) -> Result<Authenticator, String> {
+        Ok(Authenticator {
+            api_keys: config.api_keys.clone(),
+            hmac_secret: config.hmac_secret.clone(),
+            jwt: config.jwt.as_ref().map(JwtVerifier::new).transpose()?,
+        })
+    }
+
+    /// Without any configured method every connection is let in as `anonymous`
+    pub fn is_enabled(&self) -> bool {
+        !self.api_keys.is_empty() || self.hmac_secret.is_some() || self.jwt.is_some()
+    }
+
+    pub fn authenticate(&self, token: Option<&str>) -> Result<Identity, &'static str> {
+        if !self.is_enabled() {
+            return Ok(Identity::anonymous());
+        }
+
+        let token = token
+            .filter(|token| !token.is_empty())
+            .ok_or("missing token")?;
+
+        for (name, key) in &self.api_keys {
+            if bool::from(token.as_bytes().ct_eq(key.as_bytes())) {
+                return Ok(Identity {
+                    name: name.clone(),
+                    method: AuthMethod::ApiKey,
+                });
+            }
+        }
+
+        // `<claims>.<signature>` for hmac tokens, `<header>.<claims>.<signature>` for jwts
+        match token.split('.').count() {
+            2 if let Some(secret) = &self.hmac_secret => Ok(Identity {
+                name: verify_hmac_token(secret, token)?,
+                method: AuthMethod::Hmac,
+            }),
+            3 if let Some(jwt) = &self.jwt => Ok(Identity {
+                name: jwt.verify(token)?,
+                method: AuthMethod::Jwt,
+            }),
+            _ => Err("invalid token"),
+        }
+    }
+}
+
+impl JwtVerifier {
+    fn new(config: &JwtConfig) -> Result<JwtVerifier, String> {
+        let algorithm = Algorithm::from_str(&config.algorithm).map_err(|_| {
+            format!(
+                "auth.jwt.algorithm: unknown algorithm {:?}",
+                config.algorithm
+            )
+        })?;
+
+        let key = match (algorithm, &config.secret, &config.public_key_path) {
+            (Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512, Some(secret), _) => {
+                DecodingKey::from_secret(secret.as_bytes())
+            }
+            (Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512, None, _) => {
+                return Err(format!(
+                    "auth.jwt.secret: required for {}",
+                    config.algorithm
+                ));
+            }
+            (_, _, Some(path)) => {
+                let pem = fs::read(path).map_err(|e| {
+                    format!("failed to read jwt public key {}: {e}", path.display())
+                })?;
+
+                match algorithm {
+                    Algorithm::ES256 | Algorithm::ES384 => DecodingKey::from_ec_pem(&pem),
+                    Algorithm::EdDSA => DecodingKey::from_ed_pem(&pem),
+                    _ => DecodingKey::from_rsa_pem(&pem),
+                }
+                .map_err(|e| format!("invalid jwt public key {}: {e}", path.display()))?
+            }
+            (_, _, None) => {
+                return Err(format!(
+                    "auth.jwt.public_key_path: required for {}",
+                    config.algorithm
+                ));
+            }
+        };
+
+        let mut validation = Validation::new(algorithm);
+        validation.set_required_spec_claims(&["exp", "sub"]);
+        if let Some(issuer) = &config.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        match &config.audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+
+        Ok(JwtVerifier { key, validation })
+    }
+
+    fn verify(&self, token: &str) -> Result<String, &'static str> {
+        jsonwebtoken::decode::<JwtClaims>(token, &self.key, &self.validation)
+            .map(|data| data.claims.sub)
+            .map_err(|e| match e.kind() {
+                jsonwebtoken::errors::ErrorKind::ExpiredSignature => "token expired",
+                _ => "invalid token",
+            })
+    }
+}
+
+fn hmac(secret: &str) -> HmacSha256 {
+    HmacSha256::new_from_slice(secret.as_bytes()).expect("hmac should accept keys of any length")
+}
+
+/// Signs a token for `identity` that expires after `ttl_secs`, see `boa-server token`
+pub fn sign_hmac_token(secret: &str, identity: &str, ttl_secs: u64) -> Result<String, String> {
+    let claims = HmacClaims {
+        sub: identity.to_string(),
+        exp: audit::now_millis() / 1000 + i64::try_from(ttl_secs).unwrap_or(i64::MAX / 2),
+    };
+
+    let claims = URL_SAFE_NO_PAD.encode(
+        serde_json::to_vec(&claims).map_err(|e| format!("failed to serialize token: {e}"))?,
+    );
+
+    let mut mac = hmac(secret);
+    mac.update(claims.as_bytes());
+    let signature = URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes());
+
+    Ok(format!("{claims}.{signature}"))
+}
+
+fn verify_hmac_token(secret: &str, token: &str) -> Result<String, &'static str> {
+    let (claims, signature) = token.split_once('.').ok_or("invalid token")?;
+
+    let signature = URL_SAFE_NO_PAD
+        .decode(signature)
+        .map_err(|_| "invalid token")?;
+
+    let mut mac = hmac(secret);
+    mac.update(claims.as_bytes());
+    mac.verify_slice(&signature).map_err(|_| "invalid token")?;
+
+    let claims = URL_SAFE_NO_PAD
+        .decode(claims)
+        .ok()
+        .and_then(|claims| serde_json::from_slice::<HmacClaims>(&claims).ok())
+        .ok_or("invalid token")?;
+
+    if claims.exp <= audit::now_millis() / 1000 {
+        return Err("token expired");
+    }
+
+    Ok(claims.sub)
+}
+
+#[cfg(test)]
+mod tests {
+    use jsonwebtoken::{EncodingKey, Header};
+    use serde_json::json;
+
+    use super::*;
+
+    const JWT_SECRET: &str = "jwt-secret";
+
+    fn authenticator(configure: impl FnOnce(&mut AuthConfig)) -> Authenticator {
+        let mut config = AuthConfig::default();
+        configure(&mut config);
+        Authenticator::new(&config).expect("test auth config is valid")
+    }
+
+    fn jwt_authenticator() -> Authenticator {
+        authenticator(|config| {
+            config.jwt = Some(JwtConfig {
+                algorithm: "HS256".to_string(),
+                secret: Some(JWT_SECRET.to_string()),
+                public_key_path: None,
+                issuer: None,
+                audience: None,
+            });
+        })
+    }
+
+    fn jwt(secret: &str, sub: &str, exp_in_secs: i64) -> String {
+        let claims = json!({ "sub": sub, "exp": audit::now_millis() / 1000 + exp_in_secs });
+        jsonwebtoken::encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(secret.as_bytes()),
+        )
+        .expect("test claims encode")
+    }
+
+    /// `token` with its claims swapped for `claims`, keeping the signature of the original ones
+    fn replace_claims(token: &str, claims: &str) -> String {
+        let mut parts: Vec<&str> = token.split('.').collect();
+        let claims_index = parts.len() - 2;
+        parts[claims_index] = claims;
+        parts.join(".")
+    }
+
+    fn name(result: Result<Identity, &'static str>) -> Result<String, &'static str> {
+        result.map(|identity| identity.name)
+    }
+
+    #[test]
+    fn disabled_auth_lets_everyone_in_as_anonymous() {
+        let auth = authenticator(|_| {});
+
+        let identity = auth.authenticate(None).expect("auth is disabled");
+        assert_eq!(identity.name, "anonymous");
+        assert_eq!(identity.method, AuthMethod::Anonymous);
+    }
+
+    #[test]
+    fn api_key_matches_its_identity() {
+        let auth = authenticator(|config| {
+            config.api_keys = [("alice", "key-a"), ("bob", "key-b")]
+                .map(|(identity, key)| (identity.to_string(), key.to_string()))
+                .into();
+        });
+
+        let identity = auth.authenticate(Some("key-b")).expect("key is valid");
+        assert_eq!(identity.name, "bob");
+        assert_eq!(identity.method, AuthMethod::ApiKey);
+
+        assert_eq!(name(auth.authenticate(Some("key-c"))), Err("invalid token"));
+        assert_eq!(name(auth.authenticate(Some("key-"))), Err("invalid token"));
+        assert_eq!(name(auth.authenticate(Some(""))), Err("missing token"));
+        assert_eq!(name(auth.authenticate(None)), Err("missing token"));
+    }
+
+    #[test]
+    fn hmac_token_is_verified() {
+        let auth = authenticator(|config| config.hmac_secret = Some("secret".to_string()));
+
+        let token = sign_hmac_token("secret", "alice", 60).expect("token is signed");
+        let identity = auth.authenticate(Some(&token)).expect("token is valid");
+        assert_eq!(identity.name, "alice");
+        assert_eq!(identity.method, AuthMethod::Hmac);
+    }
+
+    #[test]
+    fn expired_hmac_token_is_rejected() {
+        let auth = authenticator(|config| config.hmac_secret = Some("secret".to_string()));
+
+        let token = sign_hmac_token("secret", "alice", 0).expect("token is signed");
+        assert_eq!(name(auth.authenticate(Some(&token))), Err("token expired"));
+    }
+
+    #[test]
+    fn tampered_hmac_token_is_rejected() {
+        let auth = authenticator(|config| config.hmac_secret = Some("secret".to_string()));
+
+        let token = sign_hmac_token("secret", "alice", 60).expect("token is signed");
+        let other = sign_hmac_token("secret", "mallory", 60).expect("token is signed");
+        let (other_claims, _) = other.split_once('.').expect("token has claims");
+
+        let tampered = replace_claims(&token, other_claims);
+        assert_eq!(
+            name(auth.authenticate(Some(&tampered))),
+            Err("invalid token")
+        );
+    }
+
+    #[test]
+    fn hmac_token_of_another_secret_is_rejected() {
+        let auth = authenticator(|config| config.hmac_secret = Some("secret".to_string()));
+
+        let token = sign_hmac_token("other secret", "alice", 60).expect("token is signed");
+        assert_eq!(name(auth.authenticate(Some(&token))), Err("invalid token"));
+    }
+
+    #[test]
+    fn jwt_is_verified() {
+        let auth = jwt_authenticator();
+
+        let identity = auth
+            .authenticate(Some(&jwt(JWT_SECRET, "alice", 60)))
+            .expect("jwt is valid");
+        assert_eq!(identity.name, "alice");
+        assert_eq!(identity.method, AuthMethod::Jwt);
+    }
+
+    #[test]
+    fn expired_jwt_is_rejected() {
+        let auth = jwt_authenticator();
+
+        let token = jwt(JWT_SECRET, "alice", -3600);
+        assert_eq!(name(auth.authenticate(Some(&token))), Err("token expired"));
+    }
+
+    #[test]
+    fn tampered_jwt_is_rejected() {
+        let auth = jwt_authenticator();
+
+        let token = jwt(JWT_SECRET, "alice", 60);
+        let other = jwt(JWT_SECRET, "mallory", 60);
+        let other_claims = other.split('.').nth(1).expect("jwt has claims");
+
+        let tampered = replace_claims(&token, other_claims);
+        assert_eq!(
+            name(auth.authenticate(Some(&tampered))),
+            Err("invalid token")
+        );
+    }
+
+    #[test]
+    fn jwt_of_another_key_is_rejected() {
+        let auth = jwt_authenticator();
+
+        let token = jwt("other secret", "alice", 60);
+        assert_eq!(name(auth.authenticate(Some(&token))), Err("invalid token"));
+    }
+}
@@ -0,0 +1,123 @@
+pub mod docker;
+#[cfg(test)]
+pub mod fake;
+pub mod sandbox;
+#[cfg(feature = "wasi")]
+pub mod wasi;
+pub mod workspace;
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures_util::stream::BoxStream;
+use uuid::Uuid;
+
+use crate::{
+    backend::{docker::DockerBackend, sandbox::SandboxBackend},
+    config::{BackendKind, Config},
+    error::BoaError,
+};
+
+/// What a running exec reports, its output chunks and finally its exit code
+pub enum ExecEvent {
+    Stdout(Bytes),
+    Stderr(Bytes),
+    Exited(i64),
+}
+
+pub type ExecStream = BoxStream<'static, Result<ExecEvent, BoaError>>;
+
+pub type SharedBackend = Arc<dyn ContainerBackend>;
+
+/// Where containers are created and code runs. Containers are addressed by the name they were
+/// created with, which is also their id everywhere else in the server.
+#[async_trait]
+pub trait ContainerBackend: Send + Sync {
+    /// Creates a stopped container named `name` from `image`, with the limits, mounts and
+    /// network of `config`
+    async fn create(&self, name: &str, image: &str, config: &Config) -> Result<(), BoaError>;
+
+    async fn start(&self, container_id: &str) -> Result<(), BoaError>;
+
+    /// Removes the container, stopping anything still running in it
+    async fn remove(&self, container_id: &str) -> Result<(), BoaError>;
+
+    async fn pause(&self, container_id: &str) -> Result<(), BoaError>;
+
+    async fn resume(&self, container_id: &str) -> Result<(), BoaError>;
+
+    /// Stops the container by sending `signal` to it, e.g. `SIGINT`
+    async fn signal(&self, container_id: &str, signal: &str) -> Result<(), BoaError>;
+
+    /// Runs `cmd` in a started container. An exec running longer than `timeout_secs` (unless it
+    /// is 0) is killed and exits with `TIMED_OUT_EXIT_CODE`.
+    async fn exec(
+        &self,
+        container_id: &str,
+        exec_id: Uuid,
+        cmd: Vec<String>,
+        timeout_secs: u64,
+    ) -> Result<ExecStream, BoaError>;
+
+    /// Kills the exec started with `exec_id`, with every process it started
+    async fn kill_exec(&self, container_id: &str, exec_id: Uuid) -> Result<(), BoaError>;
+
+    /// Writes `contents` to `file_name` in the directory `dir` of the container
+    async fn upload(
+        &self,
+        container_id: &str,
+        dir: &str,
+        file_name: &str,
+        contents: Vec<u8>,
+    ) -> Result<(), BoaError>;
+
+    /// Reads the file at the absolute `path` in the container
+    async fn download(&self, container_id: &str, path: &str) -> Result<Vec<u8>, BoaError>;
+
+    /// Total CPU time used by the container since it started, in nanoseconds
+    async fn cpu_usage_ns(&self, container_id: &str) -> Result<u64, BoaError>;
+
+    /// Saves the filesystem of the container as the image `repository:tag`, recording `owner` as
+    /// the identity it belongs to
+    async fn commit(
+        &self,
+        container_id: &str,
+        repository: &str,
+        tag: &str,
+        owner: &str,
+    ) -> Result<(), BoaError>;
+
+    /// Moves the tag of the image `source` to `repository:tag`, replacing the image it was on
+    async fn retag(&self, source: &str, repository: &str, tag: &str) -> Result<(), BoaError>;
+
+    /// Identity a local image was committed by, `None` when it was not committed by the server
+    async fn image_owner(&self, image: &str) -> Result<Option<String>, BoaError>;
+
+    /// Size of a local image in bytes, an error when the image is not present
+    async fn image_size(&self, image: &str) -> Result<u64, BoaError>;
+
+    /// `repository:tag` of every local image of `repository`
+    async fn image_tags(&self, repository: &str) -> Result<Vec<String>, BoaError>;
+
+    async fn remove_image(&self, image: &str) -> Result<(), BoaError>;
+
+    /// Checks that the backend answers
+    async fn ping(&self) -> Result<(), BoaError>;
+}
+
+/// Connects to the backend `config` selects
+pub async fn connect(config: &Config) -> Result<SharedBackend, String> {
+    Ok(match config.backend {
+        BackendKind::Docker => Arc::new(DockerBackend::new(docker::connect(&config.docker).await?)),
+        BackendKind::Sandbox => Arc::new(SandboxBackend::new(config).await?),
+        #[cfg(feature = "wasi")]
+        BackendKind::Wasi => Arc::new(wasi::WasiBackend::new(config).await?),
+        #[cfg(not(feature = "wasi"))]
+        BackendKind::Wasi => {
+            return Err(
+                "the wasi backend needs a boa-server built with `--features wasi`".to_string(),
+            );
+        }
+    })
+}
@@ -0,0 +1,975 @@
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    io::Read,
+    path::Path,
+    sync::Mutex,
+    time::SystemTime,
+};
+
+use async_trait::async_trait;
+use bollard::{
+    API_DEFAULT_VERSION, Docker, body_full,
+    container::LogOutput,
+    exec::{CreateExecOptions, StartExecResults},
+    query_parameters::{
+        CommitContainerOptions, CreateContainerOptions, CreateContainerOptionsBuilder,
+        DownloadFromContainerOptionsBuilder, InspectContainerOptions, ListImagesOptions,
+        RemoveContainerOptions, RemoveImageOptions, StartContainerOptions, StatsOptions,
+        StopContainerOptionsBuilder, TagImageOptionsBuilder, UploadToContainerOptionsBuilder,
+    },
+    secret::{
+        ContainerConfig, ContainerCreateBody, ContainerStateStatusEnum, HostConfig, SystemVersion,
+    },
+};
+use futures_util::{StreamExt, TryStreamExt, stream};
+use tokio::{fs, io::AsyncWriteExt};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::{
+    backend::{ContainerBackend, ExecEvent, ExecStream},
+    config::{Config, DockerConfig, PODMAN_HOST},
+    container::PIP_CACHE_DIR,
+    error::BoaError,
+    metrics::docker_error,
+};
+
+/// Seconds before a request to the docker daemon times out
+const DOCKER_TIMEOUT_SECS: u64 = 120;
+
+/// Oldest Docker Engine API the server works with, spoken by Docker 20.10 and Podman 3
+const MIN_API_VERSION: (usize, usize) = (1, 41);
+
+/// Label of committed images with the identity that committed them
+const OWNER_LABEL: &str = "boa.snapshot.owner";
+
+/// Label of containers, and of the snapshots committed from them, with the runtime image whose
+/// security profile they run with
+const RUNTIME_LABEL: &str = "boa.snapshot.runtime";
+
+/// Archive in the root of a snapshot with the files of the tmpfs mounts of the committed
+/// container, which the mounts of a container of the snapshot would hide
+const TMPFS_ARCHIVE: &str = "/.boa-tmpfs.tar";
+
+/// Connects to the configured daemon and checks that it speaks an API version the server works
+/// with. A daemon that does not answer yet is left to the readiness check.
+pub async fn connect(config: &DockerConfig) -> Result<Docker, String> {
+    let docker = client(config)?;
+
+    let version = match docker.version().await {
+        Ok(version) => version,
+        Err(e) => {
+            warn!("could not check the docker api version, the daemon did not answer: {e}");
+            return Ok(docker);
+        }
+    };
+
+    check_version(&version)?;
+
+    // requests are made with the daemon's version when it is older than bollard's
+    docker
+        .negotiate_version()
+        .await
+        .map_err(|e| format!("failed to negotiate the docker api version: {e}"))
+}
+
+fn client(config: &DockerConfig) -> Result<Docker, String> {
+    let host = match config.host.as_deref() {
+        Some(PODMAN_HOST) => Some(podman_socket()?),
+        Some(path) if path.starts_with('/') => Some(format!("unix://{path}")),
+        host => host.map(str::to_string),
+    };
+
+    let docker = match (host.as_deref(), &config.tls) {
+        (None, _) => Docker::connect_with_local_defaults(),
+        (Some(host), _) if host.starts_with("unix://") => {
+            Docker::connect_with_unix(host, DOCKER_TIMEOUT_SECS, API_DEFAULT_VERSION)
+        }
+        (Some(host), Some(tls)) => Docker::connect_with_ssl(
+            host,
+            &tls.key_path,
+            &tls.cert_path,
+            &tls.ca_path,
+            DOCKER_TIMEOUT_SECS,
+            API_DEFAULT_VERSION,
+        ),
+        (Some(host), None) => {
+            Docker::connect_with_http(host, DOCKER_TIMEOUT_SECS, API_DEFAULT_VERSION)
+        }
+    };
+
+    docker.map_err(|e| format!("failed to connect to docker daemon: {e}"))
+}
+
+/// Socket of the rootless Podman service of the user running the server
+fn podman_socket() -> Result<String, String> {
+    let runtime_dir = env::var("XDG_RUNTIME_DIR").map_err(|_| {
+        "docker.host is podman, but XDG_RUNTIME_DIR is not set, set docker.host to the path of \
+         the podman socket instead"
+            .to_string()
+    })?;
+    let socket = Path::new(&runtime_dir).join("podman/podman.sock");
+
+    if !socket.exists() {
+        return Err(format!(
+            "no podman socket at {}, start it with `systemctl --user enable --now podman.socket`",
+            socket.display()
+        ));
+    }
+
+    Ok(format!("unix://{}", socket.display()))
+}
+
+/// Rejects daemons that only speak API versions older than `MIN_API_VERSION`, or only newer
+/// ones than bollard does
+fn check_version(version: &SystemVersion) -> Result<(), String> {
+    let engine = match &version.components {
+        Some(components)
+            if components
+                .iter()
+                .any(|component| component.name.starts_with("Podman")) =>
+        {
+            "podman"
+        }
+        _ => "docker",
+    };
+    let engine_version = version.version.as_deref().unwrap_or("unknown");
+
+    let api_version = version
+        .api_version
+        .as_deref()
+        .ok_or_else(|| format!("{engine} {engine_version} did not report its api version"))?;
+    let parsed = parse_api_version(api_version).ok_or_else(|| {
+        format!("{engine} {engine_version} reported an invalid api version {api_version:?}")
+    })?;
+
+    if parsed < MIN_API_VERSION {
+        return Err(format!(
+            "{engine} {engine_version} speaks docker api {api_version}, boa-server needs at least \
+             {}.{} (Docker 20.10 or Podman 3), upgrade the daemon",
+            MIN_API_VERSION.0, MIN_API_VERSION.1
+        ));
+    }
+
+    let client_version = (
+        API_DEFAULT_VERSION.major_version,
+        API_DEFAULT_VERSION.minor_version,
+    );
+    if let Some(min_api_version) = version.min_api_version.as_deref()
+        && parse_api_version(min_api_version).is_some_and(|min| min > client_version)
+    {
+        return Err(format!(
+            "{engine} {engine_version} needs at least docker api {min_api_version}, boa-server \
+             speaks up to {}.{}",
+            client_version.0, client_version.1
+        ));
+    }
+
+    info!(
+        engine,
+        version = engine_version,
+        api_version,
+        "connected to container engine"
+    );
+
+    Ok(())
+}
+
+/// `major.minor`, as in `1.41`
+fn parse_api_version(version: &str) -> Option<(usize, usize)> {
+    let (major, minor) = version.split_once('.')?;
+
+    Some((major.parse().ok()?, minor.parse().ok()?))
+}
+
+/// File inside the container that an exec writes the pid of its process group to
+fn exec_pid_file(exec_id: Uuid) -> String {
+    format!("/tmp/boa-exec-{exec_id}.pid")
+}
+
+/// Runs containers as docker containers, and execs with `docker exec`
+pub struct DockerBackend {
+    docker: Docker,
+    /// Containers of snapshots whose tmpfs files are restored once they start
+    restores: Mutex<HashSet<String>>,
+}
+
+impl DockerBackend {
+    pub fn new(docker: Docker) -> DockerBackend {
+        DockerBackend {
+            docker,
+            restores: Mutex::default(),
+        }
+    }
+
+    /// Runtime image containers of `image` take their security profile from, the one a snapshot
+    /// was committed from. `None` for snapshots that do not record it.
+    async fn runtime_of(&self, image: &str, config: &Config) -> Result<Option<String>, BoaError> {
+        if config.runtime_images().contains(&image) {
+            return Ok(Some(image.to_string()));
+        }
+
+        let inspect = self
+            .docker
+            .inspect_image(image)
+            .await
+            .map_err(docker_error("inspect_image", "failed to inspect image"))?;
+
+        Ok(inspect
+            .config
+            .and_then(|config| config.labels)
+            .and_then(|mut labels| labels.remove(RUNTIME_LABEL)))
+    }
+
+    /// Extracts the files a snapshot kept of its tmpfs mounts into the mounts of `container_id`
+    async fn restore_tmpfs(&self, container_id: &str) -> Result<(), BoaError> {
+        let cmd = [
+            "sh",
+            "-c",
+            r#"[ ! -f "$0" ] || tar -x --no-overwrite-dir -C / -f "$0""#,
+            TMPFS_ARCHIVE,
+        ];
+
+        self.exec_with_input(container_id, cmd.map(str::to_string).to_vec(), Vec::new())
+            .await
+            .map(|_| ())
+            .map_err(|e| BoaError::Failed(format!("failed to restore snapshot files: {e}")))
+    }
+
+    async fn is_running(&self, container_id: &str) -> Result<bool, BoaError> {
+        let inspect = self
+            .docker
+            .inspect_container(container_id, None::<InspectContainerOptions>)
+            .await
+            .map_err(docker_error(
+                "inspect_container",
+                "failed to inspect container",
+            ))?;
+
+        Ok(inspect
+            .state
+            .as_ref()
+            .and_then(|s| s.status.as_ref())
+            .is_some_and(|status| *status == ContainerStateStatusEnum::RUNNING))
+    }
+
+    /// Runs `cmd` in a running container with `input` on its stdin and returns its stdout.
+    /// Files on the tmpfs mounts of a container are only reachable this way, the archive API
+    /// sees the filesystem underneath them.
+    async fn exec_with_input(
+        &self,
+        container_id: &str,
+        cmd: Vec<String>,
+        input: Vec<u8>,
+    ) -> Result<Vec<u8>, BoaError> {
+        let exec = self
+            .docker
+            .create_exec(
+                container_id,
+                CreateExecOptions {
+                    attach_stdout: Some(true),
+                    attach_stderr: Some(true),
+                    attach_stdin: Some(true),
+                    tty: Some(false),
+                    cmd: Some(cmd),
+                    ..Default::default()
+                },
+            )
+            .await
+            .map_err(docker_error("create_exec", "failed to create exec"))?;
+
+        let mut stdout = Vec::new();
+        let mut stderr = Vec::new();
+
+        if let StartExecResults::Attached {
+            mut output,
+            input: mut stdin,
+        } = self
+            .docker
+            .start_exec(&exec.id, None)
+            .await
+            .map_err(docker_error("start_exec", "failed to start exec"))?
+        {
+            // the output is read while writing, so a command that exits early cannot block it
+            let write = async {
+                let result = stdin.write_all(&input).await;
+                stdin.shutdown().await.ok();
+                result
+            };
+            let read = async {
+                while let Some(msg) = output.next().await {
+                    match msg {
+                        Ok(LogOutput::StdOut { message }) => stdout.extend_from_slice(&message),
+                        Ok(LogOutput::StdErr { message }) => stderr.extend_from_slice(&message),
+                        Ok(_) => {}
+                        Err(e) => {
+                            return Err(BoaError::Failed(format!(
+                                "failed to read exec output: {e}"
+                            )));
+                        }
+                    }
+                }
+                Ok(())
+            };
+
+            let (written, read) = tokio::join!(write, read);
+            read?;
+            written.map_err(|e| BoaError::Failed(format!("failed to write exec input: {e}")))?;
+        }
+
+        let exit_code = self
+            .docker
+            .inspect_exec(&exec.id)
+            .await
+            .map_err(docker_error("inspect_exec", "failed to inspect exec"))?
+            .exit_code;
+
+        if exit_code != Some(0) {
+            return Err(BoaError::Failed(
+                String::from_utf8_lossy(&stderr).trim().to_string(),
+            ));
+        }
+
+        Ok(stdout)
+    }
+
+    async fn commit_container(
+        &self,
+        container_id: &str,
+        repository: &str,
+        tag: &str,
+        owner: &str,
+        runtime: Option<&str>,
+    ) -> Result<(), BoaError> {
+        let mut labels = HashMap::from([(OWNER_LABEL.to_string(), owner.to_string())]);
+        if let Some(runtime) = runtime {
+            labels.insert(RUNTIME_LABEL.to_string(), runtime.to_string());
+        }
+
+        self.docker
+            .commit_container(
+                CommitContainerOptions {
+                    container: Some(container_id.to_string()),
+                    repo: Some(repository.to_string()),
+                    tag: Some(tag.to_string()),
+                    ..Default::default()
+                },
+                ContainerConfig {
+                    labels: Some(labels),
+                    ..Default::default()
+                },
+            )
+            .await
+            .map(|_| ())
+            .map_err(docker_error(
+                "commit_container",
+                "failed to commit container",
+            ))
+    }
+}
+
+#[async_trait]
+impl ContainerBackend for DockerBackend {
+    async fn create(&self, name: &str, image: &str, config: &Config) -> Result<(), BoaError> {
+        let pip_cache_volume = config.containers.pip_cache_volume();
+        let runtime = self.runtime_of(image, config).await?;
+        let security = config.security(runtime.as_deref().unwrap_or(image));
+
+        let mut env = Vec::new();
+        if pip_cache_volume.is_some() {
+            env.push(format!("PIP_CACHE_DIR={PIP_CACHE_DIR}"));
+        }
+        if security.read_only || !security.user.is_empty() {
+            // the image's home may be read-only or someone else's, pip installs to ~/.local
+            env.push("HOME=/tmp".to_string());
+        }
+
+        let mut security_opt = Vec::new();
+        if security.no_new_privileges {
+            security_opt.push("no-new-privileges:true".to_string());
+        }
+        if let Some(seccomp) = &security.seccomp {
+            let profile = fs::read_to_string(seccomp).await.map_err(|e| {
+                BoaError::Failed(format!("failed to read {}: {e}", seccomp.display()))
+            })?;
+            security_opt.push(format!("seccomp={profile}"));
+        }
+
+        let tmpfs = (security.tmpfs_mb > 0).then(|| {
+            let options = format!("rw,exec,nosuid,nodev,size={}m,mode=1777", security.tmpfs_mb);
+            HashMap::from([
+                (config.containers.working_dir.clone(), options.clone()),
+                ("/tmp".to_string(), options),
+            ])
+        });
+
+        let container_options = CreateContainerOptionsBuilder::new().name(name).build();
+
+        let container_create = ContainerCreateBody {
+            image: Some(image.to_string()),
+            tty: Some(true),
+            open_stdin: Some(true),
+
+            working_dir: Some(config.containers.working_dir.clone()),
+
+            user: (!security.user.is_empty()).then(|| security.user.clone()),
+            labels: runtime
+                .clone()
+                .map(|runtime| HashMap::from([(RUNTIME_LABEL.to_string(), runtime)])),
+
+            env: (!env.is_empty()).then_some(env),
+            host_config: Some(HostConfig {
+                binds: pip_cache_volume.map(|volume| vec![format!("{volume}:{PIP_CACHE_DIR}")]),
+                memory: i64::try_from(config.limits.memory_mb * 1024 * 1024).ok(),
+                nano_cpus: Some(config.limits.nano_cpus()),
+                pids_limit: Some(config.limits.pids),
+                network_mode: Some(config.network.mode.clone()),
+                cap_drop: Some(vec!["ALL".to_string()]),
+                cap_add: (!security.cap_add.is_empty()).then(|| security.cap_add.clone()),
+                readonly_rootfs: Some(security.read_only),
+                security_opt: (!security_opt.is_empty()).then_some(security_opt),
+                tmpfs,
+                runtime: security.runtime.clone(),
+                ..Default::default()
+            }),
+
+            cmd: Some(vec![
+                "tail".to_string(),
+                "-f".to_string(),
+                "/dev/null".to_string(),
+            ]),
+
+            attach_stdin: Some(true),
+            attach_stdout: Some(true),
+            attach_stderr: Some(true),
+
+            ..Default::default()
+        };
+
+        self.docker
+            .create_container(Some(container_options), container_create)
+            .await
+            .map_err(docker_error(
+                "create_container",
+                "failed to create new docker container",
+            ))?;
+
+        if security.tmpfs_mb > 0 && runtime.as_deref() != Some(image) {
+            self.restores
+                .lock()
+                .expect("restores lock poisoned")
+                .insert(name.to_string());
+        }
+
+        Ok(())
+    }
+
+    async fn start(&self, container_id: &str) -> Result<(), BoaError> {
+        self.docker
+            .start_container(container_id, Some(StartContainerOptions::default()))
+            .await
+            .map_err(docker_error("start_container", "failed to start container"))?;
+
+        let restore = self
+            .restores
+            .lock()
+            .expect("restores lock poisoned")
+            .remove(container_id);
+        if restore {
+            self.restore_tmpfs(container_id).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn remove(&self, container_id: &str) -> Result<(), BoaError> {
+        self.restores
+            .lock()
+            .expect("restores lock poisoned")
+            .remove(container_id);
+
+        self.docker
+            .remove_container(
+                container_id,
+                Some(RemoveContainerOptions {
+                    force: true,
+                    ..Default::default()
+                }),
+            )
+            .await
+            .map_err(docker_error(
+                "remove_container",
+                "failed to remove container",
+            ))
+    }
+
+    async fn pause(&self, container_id: &str) -> Result<(), BoaError> {
+        self.docker
+            .pause_container(container_id)
+            .await
+            .map_err(docker_error("pause_container", "failed to pause container"))
+    }
+
+    async fn resume(&self, container_id: &str) -> Result<(), BoaError> {
+        self.docker
+            .unpause_container(container_id)
+            .await
+            .map_err(docker_error(
+                "unpause_container",
+                "failed to resume container",
+            ))
+    }
+
+    async fn signal(&self, container_id: &str, signal: &str) -> Result<(), BoaError> {
+        self.docker
+            .stop_container(
+                container_id,
+                Some(StopContainerOptionsBuilder::new().signal(signal).build()),
+            )
+            .await
+            .map_err(docker_error("stop_container", "failed to stop container"))
+    }
+
+    async fn exec(
+        &self,
+        container_id: &str,
+        exec_id: Uuid,
+        cmd: Vec<String>,
+        timeout_secs: u64,
+    ) -> Result<ExecStream, BoaError> {
+        if !self.is_running(container_id).await? {
+            return Err(BoaError::NotStarted("container is not started".to_string()));
+        }
+
+        // coreutils `timeout` sends SIGTERM when time is up, and SIGKILL 5 seconds later,
+        // a timeout of 0 disables it. `timeout` also leads its own process group, so the
+        // pid written to the pid file can be used to kill the whole exec
+        let cmd = [
+            "sh",
+            "-c",
+            &format!(
+                "echo $$ > {} && exec timeout -k 5 {timeout_secs} \"$@\"",
+                exec_pid_file(exec_id)
+            ),
+            "sh",
+        ]
+        .into_iter()
+        .map(str::to_string)
+        .chain(cmd)
+        .collect();
+
+        let exec = self
+            .docker
+            .create_exec(
+                container_id,
+                CreateExecOptions {
+                    attach_stdout: Some(true),
+                    attach_stderr: Some(true),
+                    attach_stdin: Some(false),
+                    tty: Some(false),
+                    cmd: Some(cmd),
+                    ..Default::default()
+                },
+            )
+            .await
+            .map_err(docker_error("create_exec", "failed to create exec"))?;
+
+        let output = match self
+            .docker
+            .start_exec(&exec.id, None)
+            .await
+            .map_err(docker_error("start_exec", "failed to start exec"))?
+        {
+            StartExecResults::Attached { output, .. } => output
+                .filter_map(async |msg| match msg {
+                    Ok(LogOutput::StdOut { message }) => Some(Ok(ExecEvent::Stdout(message))),
+                    Ok(LogOutput::StdErr { message }) => Some(Ok(ExecEvent::Stderr(message))),
+                    _ => None,
+                })
+                .boxed(),
+            StartExecResults::Detached => {
+                warn!("exec is running in detached mode");
+                stream::empty().boxed()
+            }
+        };
+
+        // the exit code is only known once the output has ended
+        let docker = self.docker.clone();
+        let exited = stream::once(async move {
+            docker
+                .inspect_exec(&exec.id)
+                .await
+                .map(|inspect| ExecEvent::Exited(inspect.exit_code.unwrap_or(-1)))
+                .map_err(docker_error("inspect_exec", "failed to inspect exec"))
+        });
+
+        Ok(output.chain(exited).boxed())
+    }
+
+    async fn kill_exec(&self, container_id: &str, exec_id: Uuid) -> Result<(), BoaError> {
+        let exec = self
+            .docker
+            .create_exec(
+                container_id,
+                CreateExecOptions {
+                    attach_stdout: Some(true),
+                    attach_stderr: Some(true),
+                    cmd: Some(vec![
+                        "sh".to_string(),
+                        "-c".to_string(),
+                        format!(
+                            "kill -KILL -- -\"$(cat {0})\" && rm -f {0}",
+                            exec_pid_file(exec_id)
+                        ),
+                    ]),
+                    ..Default::default()
+                },
+            )
+            .await
+            .map_err(docker_error("create_exec", "failed to create kill exec"))?;
+
+        if let StartExecResults::Attached { mut output, .. } = self
+            .docker
+            .start_exec(&exec.id, None)
+            .await
+            .map_err(docker_error("start_exec", "failed to start kill exec"))?
+        {
+            while output.next().await.is_some() {}
+        }
+
+        let exit_code = self
+            .docker
+            .inspect_exec(&exec.id)
+            .await
+            .map_err(docker_error("inspect_exec", "failed to inspect kill exec"))?
+            .exit_code;
+
+        if exit_code != Some(0) {
+            return Err(BoaError::NotFound("exec is not running".to_string()));
+        }
+
+        Ok(())
+    }
+
+    async fn upload(
+        &self,
+        container_id: &str,
+        dir: &str,
+        file_name: &str,
+        contents: Vec<u8>,
+    ) -> Result<(), BoaError> {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map_or(0, |since| since.as_secs()),
+        );
+
+        let mut tar_data = Vec::new();
+        {
+            let mut builder = tar::Builder::new(&mut tar_data);
+            builder
+                .append_data(&mut header, file_name, contents.as_slice())
+                .map_err(|e| BoaError::Failed(format!("tar append failed: {e}")))?;
+            builder
+                .finish()
+                .map_err(|e| BoaError::Failed(format!("tar finish failed: {e}")))?;
+        }
+
+        if self.is_running(container_id).await? {
+            return self
+                .exec_with_input(
+                    container_id,
+                    vec![
+                        "tar".to_string(),
+                        "-x".to_string(),
+                        "-C".to_string(),
+                        dir.to_string(),
+                        "-f".to_string(),
+                        "-".to_string(),
+                    ],
+                    tar_data,
+                )
+                .await
+                .map(|_| ())
+                .map_err(|e| BoaError::Failed(format!("docker upload failed: {e}")));
+        }
+
+        self.docker
+            .upload_to_container(
+                container_id,
+                Some(UploadToContainerOptionsBuilder::new().path(dir).build()),
+                body_full(tar_data.into()),
+            )
+            .await
+            .map_err(docker_error("upload_to_container", "docker upload failed"))
+    }
+
+    async fn download(&self, container_id: &str, path: &str) -> Result<Vec<u8>, BoaError> {
+        if self.is_running(container_id).await? {
+            return self
+                .exec_with_input(
+                    container_id,
+                    vec!["cat".to_string(), "--".to_string(), path.to_string()],
+                    Vec::new(),
+                )
+                .await
+                .map_err(|e| match e {
+                    // cat failed, most likely there is no such file
+                    BoaError::Failed(message) => {
+                        BoaError::NotFound(format!("failed to read {path}: {message}"))
+                    }
+                    other => other,
+                });
+        }
+
+        let tar_data = self
+            .docker
+            .download_from_container(
+                container_id,
+                Some(
+                    DownloadFromContainerOptionsBuilder::new()
+                        .path(path)
+                        .build(),
+                ),
+            )
+            .try_fold(Vec::new(), async |mut tar_data, chunk| {
+                tar_data.extend_from_slice(&chunk);
+                Ok(tar_data)
+            })
+            .await
+            .map_err(docker_error(
+                "download_from_container",
+                "docker download failed",
+            ))?;
+
+        let mut archive = tar::Archive::new(tar_data.as_slice());
+        let mut entry = archive
+            .entries()
+            .and_then(|mut entries| entries.next().transpose())
+            .map_err(|e| BoaError::Failed(format!("invalid archive from docker: {e}")))?
+            .ok_or_else(|| BoaError::NotFound(format!("{path} does not exist")))?;
+
+        if !entry.header().entry_type().is_file() {
+            return Err(BoaError::NotFound(format!("{path} is not a file")));
+        }
+
+        let mut contents = Vec::new();
+        entry
+            .read_to_end(&mut contents)
+            .map_err(|e| BoaError::Failed(format!("invalid archive from docker: {e}")))?;
+
+        Ok(contents)
+    }
+
+    async fn cpu_usage_ns(&self, container_id: &str) -> Result<u64, BoaError> {
+        let stats = self
+            .docker
+            .stats(
+                container_id,
+                Some(StatsOptions {
+                    stream: false,
+                    one_shot: true,
+                }),
+            )
+            .next()
+            .await
+            .ok_or_else(|| BoaError::Failed("docker returned no stats".to_string()))?
+            .map_err(docker_error("stats", "failed to get container stats"))?;
+
+        stats
+            .cpu_stats
+            .and_then(|cpu_stats| cpu_stats.cpu_usage)
+            .and_then(|cpu_usage| cpu_usage.total_usage)
+            .ok_or_else(|| BoaError::Failed("container stats have no cpu usage".to_string()))
+    }
+
+    async fn commit(
+        &self,
+        container_id: &str,
+        repository: &str,
+        tag: &str,
+        owner: &str,
+    ) -> Result<(), BoaError> {
+        let inspect = self
+            .docker
+            .inspect_container(container_id, None::<InspectContainerOptions>)
+            .await
+            .map_err(docker_error(
+                "inspect_container",
+                "failed to inspect container",
+            ))?;
+
+        let tmpfs_dirs: Vec<String> = inspect
+            .host_config
+            .and_then(|host_config| host_config.tmpfs)
+            .map(|tmpfs| tmpfs.into_keys().collect())
+            .unwrap_or_default();
+        let runtime = inspect
+            .config
+            .and_then(|config| config.labels)
+            .and_then(|mut labels| labels.remove(RUNTIME_LABEL));
+
+        if tmpfs_dirs.is_empty() {
+            return self
+                .commit_container(container_id, repository, tag, owner, runtime.as_deref())
+                .await;
+        }
+
+        // a commit leaves out tmpfs mounts, so their files are archived into the committed image
+        // through a container of it, and extracted again when a container of it starts
+        let mut cmd = ["tar", "-c", "-C", "/", "-f", "-"]
+            .map(str::to_string)
+            .to_vec();
+        cmd.extend(
+            tmpfs_dirs
+                .iter()
+                .map(|dir| dir.trim_start_matches('/').to_string()),
+        );
+        let files = self
+            .exec_with_input(container_id, cmd, Vec::new())
+            .await
+            .map_err(|e| BoaError::Failed(format!("failed to save snapshot files: {e}")))?;
+
+        self.commit_container(container_id, repository, tag, owner, runtime.as_deref())
+            .await?;
+
+        let mut header = tar::Header::new_gnu();
+        header.set_size(files.len() as u64);
+        header.set_mode(0o644);
+        let mut archive = Vec::new();
+        {
+            let mut builder = tar::Builder::new(&mut archive);
+            builder
+                .append_data(
+                    &mut header,
+                    TMPFS_ARCHIVE.trim_start_matches('/'),
+                    files.as_slice(),
+                )
+                .map_err(|e| BoaError::Failed(format!("tar append failed: {e}")))?;
+            builder
+                .finish()
+                .map_err(|e| BoaError::Failed(format!("tar finish failed: {e}")))?;
+        }
+
+        let copy = self
+            .docker
+            .create_container(
+                None::<CreateContainerOptions>,
+                ContainerCreateBody {
+                    image: Some(format!("{repository}:{tag}")),
+                    ..Default::default()
+                },
+            )
+            .await
+            .map_err(docker_error(
+                "create_container",
+                "failed to create snapshot container",
+            ))?
+            .id;
+
+        let result = async {
+            self.docker
+                .upload_to_container(
+                    &copy,
+                    Some(UploadToContainerOptionsBuilder::new().path("/").build()),
+                    body_full(archive.into()),
+                )
+                .await
+                .map_err(docker_error(
+                    "upload_to_container",
+                    "failed to copy snapshot files",
+                ))?;
+
+            self.commit_container(&copy, repository, tag, owner, runtime.as_deref())
+                .await
+        }
+        .await;
+
+        self.remove(&copy).await.ok();
+
+        result
+    }
+
+    async fn retag(&self, source: &str, repository: &str, tag: &str) -> Result<(), BoaError> {
+        self.docker
+            .tag_image(
+                source,
+                Some(
+                    TagImageOptionsBuilder::new()
+                        .repo(repository)
+                        .tag(tag)
+                        .build(),
+                ),
+            )
+            .await
+            .map_err(docker_error("tag_image", "failed to tag image"))?;
+
+        // the image keeps its new tag, only the old one is removed
+        self.remove_image(source).await
+    }
+
+    async fn image_owner(&self, image: &str) -> Result<Option<String>, BoaError> {
+        let inspect = self
+            .docker
+            .inspect_image(image)
+            .await
+            .map_err(docker_error("inspect_image", "failed to inspect image"))?;
+
+        Ok(inspect
+            .config
+            .and_then(|config| config.labels)
+            .and_then(|mut labels| labels.remove(OWNER_LABEL)))
+    }
+
+    async fn image_size(&self, image: &str) -> Result<u64, BoaError> {
+        let inspect = self
+            .docker
+            .inspect_image(image)
+            .await
+            .map_err(docker_error("inspect_image", "failed to inspect image"))?;
+
+        Ok(inspect
+            .size
+            .and_then(|size| u64::try_from(size).ok())
+            .unwrap_or_default())
+    }
+
+    async fn image_tags(&self, repository: &str) -> Result<Vec<String>, BoaError> {
+        let images = self
+            .docker
+            .list_images(Some(ListImagesOptions {
+                filters: Some(HashMap::from([(
+                    "reference".to_string(),
+                    vec![repository.to_string()],
+                )])),
+                ..Default::default()
+            }))
+            .await
+            .map_err(docker_error("list_images", "failed to list images"))?;
+
+        Ok(images
+            .into_iter()
+            .flat_map(|image| image.repo_tags)
+            .collect())
+    }
+
+    async fn remove_image(&self, image: &str) -> Result<(), BoaError> {
+        self.docker
+            .remove_image(image, None::<RemoveImageOptions>, None)
+            .await
+            .map(|_| ())
+            .map_err(docker_error("remove_image", "failed to remove image"))
+    }
+
+    async fn ping(&self) -> Result<(), BoaError> {
+        self.docker
+            .ping()
+            .await
+            .map(|_| ())
+            .map_err(|e| BoaError::BackendUnavailable(format!("failed to ping docker: {e}")))
+    }
+}
@@ -0,0 +1,469 @@
+use std::{
+    collections::HashMap,
+    future,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures_util::stream;
+use tokio::{
+    sync::{Notify, mpsc},
+    time,
+};
+use uuid::Uuid;
+
+use crate::{
+    backend::{ContainerBackend, ExecEvent, ExecStream},
+    config::Config,
+    container::TIMED_OUT_EXIT_CODE,
+    error::BoaError,
+};
+
+/// Exit code of an exec killed with `kill_exec`, like a shell reports a `SIGKILL`
+pub const KILLED_EXIT_CODE: i64 = 137;
+
+#[derive(Clone)]
+enum Step {
+    Stdout(String),
+    Stderr(String),
+    Sleep(Duration),
+    Fail(String),
+}
+
+/// What an exec of one file does: the output it sends, the delays in between and the code it
+/// exits with. Execs without a script exit with 0 and no output.
+#[derive(Clone, Default)]
+pub struct Script {
+    steps: Vec<Step>,
+    exit_code: i64,
+}
+
+impl Script {
+    #[must_use]
+    pub fn stdout(mut self, text: &str) -> Script {
+        self.steps.push(Step::Stdout(text.to_string()));
+        self
+    }
+
+    #[must_use]
+    pub fn stderr(mut self, text: &str) -> Script {
+        self.steps.push(Step::Stderr(text.to_string()));
+        self
+    }
+
+    #[must_use]
+    pub fn sleep(mut self, duration: Duration) -> Script {
+        self.steps.push(Step::Sleep(duration));
+        self
+    }
+
+    /// The exec stream fails with `message` here instead of exiting
+    #[must_use]
+    pub fn fail(mut self, message: &str) -> Script {
+        self.steps.push(Step::Fail(message.to_string()));
+        self
+    }
+
+    #[must_use]
+    pub fn exit_code(mut self, exit_code: i64) -> Script {
+        self.exit_code = exit_code;
+        self
+    }
+}
+
+/// A container of the fake backend, as the tests inspect it
+#[derive(Debug, Clone, Default)]
+pub struct FakeContainer {
+    pub image: String,
+    pub running: bool,
+    pub paused: bool,
+    /// Uploaded files, by their absolute path
+    pub files: HashMap<String, Vec<u8>>,
+    /// Signals the container was stopped with, in order
+    pub signals: Vec<String>,
+}
+
+/// An image committed to the fake backend
+#[derive(Debug, Clone, Default)]
+pub struct FakeImage {
+    pub size: u64,
+    /// Identity it was committed by, `None` for images put there by an admin
+    pub owner: Option<String>,
+}
+
+struct FakeExec {
+    container_id: String,
+    kill: Arc<Notify>,
+}
+
+#[derive(Default)]
+struct FakeState {
+    containers: HashMap<String, FakeContainer>,
+    images: HashMap<String, FakeImage>,
+    /// Scripts by the path of the file that is executed
+    scripts: HashMap<String, Script>,
+    /// Errors returned by operations, by the name of the trait method
+    failures: HashMap<&'static str, String>,
+    /// How long creating a container takes
+    create_delay: Duration,
+    execs: HashMap<Uuid, FakeExec>,
+}
+
+/// Keeps containers in memory and runs execs from scripts, so the server can be tested
+/// without a docker daemon
+#[derive(Clone, Default)]
+pub struct FakeBackend {
+    state: Arc<Mutex<FakeState>>,
+}
+
+impl FakeBackend {
+    /// Execs of the file at `path` run `script` from now on
+    pub fn script(&self, path: &str, script: Script) {
+        self.lock().scripts.insert(path.to_string(), script);
+    }
+
+    /// Makes `operation`, e.g. `"start"`, fail with `message` from now on
+    pub fn fail(&self, operation: &'static str, message: &str) {
+        self.lock().failures.insert(operation, message.to_string());
+    }
+
+    /// Makes creating a container take `delay` from now on
+    pub fn delay_create(&self, delay: Duration) {
+        self.lock().create_delay = delay;
+    }
+
+    pub fn container(&self, container_id: &str) -> Option<FakeContainer> {
+        self.lock().containers.get(container_id).cloned()
+    }
+
+    pub fn container_count(&self) -> usize {
+        self.lock().containers.len()
+    }
+
+    /// Committed images, by `repository:tag`
+    pub fn images(&self) -> HashMap<String, FakeImage> {
+        self.lock().images.clone()
+    }
+
+    /// Adds an image as if it was built or published outside of the server
+    pub fn add_image(&self, image: &str, size: u64) {
+        self.lock()
+            .images
+            .insert(image.to_string(), FakeImage { size, owner: None });
+    }
+
+    fn lock(&self) -> MutexGuard<'_, FakeState> {
+        self.state.lock().expect("fake backend lock poisoned")
+    }
+
+    /// Locks the state, unless `operation` was made to fail
+    fn begin(&self, operation: &'static str) -> Result<MutexGuard<'_, FakeState>, BoaError> {
+        let state = self.lock();
+
+        match state.failures.get(operation) {
+            Some(message) => Err(BoaError::Failed(message.clone())),
+            None => Ok(state),
+        }
+    }
+
+    fn with_container<T>(
+        &self,
+        operation: &'static str,
+        container_id: &str,
+        f: impl FnOnce(&mut FakeContainer) -> Result<T, BoaError>,
+    ) -> Result<T, BoaError> {
+        let mut state = self.begin(operation)?;
+
+        let container = state
+            .containers
+            .get_mut(container_id)
+            .ok_or_else(|| BoaError::NotFound(format!("no such container: {container_id}")))?;
+
+        f(container)
+    }
+}
+
+impl FakeState {
+    /// Kills every exec running in `container_id`
+    fn kill_execs(&mut self, container_id: &str) {
+        for exec in self.execs.values() {
+            if exec.container_id == container_id {
+                exec.kill.notify_one();
+            }
+        }
+    }
+}
+
+/// Runs `script` for an exec, sending its events to `tx` until it exits, is killed or times out
+async fn run_script(
+    script: Script,
+    kill: Arc<Notify>,
+    timeout_secs: u64,
+    tx: mpsc::UnboundedSender<Result<ExecEvent, BoaError>>,
+) {
+    let steps = async {
+        for step in script.steps {
+            match step {
+                Step::Stdout(text) => {
+                    tx.send(Ok(ExecEvent::Stdout(Bytes::from(text)))).ok();
+                }
+                Step::Stderr(text) => {
+                    tx.send(Ok(ExecEvent::Stderr(Bytes::from(text)))).ok();
+                }
+                Step::Sleep(duration) => time::sleep(duration).await,
+                Step::Fail(message) => {
+                    tx.send(Err(BoaError::Failed(message))).ok();
+                    return None;
+                }
+            }
+        }
+
+        Some(script.exit_code)
+    };
+
+    let timeout = async {
+        if timeout_secs == 0 {
+            future::pending::<()>().await;
+        }
+
+        time::sleep(Duration::from_secs(timeout_secs)).await;
+    };
+
+    let exit_code = tokio::select! {
+        exit_code = steps => exit_code,
+        () = kill.notified() => Some(KILLED_EXIT_CODE),
+        () = timeout => Some(TIMED_OUT_EXIT_CODE),
+    };
+
+    if let Some(exit_code) = exit_code {
+        tx.send(Ok(ExecEvent::Exited(exit_code))).ok();
+    }
+}
+
+#[async_trait]
+impl ContainerBackend for FakeBackend {
+    async fn create(&self, name: &str, image: &str, _config: &Config) -> Result<(), BoaError> {
+        let delay = self.lock().create_delay;
+        time::sleep(delay).await;
+
+        let mut state = self.begin("create")?;
+
+        if state.containers.contains_key(name) {
+            return Err(BoaError::Failed(format!("container {name} already exists")));
+        }
+
+        state.containers.insert(
+            name.to_string(),
+            FakeContainer {
+                image: image.to_string(),
+                ..FakeContainer::default()
+            },
+        );
+
+        Ok(())
+    }
+
+    async fn start(&self, container_id: &str) -> Result<(), BoaError> {
+        self.with_container("start", container_id, |container| {
+            container.running = true;
+            Ok(())
+        })
+    }
+
+    async fn remove(&self, container_id: &str) -> Result<(), BoaError> {
+        let mut state = self.begin("remove")?;
+
+        state.kill_execs(container_id);
+        state
+            .containers
+            .remove(container_id)
+            .map(|_| ())
+            .ok_or_else(|| BoaError::NotFound(format!("no such container: {container_id}")))
+    }
+
+    async fn pause(&self, container_id: &str) -> Result<(), BoaError> {
+        self.with_container("pause", container_id, |container| {
+            container.paused = true;
+            Ok(())
+        })
+    }
+
+    async fn resume(&self, container_id: &str) -> Result<(), BoaError> {
+        self.with_container("resume", container_id, |container| {
+            container.paused = false;
+            Ok(())
+        })
+    }
+
+    async fn signal(&self, container_id: &str, signal: &str) -> Result<(), BoaError> {
+        self.with_container("signal", container_id, |container| {
+            container.running = false;
+            container.signals.push(signal.to_string());
+            Ok(())
+        })?;
+
+        self.lock().kill_execs(container_id);
+
+        Ok(())
+    }
+
+    async fn exec(
+        &self,
+        container_id: &str,
+        exec_id: Uuid,
+        cmd: Vec<String>,
+        timeout_secs: u64,
+    ) -> Result<ExecStream, BoaError> {
+        self.with_container("exec", container_id, |container| {
+            if container.running {
+                Ok(())
+            } else {
+                Err(BoaError::NotStarted(format!(
+                    "container {container_id} is not running"
+                )))
+            }
+        })?;
+
+        let kill = Arc::new(Notify::new());
+        let script = {
+            let mut state = self.lock();
+            state.execs.insert(
+                exec_id,
+                FakeExec {
+                    container_id: container_id.to_string(),
+                    kill: Arc::clone(&kill),
+                },
+            );
+
+            cmd.last()
+                .and_then(|path| state.scripts.get(path))
+                .cloned()
+                .unwrap_or_default()
+        };
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        let backend = self.clone();
+        tokio::spawn(async move {
+            run_script(script, kill, timeout_secs, tx).await;
+            backend.lock().execs.remove(&exec_id);
+        });
+
+        Ok(Box::pin(stream::unfold(rx, async |mut rx| {
+            rx.recv().await.map(|event| (event, rx))
+        })))
+    }
+
+    async fn kill_exec(&self, _container_id: &str, exec_id: Uuid) -> Result<(), BoaError> {
+        let state = self.begin("kill_exec")?;
+
+        if let Some(exec) = state.execs.get(&exec_id) {
+            exec.kill.notify_one();
+        }
+
+        Ok(())
+    }
+
+    async fn upload(
+        &self,
+        container_id: &str,
+        dir: &str,
+        file_name: &str,
+        contents: Vec<u8>,
+    ) -> Result<(), BoaError> {
+        self.with_container("upload", container_id, |container| {
+            let path = format!("{}/{file_name}", dir.trim_end_matches('/'));
+            container.files.insert(path, contents);
+            Ok(())
+        })
+    }
+
+    async fn download(&self, container_id: &str, path: &str) -> Result<Vec<u8>, BoaError> {
+        self.with_container("download", container_id, |container| {
+            container
+                .files
+                .get(path)
+                .cloned()
+                .ok_or_else(|| BoaError::NotFound(format!("no such file: {path}")))
+        })
+    }
+
+    async fn cpu_usage_ns(&self, container_id: &str) -> Result<u64, BoaError> {
+        self.with_container("cpu_usage_ns", container_id, |_| Ok(0))
+    }
+
+    async fn commit(
+        &self,
+        container_id: &str,
+        repository: &str,
+        tag: &str,
+        owner: &str,
+    ) -> Result<(), BoaError> {
+        let size = self.with_container("commit", container_id, |container| {
+            Ok(container.files.values().map(|file| file.len() as u64).sum())
+        })?;
+
+        self.lock().images.insert(
+            format!("{repository}:{tag}"),
+            FakeImage {
+                size,
+                owner: Some(owner.to_string()),
+            },
+        );
+
+        Ok(())
+    }
+
+    async fn retag(&self, source: &str, repository: &str, tag: &str) -> Result<(), BoaError> {
+        let mut state = self.begin("retag")?;
+
+        let image = state
+            .images
+            .remove(source)
+            .ok_or_else(|| BoaError::NotFound(format!("no such image: {source}")))?;
+        state.images.insert(format!("{repository}:{tag}"), image);
+
+        Ok(())
+    }
+
+    async fn image_owner(&self, image: &str) -> Result<Option<String>, BoaError> {
+        self.begin("image_owner")?
+            .images
+            .get(image)
+            .map(|image| image.owner.clone())
+            .ok_or_else(|| BoaError::NotFound(format!("no such image: {image}")))
+    }
+
+    async fn image_size(&self, image: &str) -> Result<u64, BoaError> {
+        self.begin("image_size")?
+            .images
+            .get(image)
+            .map(|image| image.size)
+            .ok_or_else(|| BoaError::NotFound(format!("no such image: {image}")))
+    }
+
+    async fn image_tags(&self, repository: &str) -> Result<Vec<String>, BoaError> {
+        let prefix = format!("{repository}:");
+
+        Ok(self
+            .begin("image_tags")?
+            .images
+            .keys()
+            .filter(|image| image.starts_with(&prefix))
+            .cloned()
+            .collect())
+    }
+
+    async fn remove_image(&self, image: &str) -> Result<(), BoaError> {
+        self.begin("remove_image")?
+            .images
+            .remove(image)
+            .map(|_| ())
+            .ok_or_else(|| BoaError::NotFound(format!("no such image: {image}")))
+    }
+
+    async fn ping(&self) -> Result<(), BoaError> {
+        self.begin("ping").map(|_| ())
+    }
+}
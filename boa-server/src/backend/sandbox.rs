@@ -0,0 +1,631 @@
+use std::{
+    collections::HashMap,
+    os::unix::process::ExitStatusExt,
+    path::PathBuf,
+    process::{ExitStatus, Stdio},
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures_util::stream;
+use tokio::{
+    fs,
+    io::{AsyncRead, AsyncReadExt},
+    process::Command,
+    sync::mpsc,
+    time,
+};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::{
+    backend::{
+        ContainerBackend, ExecEvent, ExecStream,
+        workspace::{self, Workspaces},
+    },
+    config::{Config, SandboxConfig},
+    error::BoaError,
+};
+
+/// Runs in the new namespaces as `sh -c SETUP_SCRIPT sh <cmd>`: builds a root of read-only
+/// binds of the host on a tmpfs at `$BOA_ROOT`, with the workspace at `$BOA_WORKDIR` and `/tmp`,
+/// pivots into it and unmounts the host's root. The command runs with a clean environment under
+/// `prlimit` and `timeout`, after `setpriv` dropped every capability for good. `timeout` stays in
+/// the foreground so the command is in the process group signals are sent to; as the init of the
+/// pid namespace, everything the command started dies with it when it times out.
+const SETUP_SCRIPT: &str = r#"
+set -eu
+root="$BOA_ROOT"
+
+bind_ro() {
+    if [ -L "$1" ]; then
+        mkdir -p "$root$(dirname "$1")"
+        ln -sfn "$(readlink "$1")" "$root$1"
+    elif [ -d "$1" ]; then
+        mkdir -p "$root$1"
+        mount --rbind "$1" "$root$1"
+        mount -o remount,bind,ro "$root$1"
+    elif [ -e "$1" ]; then
+        mkdir -p "$root$(dirname "$1")"
+        touch "$root$1"
+        mount --bind "$1" "$root$1"
+        mount -o remount,bind,ro "$root$1"
+    fi
+}
+
+mount -t tmpfs -o mode=755 tmpfs "$root"
+
+for path in /usr /bin /sbin /lib /lib32 /lib64 /etc $BOA_RO_BINDS; do
+    bind_ro "$path"
+done
+
+mkdir -p "$root/proc" "$root/dev" "$root/tmp" "$root$BOA_WORKDIR" "$root/.old_root"
+mount -t proc proc "$root/proc"
+for dev in null zero full random urandom; do
+    touch "$root/dev/$dev"
+    mount --bind "/dev/$dev" "$root/dev/$dev"
+done
+mount --bind "$BOA_SRC" "$root$BOA_WORKDIR"
+mount --bind "$BOA_TMP" "$root/tmp"
+mount -o remount,ro "$root"
+
+cd "$root"
+pivot_root . .old_root
+umount -l /.old_root
+cd /
+
+exec prlimit $BOA_RLIMITS -- setpriv --inh-caps=-all --ambient-caps=-all --bounding-set=-all \
+    --no-new-privs -- /usr/bin/env -i "PATH=$BOA_PATH" HOME=/tmp \
+    LANG=C.UTF-8 PYTHONUNBUFFERED=1 PYTHONUSERBASE=/tmp/.local PIP_USER=1 \
+    /bin/sh -c 'cd "$0" && exec timeout --foreground -k 5 "$@"' "$BOA_WORKDIR" "$BOA_TIMEOUT" "$@"
+"#;
+
+/// Unprivileged user execs run as, the server's own user outside of the sandbox
+const SANDBOX_UID: &str = "1000";
+
+/// Namespaces every exec runs in, the network one is added when `network.mode` is `none`. The
+/// setup script keeps the capabilities of the user namespace to build the root, as
+/// `SANDBOX_UID` they are gone once it execs the command.
+const UNSHARE_ARGS: &[&str] = &[
+    "--user",
+    "--map-user",
+    SANDBOX_UID,
+    "--map-group",
+    SANDBOX_UID,
+    "--keep-caps",
+    "--mount",
+    "--pid",
+    "--fork",
+    "--kill-child",
+    "--ipc",
+    "--uts",
+];
+
+/// How long execs get to exit after a stop signal before they are killed, like `docker stop`
+const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Settings of a sandbox, taken from the config when it is created like a container's
+struct Sandbox {
+    running: bool,
+    paused: bool,
+    workspace: PathBuf,
+    working_dir: String,
+    ro_binds: Vec<PathBuf>,
+    path: String,
+    isolate_network: bool,
+    /// `prlimit` options for the memory and process limits
+    rlimits: Vec<String>,
+    cgroup: Option<PathBuf>,
+}
+
+struct SandboxExec {
+    sandbox_id: String,
+    /// The exec leads its own process group, so every process it starts can be signalled
+    pgid: u32,
+}
+
+#[derive(Default)]
+struct SandboxState {
+    sandboxes: HashMap<String, Sandbox>,
+    execs: HashMap<Uuid, SandboxExec>,
+}
+
+/// Runs containers as local processes in user, mount, pid, ipc, uts and network namespaces
+/// with util-linux `unshare`, so boa can run without Docker. Each sandbox is a workspace
+/// directory, every exec in it is a new set of namespaces around it.
+pub struct SandboxBackend {
+    workspaces: Workspaces,
+    cgroup: Option<PathBuf>,
+    state: Arc<Mutex<SandboxState>>,
+}
+
+impl SandboxBackend {
+    /// Checks that namespaces can be created and removes the workspaces of a previous run
+    pub async fn new(config: &Config) -> Result<SandboxBackend, String> {
+        let SandboxConfig { dir, cgroup, .. } = &config.sandbox;
+
+        probe()
+            .await
+            .map_err(|e| format!("the sandbox backend needs user namespaces: {e}"))?;
+
+        if let Some(cgroup) = cgroup {
+            fs::metadata(cgroup.join("cgroup.subtree_control"))
+                .await
+                .map_err(|e| {
+                    format!(
+                        "sandbox.cgroup: {} is not a cgroup v2 directory: {e}",
+                        cgroup.display()
+                    )
+                })?;
+        }
+
+        let workspaces = Workspaces::new(dir, &config.containers.prefix).await?;
+
+        info!(dir = %dir.display(), cgroup = ?cgroup, "using the sandbox backend");
+
+        Ok(SandboxBackend {
+            workspaces,
+            cgroup: cgroup.clone(),
+            state: Arc::new(Mutex::new(SandboxState::default())),
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, SandboxState> {
+        self.state.lock().expect("sandbox lock poisoned")
+    }
+
+    fn with_sandbox<T>(
+        &self,
+        sandbox_id: &str,
+        f: impl FnOnce(&mut Sandbox) -> Result<T, BoaError>,
+    ) -> Result<T, BoaError> {
+        let mut state = self.lock();
+
+        let sandbox = state
+            .sandboxes
+            .get_mut(sandbox_id)
+            .ok_or_else(|| BoaError::NotFound(format!("no such sandbox: {sandbox_id}")))?;
+
+        f(sandbox)
+    }
+
+    /// Process groups of the execs running in `sandbox_id`
+    fn exec_groups(&self, sandbox_id: &str) -> Vec<u32> {
+        self.lock()
+            .execs
+            .values()
+            .filter(|exec| exec.sandbox_id == sandbox_id)
+            .map(|exec| exec.pgid)
+            .collect()
+    }
+
+    /// Sends `signal`, e.g. `KILL`, to every exec running in the sandbox
+    async fn signal_execs(&self, sandbox_id: &str, signal: &str) {
+        for pgid in self.exec_groups(sandbox_id) {
+            if let Err(e) = kill_group(pgid, signal).await {
+                warn!(sandbox_id, pgid, "failed to send {signal}: {e}");
+            }
+        }
+    }
+
+    async fn freeze(&self, sandbox_id: &str, frozen: bool) -> Result<(), BoaError> {
+        let cgroup = self.with_sandbox(sandbox_id, |sandbox| Ok(sandbox.cgroup.clone()))?;
+
+        // without a cgroup the execs are stopped, a process they start meanwhile is not
+        let Some(cgroup) = cgroup else {
+            self.signal_execs(sandbox_id, if frozen { "STOP" } else { "CONT" })
+                .await;
+            return Ok(());
+        };
+
+        fs::write(cgroup.join("cgroup.freeze"), if frozen { "1" } else { "0" })
+            .await
+            .map_err(|e| BoaError::Failed(format!("failed to freeze sandbox: {e}")))
+    }
+
+    /// Creates the cgroup of a sandbox with the memory, pids and cpu limits of `config`
+    async fn create_cgroup(
+        &self,
+        name: &str,
+        config: &Config,
+    ) -> Result<Option<PathBuf>, BoaError> {
+        let Some(parent) = &self.cgroup else {
+            return Ok(None);
+        };
+
+        let cgroup = parent.join(name);
+        let limits = &config.limits;
+
+        let write = async |file: &str, value: String| {
+            fs::write(cgroup.join(file), value).await.map_err(|e| {
+                BoaError::Failed(format!("failed to set {file} of the sandbox cgroup: {e}"))
+            })
+        };
+
+        fs::create_dir(&cgroup)
+            .await
+            .map_err(|e| BoaError::Failed(format!("failed to create sandbox cgroup: {e}")))?;
+
+        write("memory.max", (limits.memory_mb * 1024 * 1024).to_string()).await?;
+        write("pids.max", limits.pids.to_string()).await?;
+        // a period of 100ms, with `cpus` times that of cpu time in each
+        write("cpu.max", format!("{} 100000", limits.nano_cpus() / 10_000)).await?;
+
+        Ok(Some(cgroup))
+    }
+}
+
+/// Checks that util-linux `unshare` can create the namespaces execs run in
+async fn probe() -> Result<(), BoaError> {
+    let output = Command::new("unshare")
+        .args(UNSHARE_ARGS)
+        .arg("true")
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .map_err(|e| BoaError::BackendUnavailable(format!("failed to run unshare: {e}")))?;
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(BoaError::BackendUnavailable(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ))
+    }
+}
+
+/// Sends `signal`, e.g. `KILL`, to every process in the process group `pgid`
+async fn kill_group(pgid: u32, signal: &str) -> Result<(), BoaError> {
+    let status = Command::new("kill")
+        .args(["-s", signal, "--", &format!("-{pgid}")])
+        .stderr(Stdio::null())
+        .status()
+        .await
+        .map_err(|e| BoaError::Failed(format!("failed to run kill: {e}")))?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(BoaError::NotFound(format!("kill exited with {status}")))
+    }
+}
+
+/// Exit code the way a shell reports it, `128 + n` for processes killed by signal `n`
+fn exit_code(status: ExitStatus) -> i64 {
+    status
+        .code()
+        .or_else(|| status.signal().map(|signal| 128 + signal))
+        .map_or(-1, i64::from)
+}
+
+/// Sends what `reader` outputs to `tx` in chunks, as the events `event` makes of them
+async fn forward(
+    mut reader: impl AsyncRead + Unpin,
+    event: fn(Bytes) -> ExecEvent,
+    tx: mpsc::UnboundedSender<Result<ExecEvent, BoaError>>,
+) {
+    let mut buffer = vec![0; 8192];
+
+    loop {
+        match reader.read(&mut buffer).await {
+            Ok(0) => return,
+            Ok(read) => {
+                tx.send(Ok(event(Bytes::copy_from_slice(&buffer[..read]))))
+                    .ok();
+            }
+            Err(e) => {
+                tx.send(Err(BoaError::Failed(format!(
+                    "failed to read exec output: {e}"
+                ))))
+                .ok();
+                return;
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl ContainerBackend for SandboxBackend {
+    async fn create(&self, name: &str, image: &str, config: &Config) -> Result<(), BoaError> {
+        if self.lock().sandboxes.contains_key(name) {
+            return Err(BoaError::Failed(format!("sandbox {name} already exists")));
+        }
+
+        let workspace = self.workspaces.create(name, image).await?;
+        fs::create_dir(workspace.join("root"))
+            .await
+            .map_err(|e| BoaError::Failed(format!("failed to create sandbox {name}: {e}")))?;
+
+        let cgroup = self.create_cgroup(name, config).await?;
+
+        let mut rlimits = vec![format!("--nproc={}", config.limits.pids)];
+        // the cgroup limits memory that is used, not the address space a runtime reserves
+        if cgroup.is_none() {
+            rlimits.push(format!("--as={}", config.limits.memory_mb * 1024 * 1024));
+        }
+
+        self.lock().sandboxes.insert(
+            name.to_string(),
+            Sandbox {
+                running: false,
+                paused: false,
+                workspace,
+                working_dir: config.containers.working_dir.clone(),
+                ro_binds: config.sandbox.ro_binds.clone(),
+                path: config.sandbox.path.clone(),
+                isolate_network: config.network.mode == "none",
+                rlimits,
+                cgroup,
+            },
+        );
+
+        Ok(())
+    }
+
+    async fn start(&self, container_id: &str) -> Result<(), BoaError> {
+        self.with_sandbox(container_id, |sandbox| {
+            sandbox.running = true;
+            Ok(())
+        })
+    }
+
+    async fn remove(&self, container_id: &str) -> Result<(), BoaError> {
+        let sandbox = self
+            .lock()
+            .sandboxes
+            .remove(container_id)
+            .ok_or_else(|| BoaError::NotFound(format!("no such sandbox: {container_id}")))?;
+
+        self.signal_execs(container_id, "KILL").await;
+
+        if let Some(cgroup) = &sandbox.cgroup {
+            fs::write(cgroup.join("cgroup.kill"), "1").await.ok();
+            // the cgroup can only be removed once its processes are gone
+            for _ in 0..50 {
+                if fs::remove_dir(cgroup).await.is_ok() {
+                    break;
+                }
+                time::sleep(Duration::from_millis(100)).await;
+            }
+        }
+
+        self.workspaces.remove(&sandbox.workspace).await
+    }
+
+    async fn pause(&self, container_id: &str) -> Result<(), BoaError> {
+        self.freeze(container_id, true).await?;
+
+        self.with_sandbox(container_id, |sandbox| {
+            sandbox.paused = true;
+            Ok(())
+        })
+    }
+
+    async fn resume(&self, container_id: &str) -> Result<(), BoaError> {
+        self.freeze(container_id, false).await?;
+
+        self.with_sandbox(container_id, |sandbox| {
+            sandbox.paused = false;
+            Ok(())
+        })
+    }
+
+    async fn signal(&self, container_id: &str, signal: &str) -> Result<(), BoaError> {
+        let paused = self.with_sandbox(container_id, |sandbox| {
+            sandbox.running = false;
+            Ok(sandbox.paused)
+        })?;
+
+        if paused {
+            self.resume(container_id).await?;
+        }
+
+        self.signal_execs(container_id, signal.trim_start_matches("SIG"))
+            .await;
+
+        let mut waited = Duration::ZERO;
+        while waited < STOP_TIMEOUT && !self.exec_groups(container_id).is_empty() {
+            time::sleep(Duration::from_millis(100)).await;
+            waited += Duration::from_millis(100);
+        }
+
+        self.signal_execs(container_id, "KILL").await;
+
+        Ok(())
+    }
+
+    async fn exec(
+        &self,
+        container_id: &str,
+        exec_id: Uuid,
+        cmd: Vec<String>,
+        timeout_secs: u64,
+    ) -> Result<ExecStream, BoaError> {
+        let mut command = self.with_sandbox(container_id, |sandbox| {
+            if !sandbox.running {
+                return Err(BoaError::NotStarted("container is not started".to_string()));
+            }
+
+            // the exec joins the cgroup before `unshare` starts, so every process is in it
+            let mut command = match &sandbox.cgroup {
+                Some(cgroup) => {
+                    let mut command = Command::new("sh");
+                    command
+                        .args(["-c", "echo $$ > \"$0/cgroup.procs\" && exec \"$@\""])
+                        .arg(cgroup)
+                        .arg("unshare");
+                    command
+                }
+                None => Command::new("unshare"),
+            };
+
+            command.args(UNSHARE_ARGS);
+            if sandbox.isolate_network {
+                command.arg("--net");
+            }
+
+            let ro_binds = sandbox
+                .ro_binds
+                .iter()
+                .map(|path| path.to_string_lossy())
+                .collect::<Vec<_>>()
+                .join(" ");
+
+            command
+                .args(["--", "sh", "-c", SETUP_SCRIPT, "sh"])
+                .args(&cmd)
+                .env("BOA_ROOT", sandbox.workspace.join("root"))
+                .env("BOA_SRC", sandbox.workspace.join("src"))
+                .env("BOA_TMP", sandbox.workspace.join("tmp"))
+                .env("BOA_WORKDIR", &sandbox.working_dir)
+                .env("BOA_RO_BINDS", ro_binds)
+                .env("BOA_PATH", &sandbox.path)
+                .env("BOA_RLIMITS", sandbox.rlimits.join(" "))
+                .env("BOA_TIMEOUT", timeout_secs.to_string());
+
+            Ok(command)
+        })?;
+
+        let mut child = command
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .process_group(0)
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| BoaError::Failed(format!("failed to start sandbox exec: {e}")))?;
+
+        let pgid = child
+            .id()
+            .ok_or_else(|| BoaError::Failed("sandbox exec exited before it started".to_string()))?;
+        self.lock().execs.insert(
+            exec_id,
+            SandboxExec {
+                sandbox_id: container_id.to_string(),
+                pgid,
+            },
+        );
+
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let stderr = child.stderr.take().expect("stderr is piped");
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        let state = Arc::clone(&self.state);
+
+        tokio::spawn(async move {
+            tokio::join!(
+                forward(stdout, ExecEvent::Stdout, tx.clone()),
+                forward(stderr, ExecEvent::Stderr, tx.clone()),
+            );
+
+            let event = child
+                .wait()
+                .await
+                .map(|status| ExecEvent::Exited(exit_code(status)))
+                .map_err(|e| BoaError::Failed(format!("failed to wait for sandbox exec: {e}")));
+            tx.send(event).ok();
+
+            state
+                .lock()
+                .expect("sandbox lock poisoned")
+                .execs
+                .remove(&exec_id);
+        });
+
+        Ok(Box::pin(stream::unfold(rx, async |mut rx| {
+            rx.recv().await.map(|event| (event, rx))
+        })))
+    }
+
+    async fn kill_exec(&self, _container_id: &str, exec_id: Uuid) -> Result<(), BoaError> {
+        let pgid = self.lock().execs.get(&exec_id).map(|exec| exec.pgid);
+
+        match pgid {
+            Some(pgid) => kill_group(pgid, "KILL").await,
+            None => Ok(()),
+        }
+    }
+
+    async fn upload(
+        &self,
+        container_id: &str,
+        dir: &str,
+        file_name: &str,
+        contents: Vec<u8>,
+    ) -> Result<(), BoaError> {
+        let (workspace, working_dir) = self.with_sandbox(container_id, |sandbox| {
+            Ok((sandbox.workspace.clone(), sandbox.working_dir.clone()))
+        })?;
+
+        workspace::upload(&workspace, &working_dir, dir, file_name, contents).await
+    }
+
+    async fn download(&self, container_id: &str, path: &str) -> Result<Vec<u8>, BoaError> {
+        let (workspace, working_dir) = self.with_sandbox(container_id, |sandbox| {
+            Ok((sandbox.workspace.clone(), sandbox.working_dir.clone()))
+        })?;
+
+        workspace::download(&workspace, &working_dir, path).await
+    }
+
+    async fn cpu_usage_ns(&self, container_id: &str) -> Result<u64, BoaError> {
+        let Some(cgroup) = self.with_sandbox(container_id, |sandbox| Ok(sandbox.cgroup.clone()))?
+        else {
+            // cpu time is only accounted with a cgroup
+            return Ok(0);
+        };
+
+        let stat = fs::read_to_string(cgroup.join("cpu.stat"))
+            .await
+            .map_err(|e| BoaError::Failed(format!("failed to read sandbox cpu usage: {e}")))?;
+
+        stat.lines()
+            .find_map(|line| line.strip_prefix("usage_usec "))
+            .and_then(|usec| usec.trim().parse::<u64>().ok())
+            .map(|usec| usec * 1000)
+            .ok_or_else(|| {
+                BoaError::Failed("cpu.stat of the sandbox has no usage_usec".to_string())
+            })
+    }
+
+    async fn commit(
+        &self,
+        container_id: &str,
+        repository: &str,
+        tag: &str,
+        owner: &str,
+    ) -> Result<(), BoaError> {
+        let workspace = self.with_sandbox(container_id, |sandbox| Ok(sandbox.workspace.clone()))?;
+
+        self.workspaces
+            .commit(&workspace, repository, tag, owner)
+            .await
+    }
+
+    async fn retag(&self, source: &str, repository: &str, tag: &str) -> Result<(), BoaError> {
+        self.workspaces.retag(source, repository, tag).await
+    }
+
+    async fn image_owner(&self, image: &str) -> Result<Option<String>, BoaError> {
+        self.workspaces.image_owner(image).await
+    }
+
+    async fn image_size(&self, image: &str) -> Result<u64, BoaError> {
+        self.workspaces.image_size(image).await
+    }
+
+    async fn image_tags(&self, repository: &str) -> Result<Vec<String>, BoaError> {
+        self.workspaces.image_tags(repository).await
+    }
+
+    async fn remove_image(&self, image: &str) -> Result<(), BoaError> {
+        self.workspaces.remove_image(image).await
+    }
+
+    async fn ping(&self) -> Result<(), BoaError> {
+        probe().await?;
+        self.workspaces.check().await
+    }
+}
@@ -0,0 +1,595 @@
+use std::{
+    collections::HashMap,
+    future::{Future, pending, poll_fn},
+    path::{Path, PathBuf},
+    pin::pin,
+    sync::{
+        Arc, Mutex, MutexGuard,
+        atomic::{AtomicU64, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use boa_core::packets::server::error::ServerError;
+use bytes::Bytes;
+use futures_util::stream;
+use tokio::{
+    fs,
+    sync::{Notify, mpsc, watch},
+    task, time,
+};
+use tracing::info;
+use uuid::Uuid;
+use wasmtime::{Engine, Linker, Module, Store, StoreLimits, StoreLimitsBuilder};
+use wasmtime_wasi::{
+    DirPerms, FilePerms, I32Exit, OutputStream, Pollable, StdoutStream, StreamError, StreamResult,
+    WasiCtxBuilder,
+    preview1::{self, WasiP1Ctx},
+};
+
+use crate::{
+    backend::{
+        ContainerBackend, ExecEvent, ExecStream,
+        workspace::{self, Workspaces},
+    },
+    config::Config,
+    container::TIMED_OUT_EXIT_CODE,
+    error::BoaError,
+};
+
+/// How often running modules yield, so they can be paused, killed and timed out
+const EPOCH_TICK: Duration = Duration::from_millis(10);
+
+/// Exit code of a killed exec, like a process killed with SIGKILL
+const KILLED_EXIT_CODE: i64 = 137;
+
+/// Exit code of a module that trapped, like a process that aborted
+const TRAPPED_EXIT_CODE: i64 = 134;
+
+/// Output chunks an exec may have buffered before the module waits for them to be sent
+const OUTPUT_BUFFER: usize = 64;
+
+/// Where `wasi.python_home` is mounted in the module's filesystem
+const PYTHON_HOME: &str = "/usr/local";
+
+struct WasiSandbox {
+    running: bool,
+    paused: watch::Sender<bool>,
+    workspace: PathBuf,
+    working_dir: String,
+    memory_bytes: usize,
+    /// Time spent running modules of this sandbox
+    cpu_ns: Arc<AtomicU64>,
+}
+
+struct WasiExec {
+    sandbox_id: String,
+    kill: Arc<Notify>,
+}
+
+#[derive(Default)]
+struct WasiState {
+    sandboxes: HashMap<String, WasiSandbox>,
+    execs: HashMap<Uuid, WasiExec>,
+}
+
+/// What a running module can reach, its files and output through WASI and its memory limit
+struct ExecContext {
+    wasi: WasiP1Ctx,
+    limits: StoreLimits,
+}
+
+/// Runs WASI modules in wasmtime. Containers are workspace directories like in the sandbox
+/// backend, preopened as the working directory and `/tmp` of every module run in them. Modules
+/// get no network, no environment of the host and no files outside of those.
+pub struct WasiBackend {
+    workspaces: Workspaces,
+    engine: Engine,
+    linker: Arc<Linker<ExecContext>>,
+    /// Compiled `wasi.python`, which python files are run with
+    python: Option<Module>,
+    python_home: Option<PathBuf>,
+    state: Arc<Mutex<WasiState>>,
+}
+
+impl WasiBackend {
+    /// Compiles `wasi.python` and starts the clock running modules yield on
+    pub async fn new(config: &Config) -> Result<WasiBackend, String> {
+        let mut engine_config = wasmtime::Config::new();
+        engine_config.async_support(true).epoch_interruption(true);
+
+        let engine = Engine::new(&engine_config)
+            .map_err(|e| format!("failed to create the wasm engine: {e:#}"))?;
+
+        let mut linker = Linker::new(&engine);
+        preview1::add_to_linker_async(&mut linker, |context: &mut ExecContext| &mut context.wasi)
+            .map_err(|e| format!("failed to link WASI: {e:#}"))?;
+
+        let python = match &config.wasi.python {
+            Some(path) => {
+                let started = Instant::now();
+                let wasm = fs::read(path)
+                    .await
+                    .map_err(|e| format!("failed to read {}: {e}", path.display()))?;
+                let module = compile(&engine, &path.to_string_lossy(), wasm)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                info!(path = %path.display(), elapsed = ?started.elapsed(), "compiled python module");
+                Some(module)
+            }
+            None => None,
+        };
+
+        let workspaces = Workspaces::new(&config.wasi.dir, &config.containers.prefix).await?;
+
+        // a thread of its own, a module that does not yield would hold up a tokio worker
+        let ticking = engine.clone();
+        thread::spawn(move || {
+            loop {
+                thread::sleep(EPOCH_TICK);
+                ticking.increment_epoch();
+            }
+        });
+
+        info!(dir = %config.wasi.dir.display(), python = python.is_some(), "using the wasi backend");
+
+        Ok(WasiBackend {
+            workspaces,
+            engine,
+            linker: Arc::new(linker),
+            python,
+            python_home: config.wasi.python_home.clone(),
+            state: Arc::new(Mutex::new(WasiState::default())),
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, WasiState> {
+        self.state.lock().expect("wasi lock poisoned")
+    }
+
+    fn with_sandbox<T>(
+        &self,
+        sandbox_id: &str,
+        f: impl FnOnce(&mut WasiSandbox) -> Result<T, BoaError>,
+    ) -> Result<T, BoaError> {
+        let mut state = self.lock();
+
+        let sandbox = state
+            .sandboxes
+            .get_mut(sandbox_id)
+            .ok_or_else(|| BoaError::NotFound(format!("no such container: {sandbox_id}")))?;
+
+        f(sandbox)
+    }
+
+    fn kill_execs(&self, sandbox_id: &str) {
+        for exec in self.lock().execs.values() {
+            if exec.sandbox_id == sandbox_id {
+                exec.kill.notify_one();
+            }
+        }
+    }
+
+    /// The module `cmd` runs and its arguments. A `.wasm` file is run itself, anything `python`
+    /// runs is run with `wasi.python`.
+    async fn module(
+        &self,
+        workspace: &Path,
+        working_dir: &str,
+        cmd: &[String],
+    ) -> Result<(Module, Vec<String>), BoaError> {
+        match cmd {
+            [_, file, ..] if Path::new(file).extension().is_some_and(|ext| ext == "wasm") => {
+                let path = if file.starts_with('/') {
+                    file.clone()
+                } else {
+                    format!("{working_dir}/{file}")
+                };
+                let wasm = workspace::download(workspace, working_dir, &path).await?;
+
+                Ok((compile(&self.engine, &path, wasm).await?, cmd[1..].to_vec()))
+            }
+            [program, ..] if program == "python" => match &self.python {
+                Some(python) => Ok((python.clone(), cmd.to_vec())),
+                None => Err(BoaError::Refused(
+                    ServerError::ProcessStartFailed,
+                    "set wasi.python to run python files".to_string(),
+                )),
+            },
+            _ => Err(BoaError::Refused(
+                ServerError::ProcessStartFailed,
+                format!(
+                    "the wasi backend can only run .wasm files and python, not {}",
+                    cmd.join(" ")
+                ),
+            )),
+        }
+    }
+
+    /// A store running with the files of `workspace`, writing its output to `tx`
+    fn store(
+        &self,
+        workspace: &Path,
+        working_dir: &str,
+        args: &[String],
+        memory_bytes: usize,
+        tx: &mpsc::Sender<Result<ExecEvent, BoaError>>,
+    ) -> Result<Store<ExecContext>, BoaError> {
+        let preopen_failed =
+            |e: wasmtime::Error| BoaError::Failed(format!("failed to preopen a directory: {e:#}"));
+
+        let mut wasi = WasiCtxBuilder::new();
+        wasi.args(args)
+            .stdout(ExecOutput {
+                tx: tx.clone(),
+                event: ExecEvent::Stdout,
+            })
+            .stderr(ExecOutput {
+                tx: tx.clone(),
+                event: ExecEvent::Stderr,
+            });
+
+        // `.` lets relative paths resolve to the working directory, like the cwd of a process
+        for guest_path in [working_dir, "."] {
+            wasi.preopened_dir(
+                workspace.join("src"),
+                guest_path,
+                DirPerms::all(),
+                FilePerms::all(),
+            )
+            .map_err(preopen_failed)?;
+        }
+        wasi.preopened_dir(
+            workspace.join("tmp"),
+            "/tmp",
+            DirPerms::all(),
+            FilePerms::all(),
+        )
+        .map_err(preopen_failed)?;
+
+        if let Some(python_home) = &self.python_home {
+            wasi.preopened_dir(python_home, PYTHON_HOME, DirPerms::READ, FilePerms::READ)
+                .map_err(preopen_failed)?
+                .env("PYTHONHOME", PYTHON_HOME);
+        }
+
+        let mut store = Store::new(
+            &self.engine,
+            ExecContext {
+                wasi: wasi.build_p1(),
+                limits: StoreLimitsBuilder::new().memory_size(memory_bytes).build(),
+            },
+        );
+        store.limiter(|context| &mut context.limits);
+        store.epoch_deadline_async_yield_and_update(1);
+
+        Ok(store)
+    }
+}
+
+/// Compiles the module at `path`, which takes a while for large ones like python
+async fn compile(engine: &Engine, path: &str, wasm: Vec<u8>) -> Result<Module, BoaError> {
+    let engine = engine.clone();
+    let path = path.to_string();
+
+    task::spawn_blocking(move || {
+        Module::new(&engine, wasm)
+            .map_err(|e| BoaError::Failed(format!("failed to compile {path}: {e:#}")))
+    })
+    .await
+    .map_err(|e| BoaError::Failed(format!("failed to compile module: {e}")))?
+}
+
+/// Adds the time spent polling `future` to `cpu_ns`. Modules run while their call is polled,
+/// and yield every `EPOCH_TICK`, so this is the time they ran for.
+fn timed<F: Future>(future: F, cpu_ns: Arc<AtomicU64>) -> impl Future<Output = F::Output> {
+    let mut future = Box::pin(future);
+
+    poll_fn(move |cx| {
+        let started = Instant::now();
+        let poll = future.as_mut().poll(cx);
+        let elapsed = u64::try_from(started.elapsed().as_nanos()).unwrap_or(u64::MAX);
+        cpu_ns.fetch_add(elapsed, Ordering::Relaxed);
+        poll
+    })
+}
+
+/// Exit code of a finished module, or the message of its trap
+fn exit_code(result: wasmtime::Result<()>) -> Result<i64, String> {
+    match result {
+        Ok(()) => Ok(0),
+        Err(e) => match e.downcast_ref::<I32Exit>() {
+            Some(I32Exit(code)) => Ok(i64::from(*code)),
+            None => Err(e.root_cause().to_string()),
+        },
+    }
+}
+
+/// Sends what a module writes to stdout or stderr to its exec stream, as it is written
+#[derive(Clone)]
+struct ExecOutput {
+    tx: mpsc::Sender<Result<ExecEvent, BoaError>>,
+    event: fn(Bytes) -> ExecEvent,
+}
+
+impl StdoutStream for ExecOutput {
+    fn stream(&self) -> Box<dyn OutputStream> {
+        Box::new(self.clone())
+    }
+
+    fn isatty(&self) -> bool {
+        false
+    }
+}
+
+#[async_trait]
+impl OutputStream for ExecOutput {
+    fn write(&mut self, bytes: Bytes) -> StreamResult<()> {
+        self.tx
+            .try_send(Ok((self.event)(bytes)))
+            .map_err(|_| StreamError::Closed)
+    }
+
+    fn flush(&mut self) -> StreamResult<()> {
+        Ok(())
+    }
+
+    fn check_write(&mut self) -> StreamResult<usize> {
+        if self.tx.is_closed() {
+            return Err(StreamError::Closed);
+        }
+
+        // a write is one chunk, so the module waits in `ready` while the buffer is full
+        Ok(if self.tx.capacity() > 0 { 64 * 1024 } else { 0 })
+    }
+}
+
+#[async_trait]
+impl Pollable for ExecOutput {
+    async fn ready(&mut self) {
+        self.tx.reserve().await.ok();
+    }
+}
+
+#[async_trait]
+impl ContainerBackend for WasiBackend {
+    async fn create(&self, name: &str, image: &str, config: &Config) -> Result<(), BoaError> {
+        if self.lock().sandboxes.contains_key(name) {
+            return Err(BoaError::Failed(format!("container {name} already exists")));
+        }
+
+        let workspace = self.workspaces.create(name, image).await?;
+
+        self.lock().sandboxes.insert(
+            name.to_string(),
+            WasiSandbox {
+                running: false,
+                paused: watch::Sender::new(false),
+                workspace,
+                working_dir: config.containers.working_dir.clone(),
+                memory_bytes: usize::try_from(config.limits.memory_mb * 1024 * 1024)
+                    .unwrap_or(usize::MAX),
+                cpu_ns: Arc::new(AtomicU64::new(0)),
+            },
+        );
+
+        Ok(())
+    }
+
+    async fn start(&self, container_id: &str) -> Result<(), BoaError> {
+        self.with_sandbox(container_id, |sandbox| {
+            sandbox.running = true;
+            Ok(())
+        })
+    }
+
+    async fn remove(&self, container_id: &str) -> Result<(), BoaError> {
+        self.kill_execs(container_id);
+
+        let sandbox = self
+            .lock()
+            .sandboxes
+            .remove(container_id)
+            .ok_or_else(|| BoaError::NotFound(format!("no such container: {container_id}")))?;
+
+        self.workspaces.remove(&sandbox.workspace).await
+    }
+
+    async fn pause(&self, container_id: &str) -> Result<(), BoaError> {
+        self.with_sandbox(container_id, |sandbox| {
+            sandbox.paused.send_replace(true);
+            Ok(())
+        })
+    }
+
+    async fn resume(&self, container_id: &str) -> Result<(), BoaError> {
+        self.with_sandbox(container_id, |sandbox| {
+            sandbox.paused.send_replace(false);
+            Ok(())
+        })
+    }
+
+    async fn signal(&self, container_id: &str, _signal: &str) -> Result<(), BoaError> {
+        // modules cannot handle signals, so any signal stops them
+        self.with_sandbox(container_id, |sandbox| {
+            sandbox.running = false;
+            Ok(())
+        })?;
+
+        self.kill_execs(container_id);
+
+        Ok(())
+    }
+
+    async fn exec(
+        &self,
+        container_id: &str,
+        exec_id: Uuid,
+        cmd: Vec<String>,
+        timeout_secs: u64,
+    ) -> Result<ExecStream, BoaError> {
+        let (workspace, working_dir, memory_bytes, mut paused, cpu_ns) =
+            self.with_sandbox(container_id, |sandbox| {
+                if !sandbox.running {
+                    return Err(BoaError::NotStarted("container is not started".to_string()));
+                }
+
+                Ok((
+                    sandbox.workspace.clone(),
+                    sandbox.working_dir.clone(),
+                    sandbox.memory_bytes,
+                    sandbox.paused.subscribe(),
+                    Arc::clone(&sandbox.cpu_ns),
+                ))
+            })?;
+
+        let (module, args) = self.module(&workspace, &working_dir, &cmd).await?;
+
+        let (tx, rx) = mpsc::channel(OUTPUT_BUFFER);
+        let mut store = self.store(&workspace, &working_dir, &args, memory_bytes, &tx)?;
+
+        let instance = self
+            .linker
+            .instantiate_async(&mut store, &module)
+            .await
+            .map_err(|e| BoaError::Failed(format!("failed to instantiate {}: {e:#}", args[0])))?;
+        let start = instance
+            .get_typed_func::<(), ()>(&mut store, "_start")
+            .map_err(|e| BoaError::Failed(format!("{} is not a WASI command: {e:#}", args[0])))?;
+
+        let kill = Arc::new(Notify::new());
+        self.lock().execs.insert(
+            exec_id,
+            WasiExec {
+                sandbox_id: container_id.to_string(),
+                kill: Arc::clone(&kill),
+            },
+        );
+
+        let state = Arc::clone(&self.state);
+
+        tokio::spawn(async move {
+            let mut running = pin!(timed(
+                async move { start.call_async(&mut store, ()).await },
+                cpu_ns
+            ));
+            let mut timeout = pin!(async {
+                match timeout_secs {
+                    0 => pending().await,
+                    secs => time::sleep(Duration::from_secs(secs)).await,
+                }
+            });
+
+            let exit_code = loop {
+                // a paused module is not polled, so it stays where it yielded
+                let is_paused = *paused.borrow_and_update();
+
+                tokio::select! {
+                    result = &mut running, if !is_paused => break exit_code(result),
+                    Ok(()) = paused.changed() => {}
+                    () = kill.notified() => break Ok(KILLED_EXIT_CODE),
+                    () = &mut timeout => break Ok(TIMED_OUT_EXIT_CODE),
+                }
+            };
+
+            let exit_code = exit_code.unwrap_or_else(|trap| {
+                let message = Bytes::from(format!("{trap}\n"));
+                tx.try_send(Ok(ExecEvent::Stderr(message))).ok();
+                TRAPPED_EXIT_CODE
+            });
+            tx.send(Ok(ExecEvent::Exited(exit_code))).await.ok();
+
+            state
+                .lock()
+                .expect("wasi lock poisoned")
+                .execs
+                .remove(&exec_id);
+        });
+
+        Ok(Box::pin(stream::unfold(rx, async |mut rx| {
+            rx.recv().await.map(|event| (event, rx))
+        })))
+    }
+
+    async fn kill_exec(&self, _container_id: &str, exec_id: Uuid) -> Result<(), BoaError> {
+        if let Some(exec) = self.lock().execs.get(&exec_id) {
+            exec.kill.notify_one();
+        }
+
+        Ok(())
+    }
+
+    async fn upload(
+        &self,
+        container_id: &str,
+        dir: &str,
+        file_name: &str,
+        contents: Vec<u8>,
+    ) -> Result<(), BoaError> {
+        let (workspace, working_dir) = self.with_sandbox(container_id, |sandbox| {
+            Ok((sandbox.workspace.clone(), sandbox.working_dir.clone()))
+        })?;
+
+        workspace::upload(&workspace, &working_dir, dir, file_name, contents).await
+    }
+
+    async fn download(&self, container_id: &str, path: &str) -> Result<Vec<u8>, BoaError> {
+        let (workspace, working_dir) = self.with_sandbox(container_id, |sandbox| {
+            Ok((sandbox.workspace.clone(), sandbox.working_dir.clone()))
+        })?;
+
+        workspace::download(&workspace, &working_dir, path).await
+    }
+
+    async fn cpu_usage_ns(&self, container_id: &str) -> Result<u64, BoaError> {
+        self.with_sandbox(container_id, |sandbox| {
+            Ok(sandbox.cpu_ns.load(Ordering::Relaxed))
+        })
+    }
+
+    async fn commit(
+        &self,
+        container_id: &str,
+        repository: &str,
+        tag: &str,
+        owner: &str,
+    ) -> Result<(), BoaError> {
+        let workspace = self.with_sandbox(container_id, |sandbox| Ok(sandbox.workspace.clone()))?;
+
+        self.workspaces
+            .commit(&workspace, repository, tag, owner)
+            .await
+    }
+
+    async fn retag(&self, source: &str, repository: &str, tag: &str) -> Result<(), BoaError> {
+        self.workspaces.retag(source, repository, tag).await
+    }
+
+    async fn image_owner(&self, image: &str) -> Result<Option<String>, BoaError> {
+        self.workspaces.image_owner(image).await
+    }
+
+    async fn image_size(&self, image: &str) -> Result<u64, BoaError> {
+        self.workspaces.image_size(image).await
+    }
+
+    async fn image_tags(&self, repository: &str) -> Result<Vec<String>, BoaError> {
+        self.workspaces.image_tags(repository).await
+    }
+
+    async fn remove_image(&self, image: &str) -> Result<(), BoaError> {
+        self.workspaces.remove_image(image).await
+    }
+
+    async fn ping(&self) -> Result<(), BoaError> {
+        if let Some(python_home) = &self.python_home {
+            fs::metadata(python_home).await.map_err(|e| {
+                BoaError::BackendUnavailable(format!("{}: {e}", python_home.display()))
+            })?;
+        }
+
+        self.workspaces.check().await
+    }
+}
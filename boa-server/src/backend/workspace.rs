@@ -0,0 +1,418 @@
+use std::{
+    fs::File,
+    io::{self, Read, Write},
+    os::fd::OwnedFd,
+    path::{Component, Path, PathBuf},
+};
+
+use boa_core::packets::server::error::ServerError;
+use rustix::{
+    fs::{self as rfs, Mode, OFlags},
+    io::Errno,
+};
+use tempfile::TempDir;
+use tokio::{fs, task};
+use tracing::warn;
+
+use crate::{error::BoaError, snapshot};
+
+/// File next to the `src` and `tmp` of an image with the identity that committed it
+const OWNER_FILE: &str = "owner";
+
+/// Files of the backends that run code locally. A container is a workspace directory
+/// `<dir>/containers/<name>` with its `src` and `tmp`, a snapshot is a copy of them in
+/// `<dir>/images/<repository:tag>`.
+pub struct Workspaces {
+    containers_dir: PathBuf,
+    images_dir: PathBuf,
+    /// Images of this repository, or of repositories named after it like the one snapshots are
+    /// committed to before their size is checked, are snapshots. Every other image is a runtime,
+    /// which local backends run from the host.
+    snapshot_repository: String,
+}
+
+impl Workspaces {
+    /// Uses `dir`, removing the workspaces of a previous run
+    pub async fn new(dir: &Path, prefix: &str) -> Result<Workspaces, String> {
+        let containers_dir = dir.join("containers");
+        let images_dir = dir.join("images");
+
+        if fs::metadata(&containers_dir).await.is_ok() {
+            warn!(dir = %containers_dir.display(), "removing workspaces of a previous run");
+            fs::remove_dir_all(&containers_dir)
+                .await
+                .map_err(|e| format!("failed to remove old workspaces: {e}"))?;
+        }
+
+        for dir in [&containers_dir, &images_dir] {
+            fs::create_dir_all(dir)
+                .await
+                .map_err(|e| format!("failed to create {}: {e}", dir.display()))?;
+        }
+
+        Ok(Workspaces {
+            containers_dir,
+            images_dir,
+            snapshot_repository: snapshot::repository(prefix),
+        })
+    }
+
+    /// Checks that workspaces and snapshots can still be created, e.g. that their directories
+    /// were not removed or their disk made read-only
+    pub async fn check(&self) -> Result<(), BoaError> {
+        let dirs = [self.containers_dir.clone(), self.images_dir.clone()];
+
+        task::spawn_blocking(move || {
+            for dir in dirs {
+                tempfile::tempdir_in(&dir)
+                    .and_then(TempDir::close)
+                    .map_err(|e| {
+                        BoaError::BackendUnavailable(format!(
+                            "cannot write to {}: {e}",
+                            dir.display()
+                        ))
+                    })?;
+            }
+
+            Ok(())
+        })
+        .await
+        .map_err(|e| BoaError::Failed(format!("failed to check the workspaces: {e}")))?
+    }
+
+    fn is_snapshot(&self, image: &str) -> bool {
+        image.starts_with(&self.snapshot_repository)
+    }
+
+    fn image_dir(&self, image: &str) -> Result<PathBuf, BoaError> {
+        if image.contains('/') || image.starts_with('.') {
+            return Err(BoaError::NotFound(format!("invalid image name {image:?}")));
+        }
+
+        Ok(self.images_dir.join(image))
+    }
+
+    /// Creates the workspace of `name`, with the files of `image` when it is a snapshot
+    pub async fn create(&self, name: &str, image: &str) -> Result<PathBuf, BoaError> {
+        let workspace = self.containers_dir.join(name);
+
+        if self.is_snapshot(image) {
+            let image_dir = self.image_dir(image)?;
+
+            for dir in ["src", "tmp"] {
+                copy_dir(&image_dir.join(dir), &workspace.join(dir))
+                    .await
+                    .map_err(|e| {
+                        BoaError::Failed(format!("failed to restore snapshot {image}: {e}"))
+                    })?;
+            }
+        }
+
+        for dir in ["src", "tmp"] {
+            fs::create_dir_all(workspace.join(dir))
+                .await
+                .map_err(|e| BoaError::Failed(format!("failed to create workspace {name}: {e}")))?;
+        }
+
+        Ok(workspace)
+    }
+
+    pub async fn remove(&self, workspace: &Path) -> Result<(), BoaError> {
+        fs::remove_dir_all(workspace)
+            .await
+            .map_err(|e| BoaError::Failed(format!("failed to remove {}: {e}", workspace.display())))
+    }
+
+    /// Saves `src` and `tmp` of `workspace` as the image `repository:tag`
+    pub async fn commit(
+        &self,
+        workspace: &Path,
+        repository: &str,
+        tag: &str,
+        owner: &str,
+    ) -> Result<(), BoaError> {
+        let image_dir = self.image_dir(&format!("{repository}:{tag}"))?;
+
+        if fs::metadata(&image_dir).await.is_ok() {
+            fs::remove_dir_all(&image_dir)
+                .await
+                .map_err(|e| BoaError::Failed(format!("failed to replace snapshot: {e}")))?;
+        }
+
+        for dir in ["src", "tmp"] {
+            copy_dir(&workspace.join(dir), &image_dir.join(dir))
+                .await
+                .map_err(|e| BoaError::Failed(format!("failed to save snapshot: {e}")))?;
+        }
+
+        fs::write(image_dir.join(OWNER_FILE), owner)
+            .await
+            .map_err(|e| BoaError::Failed(format!("failed to save snapshot owner: {e}")))?;
+
+        Ok(())
+    }
+
+    pub async fn retag(&self, source: &str, repository: &str, tag: &str) -> Result<(), BoaError> {
+        let source_dir = self.image_dir(source)?;
+        let image_dir = self.image_dir(&format!("{repository}:{tag}"))?;
+
+        if fs::metadata(&image_dir).await.is_ok() {
+            fs::remove_dir_all(&image_dir)
+                .await
+                .map_err(|e| BoaError::Failed(format!("failed to replace snapshot: {e}")))?;
+        }
+
+        fs::rename(&source_dir, &image_dir)
+            .await
+            .map_err(|e| BoaError::NotFound(format!("failed to retag {source}: {e}")))
+    }
+
+    pub async fn image_owner(&self, image: &str) -> Result<Option<String>, BoaError> {
+        let image_dir = self.image_dir(image)?;
+
+        if fs::metadata(&image_dir).await.is_err() {
+            return Err(BoaError::NotFound(format!("no such image {image}")));
+        }
+
+        match fs::read_to_string(image_dir.join(OWNER_FILE)).await {
+            Ok(owner) => Ok(Some(owner)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(BoaError::Failed(format!(
+                "failed to read the owner of {image}: {e}"
+            ))),
+        }
+    }
+
+    pub async fn image_size(&self, image: &str) -> Result<u64, BoaError> {
+        if !self.is_snapshot(image) {
+            // runtimes run from the host, there is nothing to pull
+            return Ok(0);
+        }
+
+        dir_size(&self.image_dir(image)?)
+            .await
+            .map_err(|e| BoaError::NotFound(format!("no such image {image}: {e}")))
+    }
+
+    pub async fn image_tags(&self, repository: &str) -> Result<Vec<String>, BoaError> {
+        let prefix = format!("{repository}:");
+        let mut tags = Vec::new();
+
+        let mut entries = fs::read_dir(&self.images_dir)
+            .await
+            .map_err(|e| BoaError::Failed(format!("failed to list snapshots: {e}")))?;
+
+        while let Some(entry) = entries
+            .next_entry()
+            .await
+            .map_err(|e| BoaError::Failed(format!("failed to list snapshots: {e}")))?
+        {
+            let image = entry.file_name().to_string_lossy().into_owned();
+
+            if image.starts_with(&prefix) {
+                tags.push(image);
+            }
+        }
+
+        Ok(tags)
+    }
+
+    pub async fn remove_image(&self, image: &str) -> Result<(), BoaError> {
+        fs::remove_dir_all(self.image_dir(image)?)
+            .await
+            .map_err(|e| BoaError::NotFound(format!("failed to remove image {image}: {e}")))
+    }
+}
+
+/// Directory of a workspace that holds `path` and the path relative to it, only the working
+/// directory (`src`) and `/tmp` (`tmp`) are kept
+fn workspace_path<'a>(
+    workspace: &Path,
+    working_dir: &str,
+    path: &'a str,
+) -> Result<(PathBuf, &'a Path), BoaError> {
+    let path = Path::new(path);
+
+    if path
+        .components()
+        .any(|component| matches!(component, Component::ParentDir))
+    {
+        return Err(BoaError::NotFound(format!(
+            "invalid path {}",
+            path.display()
+        )));
+    }
+
+    let (dir, relative) = if let Ok(relative) = path.strip_prefix(working_dir) {
+        ("src", relative)
+    } else if let Ok(relative) = path.strip_prefix("/tmp") {
+        ("tmp", relative)
+    } else {
+        return Err(BoaError::NotFound(format!(
+            "only files in {working_dir} and /tmp can be accessed, not {}",
+            path.display()
+        )));
+    };
+
+    Ok((workspace.join(dir), relative))
+}
+
+/// Opens the directory `relative` under `root`, creating the missing ones when `create` is set.
+/// Code in the workspace runs as the server's user and can replace any of them with a symlink
+/// to a file of the host, so none of them is followed.
+fn open_dir(root: &Path, relative: &Path, create: bool) -> io::Result<OwnedFd> {
+    let mut dir = rfs::open(
+        root,
+        OFlags::RDONLY | OFlags::DIRECTORY | OFlags::CLOEXEC,
+        Mode::empty(),
+    )?;
+
+    for component in relative.components() {
+        let Component::Normal(name) = component else {
+            continue;
+        };
+        let flags = OFlags::RDONLY | OFlags::DIRECTORY | OFlags::NOFOLLOW | OFlags::CLOEXEC;
+
+        dir = match rfs::openat(&dir, name, flags, Mode::empty()) {
+            Err(Errno::NOENT) if create => {
+                match rfs::mkdirat(&dir, name, Mode::from_raw_mode(0o755)) {
+                    Ok(()) | Err(Errno::EXIST) => {}
+                    Err(e) => return Err(e.into()),
+                }
+                rfs::openat(&dir, name, flags, Mode::empty())?
+            }
+            result => result?,
+        };
+    }
+
+    Ok(dir)
+}
+
+/// Opens the regular file `relative` under `root` with `flags`, without following any symlink on
+/// the way. Fifos and devices are opened non-blocking and refused.
+fn open_file(root: &Path, relative: &Path, flags: OFlags, create: bool) -> io::Result<File> {
+    let Some(name) = relative.file_name() else {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "not a file"));
+    };
+    let dir = open_dir(root, relative.parent().unwrap_or(Path::new("")), create)?;
+
+    let file = rfs::openat(
+        &dir,
+        name,
+        flags | OFlags::NOFOLLOW | OFlags::NONBLOCK | OFlags::CLOEXEC,
+        Mode::from_raw_mode(0o644),
+    )?;
+    let file = File::from(file);
+
+    if !file.metadata()?.is_file() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "not a regular file",
+        ));
+    }
+
+    Ok(file)
+}
+
+/// Writes `contents` to `file_name` in the directory `dir` of a workspace
+pub async fn upload(
+    workspace: &Path,
+    working_dir: &str,
+    dir: &str,
+    file_name: &str,
+    contents: Vec<u8>,
+) -> Result<(), BoaError> {
+    let path = format!("{}/{file_name}", dir.trim_end_matches('/'));
+    let (root, relative) = workspace_path(workspace, working_dir, &path)?;
+    let relative = relative.to_path_buf();
+
+    task::spawn_blocking(move || {
+        let flags = OFlags::WRONLY | OFlags::CREATE | OFlags::TRUNC;
+        open_file(&root, &relative, flags, true)?.write_all(&contents)
+    })
+    .await
+    .map_err(|e| BoaError::Failed(format!("failed to upload {file_name}: {e}")))?
+    .map_err(|e| {
+        // a symlink or something else than a directory or file on the way
+        let refused = e.kind() == io::ErrorKind::InvalidInput
+            || matches!(
+                e.raw_os_error().map(Errno::from_raw_os_error),
+                Some(Errno::LOOP | Errno::NOTDIR)
+            );
+
+        if refused {
+            BoaError::Refused(
+                ServerError::UploadFailed,
+                format!("failed to upload {file_name}: {path} is not a regular file"),
+            )
+        } else {
+            BoaError::Failed(format!("failed to upload {file_name}: {e}"))
+        }
+    })
+}
+
+/// Reads the file at `path` in a workspace
+pub async fn download(
+    workspace: &Path,
+    working_dir: &str,
+    path: &str,
+) -> Result<Vec<u8>, BoaError> {
+    let (root, relative) = workspace_path(workspace, working_dir, path)?;
+    let relative = relative.to_path_buf();
+
+    task::spawn_blocking(move || {
+        let mut contents = Vec::new();
+        open_file(&root, &relative, OFlags::RDONLY, false)?.read_to_end(&mut contents)?;
+        Ok::<_, io::Error>(contents)
+    })
+    .await
+    .map_err(|e| BoaError::Failed(format!("failed to read {path}: {e}")))?
+    .map_err(|e| BoaError::NotFound(format!("failed to read {path}: {e}")))
+}
+
+/// Total size of the files under `dir`
+async fn dir_size(dir: &Path) -> io::Result<u64> {
+    let mut size = 0;
+    let mut dirs = vec![dir.to_path_buf()];
+
+    while let Some(dir) = dirs.pop() {
+        let mut entries = fs::read_dir(&dir).await?;
+
+        while let Some(entry) = entries.next_entry().await? {
+            let metadata = entry.metadata().await?;
+
+            if metadata.is_dir() {
+                dirs.push(entry.path());
+            } else {
+                size += metadata.len();
+            }
+        }
+    }
+
+    Ok(size)
+}
+
+/// Copies the files under `from` to `to`, which must not exist yet
+async fn copy_dir(from: &Path, to: &Path) -> io::Result<()> {
+    let mut dirs = vec![(from.to_path_buf(), to.to_path_buf())];
+
+    while let Some((from, to)) = dirs.pop() {
+        fs::create_dir_all(&to).await?;
+        let mut entries = fs::read_dir(&from).await?;
+
+        while let Some(entry) = entries.next_entry().await? {
+            let file_type = entry.file_type().await?;
+            let target = to.join(entry.file_name());
+
+            if file_type.is_dir() {
+                dirs.push((entry.path(), target));
+            } else if file_type.is_symlink() {
+                // skipped, the host would follow them when restoring or reading the copy
+            } else {
+                fs::copy(entry.path(), target).await?;
+            }
+        }
+    }
+
+    Ok(())
+}
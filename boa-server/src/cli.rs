@@ -0,0 +1,353 @@
+use std::{
+    collections::HashMap,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use bollard::{
+    Docker,
+    query_parameters::{
+        CreateImageOptions, InspectContainerOptions, ListContainersOptions, RemoveContainerOptions,
+    },
+    secret::ContainerSummary,
+};
+use clap::Subcommand;
+use futures_util::StreamExt;
+use owo_colors::OwoColorize;
+use tokio::{net::TcpStream, time::timeout};
+use tracing::{error, info};
+
+use crate::{
+    auth::{self, Authenticator},
+    config::Config,
+    tls::TlsCertificate,
+};
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run the server, the default when no command is given
+    Serve,
+
+    /// List the containers created with the configured prefix
+    List,
+
+    /// Remove containers left behind by a server that is no longer running
+    Gc {
+        /// Remove the containers even if a server is listening on the configured address
+        #[arg(long)]
+        force: bool,
+
+        /// Only print the containers that would be removed
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Print the docker inspect output of a container
+    Inspect {
+        /// Container name or id, the prefix may be left out
+        id: String,
+    },
+
+    /// Config file commands
+    #[command(subcommand)]
+    Config(ConfigCommand),
+
+    /// Runtime image commands
+    #[command(subcommand)]
+    Images(ImagesCommand),
+
+    /// Print a bearer token for `/ws` signed with `auth.hmac_secret`
+    Token {
+        /// Identity the token authenticates as
+        identity: String,
+
+        /// Seconds until the token expires
+        #[arg(long, default_value_t = 3600)]
+        ttl_secs: u64,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ConfigCommand {
+    /// Validate the config and print the effective settings
+    Check,
+}
+
+#[derive(Subcommand)]
+pub enum ImagesCommand {
+    /// Pull the images of every configured runtime
+    Pull,
+}
+
+/// Containers whose name starts with `<prefix>-`
+async fn prefixed_containers(
+    docker: &Docker,
+    container_prefix: &str,
+) -> Result<Vec<ContainerSummary>, String> {
+    let containers = docker
+        .list_containers(Some(ListContainersOptions {
+            all: true,
+            filters: Some(HashMap::from([(
+                "name".to_string(),
+                vec![format!("^/{container_prefix}-")],
+            )])),
+            ..Default::default()
+        }))
+        .await
+        .map_err(|e| format!("failed to list containers: {e}"))?;
+
+    Ok(containers
+        .into_iter()
+        .filter(|container| container_name(container).starts_with(&format!("{container_prefix}-")))
+        .collect())
+}
+
+fn container_name(container: &ContainerSummary) -> &str {
+    container
+        .names
+        .as_ref()
+        .and_then(|names| names.first())
+        .map_or("", |name| name.trim_start_matches('/'))
+}
+
+pub async fn list(docker: &Docker, config: &Config) -> Result<(), String> {
+    let containers = prefixed_containers(docker, &config.containers.prefix).await?;
+
+    if containers.is_empty() {
+        println!("no containers with the prefix {}", config.containers.prefix);
+        return Ok(());
+    }
+
+    println!(
+        "{}",
+        format!("{:<50} {:<10} {:<24} IMAGE", "NAME", "STATE", "STATUS").bold()
+    );
+
+    for container in &containers {
+        println!(
+            "{:<50} {:<10} {:<24} {}",
+            container_name(container),
+            container
+                .state
+                .map(|state| state.to_string())
+                .unwrap_or_default(),
+            container.status.as_deref().unwrap_or_default(),
+            container.image.as_deref().unwrap_or_default(),
+        );
+    }
+
+    Ok(())
+}
+
+/// Whether something accepts connections on `bind`, used to guess if a server is running
+async fn is_listening(bind: SocketAddr) -> bool {
+    let mut addr = bind;
+
+    if addr.ip().is_unspecified() {
+        addr.set_ip(if addr.is_ipv4() {
+            Ipv4Addr::LOCALHOST.into()
+        } else {
+            Ipv6Addr::LOCALHOST.into()
+        });
+    }
+
+    matches!(
+        timeout(Duration::from_secs(1), TcpStream::connect(addr)).await,
+        Ok(Ok(_))
+    )
+}
+
+pub async fn gc(
+    docker: &Docker,
+    config: &Config,
+    force: bool,
+    dry_run: bool,
+) -> Result<(), String> {
+    if !force && is_listening(config.server.bind).await {
+        return Err(format!(
+            "a server is listening on {}, its containers are not orphans, pass --force to remove them anyway",
+            config.server.bind
+        ));
+    }
+
+    let containers = prefixed_containers(docker, &config.containers.prefix).await?;
+
+    if containers.is_empty() {
+        info!("no orphaned containers");
+        return Ok(());
+    }
+
+    let mut failed = 0;
+
+    for container in &containers {
+        let name = container_name(container);
+
+        if dry_run {
+            info!(container_id = name, "would remove container");
+            continue;
+        }
+
+        match docker
+            .remove_container(
+                name,
+                Some(RemoveContainerOptions {
+                    force: true,
+                    ..Default::default()
+                }),
+            )
+            .await
+        {
+            Ok(()) => info!(container_id = name, "removed container"),
+            Err(e) => {
+                error!(container_id = name, "failed to remove container: {e}");
+                failed += 1;
+            }
+        }
+    }
+
+    if failed > 0 {
+        return Err(format!("failed to remove {failed} container(s)"));
+    }
+
+    Ok(())
+}
+
+pub async fn inspect(docker: &Docker, config: &Config, id: &str) -> Result<(), String> {
+    let prefix = format!("{}-", config.containers.prefix);
+    let name = if id.starts_with(&prefix) {
+        id.to_string()
+    } else {
+        format!("{prefix}{id}")
+    };
+
+    let inspect = match docker
+        .inspect_container(&name, None::<InspectContainerOptions>)
+        .await
+    {
+        Ok(inspect) => inspect,
+        // fall back to a container id, as long as it still has the prefix
+        Err(_) => docker
+            .inspect_container(id, None::<InspectContainerOptions>)
+            .await
+            .map_err(|e| format!("failed to inspect container {id}: {e}"))?,
+    };
+
+    let is_prefixed = inspect
+        .name
+        .as_deref()
+        .is_some_and(|name| name.trim_start_matches('/').starts_with(&prefix));
+
+    if !is_prefixed {
+        return Err(format!(
+            "container {id} was not created with the prefix {}",
+            config.containers.prefix
+        ));
+    }
+
+    let json = serde_json::to_string_pretty(&inspect)
+        .map_err(|e| format!("failed to serialize inspect output: {e}"))?;
+
+    println!("{json}");
+
+    Ok(())
+}
+
+pub fn config_check(config: &Config) -> Result<(), String> {
+    // keys and certificates are only read when the authenticator and listener are built
+    Authenticator::new(&config.auth)?;
+    if let Some(tls) = &config.server.tls {
+        TlsCertificate::load(tls)?;
+    }
+
+    let redacted = || "<redacted>".to_string();
+
+    let mut config = config.clone();
+    if config.server.admin_token.is_some() {
+        config.server.admin_token = Some(redacted());
+    }
+    for key in config.auth.api_keys.values_mut() {
+        *key = redacted();
+    }
+    if config.auth.hmac_secret.is_some() {
+        config.auth.hmac_secret = Some(redacted());
+    }
+    if let Some(jwt) = &mut config.auth.jwt
+        && jwt.secret.is_some()
+    {
+        jwt.secret = Some(redacted());
+    }
+
+    let toml =
+        toml::to_string_pretty(&config).map_err(|e| format!("failed to serialize config: {e}"))?;
+
+    info!("config is valid");
+    println!("{toml}");
+
+    Ok(())
+}
+
+pub async fn images_pull(docker: &Docker, config: &Config) -> Result<(), String> {
+    let mut failed = 0;
+
+    for image in config.runtime_images() {
+        info!(image, "pulling image...");
+
+        // docker pulls every tag of the repository when the tag is left empty
+        let (repository, tag) = match image.rsplit_once(':') {
+            Some((repository, tag)) if !tag.contains('/') => (repository, tag),
+            _ => (image, "latest"),
+        };
+
+        let mut stream = docker.create_image(
+            Some(CreateImageOptions {
+                from_image: Some(repository.to_string()),
+                tag: Some(tag.to_string()),
+                ..Default::default()
+            }),
+            None,
+            None,
+        );
+
+        let mut result = Ok(());
+
+        while let Some(info) = stream.next().await {
+            match info {
+                Ok(info) => {
+                    if let Some(status) = info.status
+                        && info.id.is_none()
+                    {
+                        info!(image, "{status}");
+                    }
+                }
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+
+        match result {
+            Ok(()) => info!(image, "pulled image"),
+            Err(e) => {
+                error!(image, "failed to pull image: {e}");
+                failed += 1;
+            }
+        }
+    }
+
+    if failed > 0 {
+        return Err(format!("failed to pull {failed} image(s)"));
+    }
+
+    Ok(())
+}
+
+pub fn token(config: &Config, identity: &str, ttl_secs: u64) -> Result<(), String> {
+    let Some(secret) = &config.auth.hmac_secret else {
+        return Err("auth.hmac_secret is not set".to_string());
+    };
+
+    println!("{}", auth::sign_hmac_token(secret, identity, ttl_secs)?);
+
+    Ok(())
+}
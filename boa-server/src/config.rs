@@ -0,0 +1,959 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
+
+use clap::{Args, ValueEnum};
+use serde::{Deserialize, Serialize};
+
+/// Loaded when no config file is passed and it exists in the working directory
+pub const DEFAULT_CONFIG_PATH: &str = "boa.toml";
+
+/// Shortest api key or hmac secret that is accepted
+const MIN_SECRET_LEN: usize = 16;
+
+/// `docker.host` that connects to the rootless Podman socket of the user running the server
+pub const PODMAN_HOST: &str = "podman";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub backend: BackendKind,
+    pub docker: DockerConfig,
+    pub sandbox: SandboxConfig,
+    pub wasi: WasiConfig,
+    pub containers: ContainersConfig,
+    pub runtimes: BTreeMap<String, RuntimeConfig>,
+    pub limits: LimitsConfig,
+    pub network: NetworkConfig,
+    pub audit: AuditConfig,
+    pub auth: AuthConfig,
+    pub quotas: QuotasConfig,
+    pub connections: ConnectionsConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            server: ServerConfig::default(),
+            backend: BackendKind::default(),
+            docker: DockerConfig::default(),
+            sandbox: SandboxConfig::default(),
+            wasi: WasiConfig::default(),
+            containers: ContainersConfig::default(),
+            runtimes: BTreeMap::from([(
+                "python".to_string(),
+                RuntimeConfig {
+                    image: "python:3.11-slim".to_string(),
+                    security: SecurityConfig::default(),
+                },
+            )]),
+            limits: LimitsConfig::default(),
+            network: NetworkConfig::default(),
+            audit: AuditConfig::default(),
+            auth: AuthConfig::default(),
+            quotas: QuotasConfig::default(),
+            connections: ConnectionsConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub bind: SocketAddr,
+    pub admin_token: Option<String>,
+    pub drain_timeout_secs: u64,
+    /// Serves HTTPS and WSS instead of plain HTTP when set
+    pub tls: Option<TlsConfig>,
+    /// Origins browsers may open `/ws` from, e.g. `https://boa.example.com`. Any origin is
+    /// allowed when empty, and upgrades without an `Origin` header are never rejected.
+    pub allowed_origins: Vec<String>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind: SocketAddr::from(([0, 0, 0, 0], 4040)),
+            admin_token: None,
+            drain_timeout_secs: 30,
+            tls: None,
+            allowed_origins: Vec::new(),
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn is_origin_allowed(&self, origin: &str) -> bool {
+        self.allowed_origins.is_empty()
+            || self
+                .allowed_origins
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(origin))
+    }
+}
+
+/// PEM certificate chain and private key, reloaded when either file changes
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+}
+
+/// Where containers are created and code runs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendKind {
+    /// Docker or Podman containers
+    #[default]
+    Docker,
+    /// Local processes in Linux namespaces, for machines without Docker
+    Sandbox,
+    /// WASI modules in an embedded WebAssembly runtime, needs the `wasi` feature
+    Wasi,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DockerConfig {
+    /// `unix:///path/to/docker.sock` or just the socket path, `tcp://host:port`, or `podman` for
+    /// the rootless Podman socket. The local defaults are used when unset.
+    pub host: Option<String>,
+    /// Client certificate for a `tcp://` host that requires TLS, like `docker --tlsverify`
+    pub tls: Option<DockerTlsConfig>,
+}
+
+/// PEM files of a daemon's TLS client auth, as found in a `DOCKER_CERT_PATH` directory
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[allow(clippy::struct_field_names)]
+pub struct DockerTlsConfig {
+    /// CA the daemon's certificate is checked against, `ca.pem`
+    pub ca_path: PathBuf,
+    /// `cert.pem`
+    pub cert_path: PathBuf,
+    /// `key.pem`
+    pub key_path: PathBuf,
+}
+
+/// Settings of the `sandbox` backend. Every runtime runs the host's interpreters, from `path`
+/// inside a read-only view of the host's `/usr`, `/etc` and `ro_binds`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SandboxConfig {
+    /// Directory the workspaces and snapshots of sandboxes are kept in
+    pub dir: PathBuf,
+    /// More host directories to mount read-only at the same path, e.g. a python install
+    pub ro_binds: Vec<PathBuf>,
+    /// `PATH` inside the sandbox
+    pub path: String,
+    /// Delegated cgroup v2 directory the server may create a cgroup for each sandbox in,
+    /// required for the cpu limit, pausing with the freezer and cpu quotas
+    pub cgroup: Option<PathBuf>,
+}
+
+impl Default for SandboxConfig {
+    fn default() -> Self {
+        Self {
+            dir: PathBuf::from("boa-sandbox"),
+            ro_binds: Vec::new(),
+            path: "/usr/local/bin:/usr/bin:/bin".to_string(),
+            cgroup: None,
+        }
+    }
+}
+
+/// Settings of the `wasi` backend. Uploaded `.wasm` modules run as they are, python files run
+/// with a WASI build of python.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WasiConfig {
+    /// Directory the workspaces and snapshots of containers are kept in
+    pub dir: PathBuf,
+    /// `python.wasm` module that runs python files, python is rejected when unset
+    pub python: Option<PathBuf>,
+    /// Prefix of the module's standard library, mounted read-only at `/usr/local`, e.g. a
+    /// directory with `lib/python3.12`
+    pub python_home: Option<PathBuf>,
+}
+
+impl Default for WasiConfig {
+    fn default() -> Self {
+        Self {
+            dir: PathBuf::from("boa-wasi"),
+            python: None,
+            python_home: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ContainersConfig {
+    pub prefix: String,
+    pub working_dir: String,
+    pub default_runtime: String,
+    pub pool_size: usize,
+    pub pip_cache: bool,
+    /// Defaults to `<prefix>-pip-cache`
+    pub pip_cache_volume: Option<String>,
+}
+
+impl Default for ContainersConfig {
+    fn default() -> Self {
+        Self {
+            prefix: "boa-runner".to_string(),
+            working_dir: "/src".to_string(),
+            default_runtime: "python".to_string(),
+            pool_size: 0,
+            pip_cache: true,
+            pip_cache_volume: None,
+        }
+    }
+}
+
+impl ContainersConfig {
+    pub fn pip_cache_volume(&self) -> Option<String> {
+        if !self.pip_cache {
+            return None;
+        }
+
+        Some(
+            self.pip_cache_volume
+                .clone()
+                .unwrap_or_else(|| format!("{}-pip-cache", self.prefix)),
+        )
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RuntimeConfig {
+    pub image: String,
+    #[serde(default)]
+    pub security: SecurityConfig,
+}
+
+/// How the docker containers of a runtime are locked down. Every capability is dropped, and
+/// by default code runs as an unprivileged user on a read-only image.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SecurityConfig {
+    /// Capabilities given back after dropping all of them, e.g. `CHOWN`
+    pub cap_add: Vec<String>,
+    /// Keeps processes from gaining privileges, e.g. through setuid binaries
+    pub no_new_privileges: bool,
+    /// Mounts the image read-only, leaving the tmpfs working directory and `/tmp` writable
+    pub read_only: bool,
+    /// Size of the tmpfs mounted at the working directory and at `/tmp` each, 0 keeps them
+    /// on the container's filesystem
+    pub tmpfs_mb: u64,
+    /// `uid:gid` or user name code runs as, the image's user when empty
+    pub user: String,
+    /// JSON seccomp profile, docker's default profile is used when unset
+    pub seccomp: Option<PathBuf>,
+    /// OCI runtime the containers run with, e.g. `runsc` for gVisor
+    pub runtime: Option<String>,
+}
+
+impl Default for SecurityConfig {
+    fn default() -> Self {
+        Self {
+            cap_add: Vec::new(),
+            no_new_privileges: true,
+            read_only: true,
+            tmpfs_mb: 256,
+            user: "1000:1000".to_string(),
+            seccomp: None,
+            runtime: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    pub memory_mb: u64,
+    pub cpus: f64,
+    pub pids: i64,
+    /// Execs running longer than this are killed, `0` disables the timeout
+    pub exec_timeout_secs: u64,
+    pub snapshot_max_count: usize,
+    pub snapshot_max_size_mb: u64,
+}
+
+impl LimitsConfig {
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn nano_cpus(&self) -> i64 {
+        // `cpus` is validated to be in 0..=1024, so this can't overflow
+        (self.cpus * 1e9).round() as i64
+    }
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            memory_mb: 1024,
+            cpus: 1.0,
+            pids: 512,
+            exec_timeout_secs: 300,
+            snapshot_max_count: 10,
+            snapshot_max_size_mb: 2048,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetworkConfig {
+    /// Docker network mode for containers, `none` disables networking
+    pub mode: String,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            mode: "bridge".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuditConfig {
+    /// sqlite database every exec is recorded in, the audit log is disabled when unset or empty
+    pub path: Option<PathBuf>,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            path: Some(PathBuf::from("boa-audit.db")),
+        }
+    }
+}
+
+/// Ways a client can authenticate on `/ws`, any number of them can be enabled at once.
+/// The endpoint is open to anyone when none is.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// Static keys, by the identity they authenticate as
+    pub api_keys: BTreeMap<String, String>,
+    /// Secret that tokens from `boa-server token` are signed with
+    pub hmac_secret: Option<String>,
+    pub jwt: Option<JwtConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JwtConfig {
+    /// `HS256`, `RS256`, `ES256`, `EdDSA`...
+    #[serde(default = "default_jwt_algorithm")]
+    pub algorithm: String,
+    /// Shared secret for the `HS*` algorithms
+    pub secret: Option<String>,
+    /// PEM public key for every other algorithm
+    pub public_key_path: Option<PathBuf>,
+    /// Required `iss` claim
+    pub issuer: Option<String>,
+    /// Required `aud` claim
+    pub audience: Option<String>,
+}
+
+fn default_jwt_algorithm() -> String {
+    "HS256".to_string()
+}
+
+/// Limits for each identity, the `default` ones apply to identities without their own
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QuotasConfig {
+    pub default: QuotaLimits,
+    pub identities: BTreeMap<String, QuotaLimits>,
+}
+
+impl QuotasConfig {
+    pub fn limits(&self, identity: &str) -> &QuotaLimits {
+        self.identities.get(identity).unwrap_or(&self.default)
+    }
+}
+
+/// Every limit is shared by all connections of an identity, 0 disables it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QuotaLimits {
+    /// Containers open at once
+    pub max_containers: u64,
+    /// Execs running at once
+    pub max_execs: u64,
+    /// Execs started within the last hour
+    pub execs_per_hour: u64,
+    /// CPU seconds used by execs within the last 24 hours
+    pub cpu_secs_per_day: u64,
+}
+
+impl QuotaLimits {
+    pub fn unlimited() -> QuotaLimits {
+        QuotaLimits {
+            max_containers: 0,
+            max_execs: 0,
+            execs_per_hour: 0,
+            cpu_secs_per_day: 0,
+        }
+    }
+}
+
+impl Default for QuotaLimits {
+    fn default() -> Self {
+        Self {
+            max_containers: 4,
+            max_execs: 4,
+            execs_per_hour: 0,
+            cpu_secs_per_day: 0,
+        }
+    }
+}
+
+/// Limits on WebSocket connections and what clients send over them, 0 disables a limit.
+/// A client that goes over one is sent an error and disconnected.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConnectionsConfig {
+    /// Open connections from one IP address
+    pub max_per_ip: usize,
+    /// Open connections of one authenticated identity
+    pub max_per_identity: usize,
+    /// Client packets per second, short bursts of up to a second's worth are allowed
+    pub packets_per_sec: u32,
+    pub max_text_frame_bytes: usize,
+    /// Size of a single uploaded file
+    pub max_upload_bytes: u64,
+}
+
+impl Default for ConnectionsConfig {
+    fn default() -> Self {
+        Self {
+            max_per_ip: 16,
+            max_per_identity: 16,
+            packets_per_sec: 20,
+            max_text_frame_bytes: 64 * 1024,
+            max_upload_bytes: 16 * 1024 * 1024,
+        }
+    }
+}
+
+/// Settings that can be overridden from the environment or the command line
+#[derive(Debug, Clone, Default, Args)]
+pub struct ConfigOverrides {
+    /// Path to the TOML config file
+    #[arg(long, global = true, env = "BOA_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Address to listen on
+    #[arg(long, global = true, env = "BOA_BIND_ADDRESS")]
+    pub bind: Option<SocketAddr>,
+
+    /// Port to listen on, replacing the port of the bind address
+    #[arg(long, global = true, env = "BOA_SERVER_PORT")]
+    pub port: Option<u16>,
+
+    /// Prefix for the names of created containers
+    #[arg(long, global = true, env = "BOA_CONTAINER_PREFIX")]
+    pub container_prefix: Option<String>,
+
+    /// Where containers run
+    #[arg(long, global = true, env = "BOA_BACKEND", value_enum)]
+    pub backend: Option<BackendKind>,
+
+    /// Docker endpoint, e.g. `unix:///var/run/docker.sock`, `tcp://host:2376` or `podman`
+    #[arg(long, global = true, env = "BOA_DOCKER_HOST")]
+    pub docker_host: Option<String>,
+
+    /// Number of started, idle containers to keep per runtime
+    #[arg(long, global = true, env = "BOA_POOL_SIZE")]
+    pub pool_size: Option<usize>,
+
+    /// Seconds to wait for running execs on shutdown
+    #[arg(long, global = true, env = "BOA_DRAIN_TIMEOUT_SECS")]
+    pub drain_timeout_secs: Option<u64>,
+
+    /// Bearer token for the admin api
+    #[arg(long, global = true, env = "BOA_ADMIN_TOKEN", hide_env_values = true)]
+    pub admin_token: Option<String>,
+
+    /// Volume for the shared pip cache, an empty name disables it
+    #[arg(long, global = true, env = "BOA_PIP_CACHE_VOLUME")]
+    pub pip_cache_volume: Option<String>,
+
+    /// Maximum number of snapshot images
+    #[arg(long, global = true, env = "BOA_SNAPSHOT_MAX_COUNT")]
+    pub snapshot_max_count: Option<usize>,
+
+    /// Maximum size of a snapshot image in megabytes
+    #[arg(long, global = true, env = "BOA_SNAPSHOT_MAX_SIZE_MB")]
+    pub snapshot_max_size_mb: Option<u64>,
+
+    /// sqlite database for the exec audit log, an empty path disables it
+    #[arg(long, global = true, env = "BOA_AUDIT_DB", value_parser = parse_path)]
+    pub audit_db: Option<PathBuf>,
+
+    /// Secret that `/ws` bearer tokens are signed with
+    #[arg(
+        long,
+        global = true,
+        env = "BOA_AUTH_HMAC_SECRET",
+        hide_env_values = true
+    )]
+    pub auth_hmac_secret: Option<String>,
+
+    /// PEM certificate chain to serve HTTPS and WSS with, requires `--tls-key`
+    #[arg(long, global = true, env = "BOA_TLS_CERT", requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM private key of the certificate, requires `--tls-cert`
+    #[arg(long, global = true, env = "BOA_TLS_KEY", requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+}
+
+/// Like clap's path parser, but an empty path is allowed to mean "disabled"
+#[allow(clippy::unnecessary_wraps)]
+fn parse_path(path: &str) -> Result<PathBuf, String> {
+    Ok(PathBuf::from(path))
+}
+
+impl Config {
+    /// Loads the config file, applies the overrides on top of it and validates the result
+    pub fn load(overrides: &ConfigOverrides) -> Result<Config, Vec<String>> {
+        let mut config = match &overrides.config {
+            Some(path) => Config::from_file(path).map_err(|e| vec![e])?,
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => {
+                Config::from_file(Path::new(DEFAULT_CONFIG_PATH)).map_err(|e| vec![e])?
+            }
+            None => Config::default(),
+        };
+
+        config.apply(overrides);
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Config, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("failed to read config file {}: {e}", path.display()))?;
+
+        toml::from_str(&content)
+            .map_err(|e| format!("failed to parse config file {}: {e}", path.display()))
+    }
+
+    fn apply(&mut self, overrides: &ConfigOverrides) {
+        if let Some(bind) = overrides.bind {
+            self.server.bind = bind;
+        }
+        if let Some(port) = overrides.port {
+            self.server.bind.set_port(port);
+        }
+        if let Some(prefix) = &overrides.container_prefix {
+            self.containers.prefix.clone_from(prefix);
+        }
+        if let Some(backend) = overrides.backend {
+            self.backend = backend;
+        }
+        if let Some(host) = &overrides.docker_host {
+            self.docker.host = Some(host.clone());
+        }
+        if let Some(pool_size) = overrides.pool_size {
+            self.containers.pool_size = pool_size;
+        }
+        if let Some(drain_timeout_secs) = overrides.drain_timeout_secs {
+            self.server.drain_timeout_secs = drain_timeout_secs;
+        }
+        if let Some(admin_token) = &overrides.admin_token {
+            self.server.admin_token = Some(admin_token.clone());
+        }
+        if let Some(volume) = &overrides.pip_cache_volume {
+            self.containers.pip_cache = !volume.is_empty();
+            self.containers.pip_cache_volume = Some(volume.clone()).filter(|v| !v.is_empty());
+        }
+        if let Some(max_count) = overrides.snapshot_max_count {
+            self.limits.snapshot_max_count = max_count;
+        }
+        if let Some(max_size_mb) = overrides.snapshot_max_size_mb {
+            self.limits.snapshot_max_size_mb = max_size_mb;
+        }
+        if let Some(path) = &overrides.audit_db {
+            self.audit.path = Some(path.clone());
+        }
+        if let Some(secret) = &overrides.auth_hmac_secret {
+            self.auth.hmac_secret = Some(secret.clone());
+        }
+        if let (Some(cert_path), Some(key_path)) = (&overrides.tls_cert, &overrides.tls_key) {
+            self.server.tls = Some(TlsConfig {
+                cert_path: cert_path.clone(),
+                key_path: key_path.clone(),
+            });
+        }
+    }
+
+    /// Returns every problem with the config, not just the first one
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors = Vec::new();
+
+        for origin in &self.server.allowed_origins {
+            if !is_origin(origin) {
+                errors.push(format!(
+                    "server.allowed_origins: {origin:?} must be an origin like https://example.com"
+                ));
+            }
+        }
+
+        if !is_docker_name(&self.containers.prefix) {
+            errors.push(format!(
+                "containers.prefix: {:?} must only contain letters, digits, '_', '.' and '-'",
+                self.containers.prefix
+            ));
+        }
+
+        if !self.containers.working_dir.starts_with('/') {
+            errors.push(format!(
+                "containers.working_dir: {:?} must be an absolute path",
+                self.containers.working_dir
+            ));
+        }
+
+        if self.runtimes.is_empty() {
+            errors.push("runtimes: at least one runtime must be configured".to_string());
+        } else if !self.runtimes.contains_key(&self.containers.default_runtime) {
+            errors.push(format!(
+                "containers.default_runtime: no runtime named {:?} is configured",
+                self.containers.default_runtime
+            ));
+        }
+
+        if let Some(volume) = &self.containers.pip_cache_volume
+            && !is_docker_name(volume)
+        {
+            errors.push(format!(
+                "containers.pip_cache_volume: {volume:?} is not a valid volume name"
+            ));
+        }
+
+        self.validate_runtimes(&mut errors);
+
+        if self.limits.memory_mb == 0 {
+            errors.push("limits.memory_mb: must be greater than 0".to_string());
+        }
+
+        if !self.limits.cpus.is_finite() || self.limits.cpus <= 0.0 || self.limits.cpus > 1024.0 {
+            errors.push("limits.cpus: must be between 0 and 1024".to_string());
+        }
+
+        if self.limits.pids <= 0 {
+            errors.push("limits.pids: must be greater than 0".to_string());
+        }
+
+        if self.network.mode.trim().is_empty() {
+            errors.push("network.mode: must not be empty".to_string());
+        }
+
+        self.validate_docker(&mut errors);
+
+        for path in &self.sandbox.ro_binds {
+            if !path.is_absolute() {
+                errors.push(format!(
+                    "sandbox.ro_binds: {} must be an absolute path",
+                    path.display()
+                ));
+            }
+        }
+
+        if self.wasi.python_home.is_some() && self.wasi.python.is_none() {
+            errors.push("wasi.python_home: is only used with wasi.python".to_string());
+        }
+
+        for (name, key) in &self.auth.api_keys {
+            if key.len() < MIN_SECRET_LEN {
+                errors.push(format!(
+                    "auth.api_keys.{name}: must be at least {MIN_SECRET_LEN} characters"
+                ));
+            }
+        }
+
+        if let Some(secret) = &self.auth.hmac_secret
+            && secret.len() < MIN_SECRET_LEN
+        {
+            errors.push(format!(
+                "auth.hmac_secret: must be at least {MIN_SECRET_LEN} characters"
+            ));
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    fn validate_runtimes(&self, errors: &mut Vec<String>) {
+        for (name, runtime) in &self.runtimes {
+            if runtime.image.trim().is_empty() {
+                errors.push(format!("runtimes.{name}.image: must not be empty"));
+            }
+
+            let security = &runtime.security;
+
+            if security.read_only && security.tmpfs_mb == 0 {
+                errors.push(format!(
+                    "runtimes.{name}.security.read_only: needs tmpfs_mb, or nothing is writable"
+                ));
+            }
+
+            for capability in &security.cap_add {
+                if capability.is_empty()
+                    || !capability
+                        .chars()
+                        .all(|c| c.is_ascii_uppercase() || c == '_')
+                {
+                    errors.push(format!(
+                        "runtimes.{name}.security.cap_add: {capability:?} is not a capability \
+                         name like CHOWN"
+                    ));
+                }
+            }
+
+            if security.runtime.as_deref().is_some_and(str::is_empty) {
+                errors.push(format!(
+                    "runtimes.{name}.security.runtime: must not be empty"
+                ));
+            }
+
+            // the pool and snapshots know containers by image, not by runtime
+            if let Some((other, _)) = self.runtimes.iter().find(|(other, other_runtime)| {
+                other.as_str() < name.as_str()
+                    && other_runtime.image == runtime.image
+                    && other_runtime.security != runtime.security
+            }) {
+                errors.push(format!(
+                    "runtimes.{name}.security: differs from runtimes.{other}.security, which \
+                     uses the same image"
+                ));
+            }
+        }
+    }
+
+    fn validate_docker(&self, errors: &mut Vec<String>) {
+        let Some(host) = &self.docker.host else {
+            if self.docker.tls.is_some() {
+                errors.push("docker.tls: requires a tcp:// or https:// docker.host".to_string());
+            }
+            return;
+        };
+
+        let is_tcp = host.starts_with("tcp://") || host.starts_with("https://");
+
+        if !(is_tcp
+            || host == PODMAN_HOST
+            || host.starts_with('/')
+            || host.starts_with("unix://")
+            || host.starts_with("http://"))
+        {
+            errors.push(format!(
+                "docker.host: {host:?} must be podman, a socket path, or start with unix://, \
+                 tcp://, http:// or https://"
+            ));
+        }
+
+        if self.docker.tls.is_some() && !is_tcp {
+            errors.push(format!(
+                "docker.tls: requires a tcp:// or https:// docker.host, not {host:?}"
+            ));
+        }
+
+        if self.docker.tls.is_none() && host.starts_with("https://") {
+            errors.push(format!(
+                "docker.host: {host:?} requires docker.tls with the client certificate"
+            ));
+        }
+    }
+
+    /// Image of the runtime named `runtime`, or of the default runtime
+    pub fn runtime_image(&self, runtime: Option<&str>) -> Option<&str> {
+        let runtime = runtime.unwrap_or(&self.containers.default_runtime);
+
+        self.runtimes
+            .get(runtime)
+            .map(|runtime| runtime.image.as_str())
+    }
+
+    /// Security profile of containers of the runtime `image`. Images of no runtime, like
+    /// snapshots that do not record the one they were committed from, get the default runtime's.
+    pub fn security(&self, image: &str) -> SecurityConfig {
+        self.runtimes
+            .values()
+            .find(|runtime| runtime.image == image)
+            .or_else(|| self.runtimes.get(&self.containers.default_runtime))
+            .map(|runtime| runtime.security.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn runtime_images(&self) -> Vec<&str> {
+        self.runtimes
+            .values()
+            .map(|runtime| runtime.image.as_str())
+            .collect()
+    }
+}
+
+fn is_docker_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.starts_with(|c: char| c.is_ascii_alphanumeric())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'))
+}
+
+/// `http(s)://host[:port]`, the form browsers send in the `Origin` header
+fn is_origin(origin: &str) -> bool {
+    origin
+        .strip_prefix("https://")
+        .or_else(|| origin.strip_prefix("http://"))
+        .is_some_and(|host| !host.is_empty() && !host.contains(['/', '?', '#']))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Errors of the default config changed by `configure`
+    fn errors(configure: impl FnOnce(&mut Config)) -> Vec<String> {
+        let mut config = Config::default();
+        configure(&mut config);
+        config.validate().err().unwrap_or_default()
+    }
+
+    /// Asserts that `configure` makes the config invalid because of `field`
+    fn assert_rejected(field: &str, configure: impl FnOnce(&mut Config)) {
+        let errors = errors(configure);
+        assert!(
+            errors
+                .iter()
+                .any(|error| error.starts_with(&format!("{field}:"))),
+            "expected an error for {field}, got {errors:?}"
+        );
+    }
+
+    fn docker_tls() -> DockerTlsConfig {
+        DockerTlsConfig {
+            ca_path: PathBuf::from("ca.pem"),
+            cert_path: PathBuf::from("cert.pem"),
+            key_path: PathBuf::from("key.pem"),
+        }
+    }
+
+    #[test]
+    fn default_config_is_valid() {
+        assert_eq!(errors(|_| {}), Vec::<String>::new());
+    }
+
+    #[test]
+    fn zero_memory_is_rejected() {
+        assert_rejected("limits.memory_mb", |config| config.limits.memory_mb = 0);
+    }
+
+    #[test]
+    fn cpus_out_of_range_are_rejected() {
+        for cpus in [0.0, -1.0, 2048.0, f64::NAN] {
+            assert_rejected("limits.cpus", |config| config.limits.cpus = cpus);
+        }
+    }
+
+    #[test]
+    fn zero_pids_are_rejected() {
+        assert_rejected("limits.pids", |config| config.limits.pids = 0);
+    }
+
+    #[test]
+    fn unknown_default_runtime_is_rejected() {
+        assert_rejected("containers.default_runtime", |config| {
+            config.containers.default_runtime = "ruby".to_string();
+        });
+    }
+
+    #[test]
+    fn missing_runtimes_are_rejected() {
+        assert_rejected("runtimes", |config| config.runtimes.clear());
+    }
+
+    #[test]
+    fn read_only_runtime_without_tmpfs_is_rejected() {
+        assert_rejected("runtimes.python.security.read_only", |config| {
+            if let Some(runtime) = config.runtimes.get_mut("python") {
+                runtime.security.tmpfs_mb = 0;
+            }
+        });
+    }
+
+    #[test]
+    fn short_api_key_is_rejected() {
+        assert_rejected("auth.api_keys.alice", |config| {
+            config
+                .auth
+                .api_keys
+                .insert("alice".to_string(), "short".to_string());
+        });
+    }
+
+    #[test]
+    fn short_hmac_secret_is_rejected() {
+        assert_rejected("auth.hmac_secret", |config| {
+            config.auth.hmac_secret = Some("short".to_string());
+        });
+    }
+
+    #[test]
+    fn docker_tls_without_a_tcp_host_is_rejected() {
+        assert_rejected("docker.tls", |config| {
+            config.docker.tls = Some(docker_tls());
+        });
+        assert_rejected("docker.tls", |config| {
+            config.docker.host = Some("unix:///var/run/docker.sock".to_string());
+            config.docker.tls = Some(docker_tls());
+        });
+    }
+
+    #[test]
+    fn https_docker_host_without_tls_is_rejected() {
+        assert_rejected("docker.host", |config| {
+            config.docker.host = Some("https://docker.example.com:2376".to_string());
+        });
+    }
+
+    #[test]
+    fn snapshot_containers_keep_the_runtime_profile() {
+        let mut config = Config::default();
+        config.runtimes.insert(
+            "node".to_string(),
+            RuntimeConfig {
+                image: "node:22-slim".to_string(),
+                security: SecurityConfig {
+                    tmpfs_mb: 64,
+                    ..SecurityConfig::default()
+                },
+            },
+        );
+
+        // the runtime a snapshot was committed from, or the default one when it is not recorded
+        let security = config.security("node:22-slim");
+        assert!(security.read_only);
+        assert_eq!(security.tmpfs_mb, 64);
+
+        let security = config.security("boa-snapshot:lab-3");
+        assert!(security.read_only);
+        assert_eq!(security.tmpfs_mb, SecurityConfig::default().tmpfs_mb);
+    }
+}
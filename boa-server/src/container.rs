@@ -1,305 +1,410 @@
-use std::{fs::File, path::Path};
-
-use axum::body::Bytes;
-
-use boa_core::packets::{
-    client::process::ProcessControlSignal,
-    server::{ServerPacket, process::ProcessOutputPacket},
+use std::{
+    collections::HashMap,
+    path::Path,
+    time::{Instant, SystemTime},
 };
-use futures_util::stream::StreamExt;
 
-use bollard::{
-    Docker, body_full,
-    exec::{CreateExecOptions, StartExecResults},
-    query_parameters::{
-        CreateContainerOptionsBuilder, InspectContainerOptions, StartContainerOptions,
-        StopContainerOptionsBuilder, UploadToContainerOptionsBuilder,
-    },
-    secret::{ContainerCreateBody, ContainerStateStatusEnum},
-};
+use boa_core::packets::server::{ServerPacket, error::ServerError, process::ProcessOutputPacket};
+use futures_util::stream::StreamExt;
 
-use owo_colors::{OwoColorize, Style};
+use sha2::{Digest, Sha256};
 use tokio::sync::mpsc::UnboundedSender;
+use tracing::{Span, debug, info, instrument, trace};
 use uuid::Uuid;
 
-use crate::{logger::Logger, routes::ws::WsOutbound};
+use crate::{
+    backend::{ContainerBackend, ExecEvent},
+    config::Config,
+    error::BoaError,
+    metrics::METRICS,
+    routes::ws::WsOutbound,
+};
+
+/// Where the shared pip cache volume is mounted inside containers
+pub const PIP_CACHE_DIR: &str = "/pip-cache";
+
+/// Exit code of `timeout` when the command ran out of time
+pub const TIMED_OUT_EXIT_CODE: i64 = 124;
 
 #[derive(Clone)]
 pub struct BoaContainer {
-    pub logger: Logger,
     pub container_id: String,
-    pub started: bool,
-    pub executing: bool,
+    pub image: String,
+    pub paused: bool,
+    pub created_at: SystemTime,
+    /// Connection the container was opened by, `None` while it waits in the pool
+    pub owner: Option<Uuid>,
+    /// Identity whose quota the container counts against, set with `owner`
+    pub owner_identity: Option<String>,
+    /// CPU time of the container that was already charged to `owner_identity`
+    pub cpu_charged_ns: u64,
+    /// Sha256 of the files uploaded into the container, by file name
+    pub file_hashes: HashMap<String, String>,
 }
 
-impl BoaContainer {
-    pub async fn new(
-        docker: &Docker,
-        container_prefix: String,
-    ) -> Result<(String, BoaContainer), String> {
-        let container_name = format!("{container_prefix}-{}", Uuid::new_v4());
+/// What an exec returned, and how much output it sent
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ExecOutput {
+    pub exit_code: i64,
+    pub stdout_bytes: u64,
+    pub stderr_bytes: u64,
+}
 
-        let logger = Logger::new(format!("[boa-server#.{container_name}]"));
+impl ExecOutput {
+    /// The exit code, or `BoaError::Timeout` when `timeout` stopped the exec after `timeout_secs`
+    pub fn result(&self, timeout_secs: u64) -> Result<i64, BoaError> {
+        if timeout_secs > 0 && self.exit_code == TIMED_OUT_EXIT_CODE {
+            Err(BoaError::Timeout(format!(
+                "exec timed out after {timeout_secs}s"
+            )))
+        } else {
+            Ok(self.exit_code)
+        }
+    }
+}
 
-        logger.log("creating new container...", "");
+/// The control signals that stop a container
+#[derive(Debug, Clone, Copy)]
+pub enum StopSignal {
+    Interrupt,
+    Terminate,
+}
 
-        let container_options = CreateContainerOptionsBuilder::new()
-            .name(&container_name)
-            .build();
+#[derive(Clone)]
+pub enum ExecKind {
+    File(String),
+    Requirements(String),
+}
 
-        let container_create = ContainerCreateBody {
-            image: Some("python:3.11-slim".to_string()),
-            tty: Some(true),
-            open_stdin: Some(true),
+impl ExecKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ExecKind::File(_) => "exec_file",
+            ExecKind::Requirements(_) => "install_requirements",
+        }
+    }
 
-            working_dir: Some("/src".to_string()),
+    pub fn path(&self) -> &str {
+        match self {
+            ExecKind::File(path) | ExecKind::Requirements(path) => path,
+        }
+    }
 
-            cmd: Some(vec![
-                "tail".to_string(),
-                "-f".to_string(),
-                "/dev/null".to_string(),
-            ]),
+    fn cmd(&self) -> Vec<String> {
+        match self {
+            ExecKind::File(file_path) => vec!["python".to_string(), file_path.clone()],
+            ExecKind::Requirements(requirements_path) => vec![
+                "pip".to_string(),
+                "install".to_string(),
+                "--progress-bar".to_string(),
+                "off".to_string(),
+                "-r".to_string(),
+                requirements_path.clone(),
+            ],
+        }
+    }
+}
 
-            attach_stdin: Some(true),
-            attach_stdout: Some(true),
-            attach_stderr: Some(true),
+/// Sends a chunk of exec output to the client and counts it in `output`
+fn forward_output(event: ExecEvent, sender: &UnboundedSender<WsOutbound>, output: &mut ExecOutput) {
+    let (stream, message, packet): (_, _, fn(String) -> ProcessOutputPacket) = match event {
+        ExecEvent::Stdout(message) => {
+            output.stdout_bytes += message.len() as u64;
+            ("stdout", message, ProcessOutputPacket::StdOut)
+        }
+        ExecEvent::Stderr(message) => {
+            output.stderr_bytes += message.len() as u64;
+            ("stderr", message, ProcessOutputPacket::StdErr)
+        }
+        ExecEvent::Exited(exit_code) => {
+            output.exit_code = exit_code;
+            return;
+        }
+    };
 
-            ..Default::default()
-        };
+    let text = String::from_utf8_lossy(&message).to_string();
+    trace!(stream, bytes = message.len(), output = %text, "exec output");
 
-        let container = docker
-            .create_container(Some(container_options), container_create)
-            .await
-            .map_err(|e| format!("failed to create new docker container {container_name}: {e}!"))?;
+    METRICS
+        .output_bytes
+        .with_label_values(&[stream])
+        .inc_by(message.len() as u64);
+
+    sender
+        .send(WsOutbound::Packet(ServerPacket::ProcessOutput(packet(
+            text,
+        ))))
+        .ok();
+}
+
+/// Removes the container named `container_id`, stopping anything running in it
+pub async fn remove_container(
+    backend: &dyn ContainerBackend,
+    container_id: &str,
+) -> Result<(), BoaError> {
+    backend.remove(container_id).await?;
+
+    METRICS.containers_removed.inc();
+
+    Ok(())
+}
+
+impl BoaContainer {
+    #[instrument(skip_all, fields(image = %image, container_id = tracing::field::Empty))]
+    pub async fn new(
+        backend: &dyn ContainerBackend,
+        config: &Config,
+        image: &str,
+    ) -> Result<(String, BoaContainer), BoaError> {
+        let container_name = format!("{}-{}", config.containers.prefix, Uuid::new_v4());
+
+        Span::current().record("container_id", container_name.as_str());
+        debug!("creating new container...");
 
-        logger.log("created container", "");
+        backend.create(&container_name, image, config).await?;
+
+        METRICS.containers_created.inc();
+        info!("created container");
 
         Ok((
             container_name.clone(),
             BoaContainer {
-                logger,
                 container_id: container_name,
-                started: false,
-                executing: false,
+                image: image.to_string(),
+                paused: false,
+                created_at: SystemTime::now(),
+                owner: None,
+                owner_identity: None,
+                cpu_charged_ns: 0,
+                file_hashes: HashMap::new(),
             },
         ))
     }
 }
 
 impl BoaContainer {
-    pub async fn upload_tar(&self, docker: &Docker, tar: Bytes) -> Result<(), String> {
-        docker
-            .upload_to_container(
-                &self.container_id,
-                Some(UploadToContainerOptionsBuilder::new().path("/src/").build()),
-                body_full(tar),
-            )
-            .await
-            .map_err(|e| format!("failed to upload tar to container: {e}"))
+    #[instrument(skip_all, fields(container_id = %self.container_id))]
+    pub async fn start(&mut self, backend: &dyn ContainerBackend) -> Result<(), BoaError> {
+        debug!("starting container...");
+        let timer = METRICS.start_duration.start_timer();
+
+        backend.start(&self.container_id).await?;
+
+        timer.observe_duration();
+
+        info!("started container");
+
+        Ok(())
     }
 
-    pub async fn start(&mut self, docker: &Docker) -> Result<(), String> {
-        self.logger.log("starting container...", "");
-        match docker
-            .start_container(&self.container_id, Some(StartContainerOptions::default()))
-            .await
-        {
-            Ok(_) => {
-                self.logger.log("started container", "");
-            }
-            Err(e) => return Err(format!("failed to start container: {e}")),
-        };
+    #[instrument(skip_all, fields(container_id = %self.container_id))]
+    pub async fn remove(&self, backend: &dyn ContainerBackend) -> Result<(), BoaError> {
+        remove_container(backend, &self.container_id).await?;
+
+        info!("removed container");
 
         Ok(())
     }
 
+    #[instrument(skip_all, fields(container_id = %self.container_id))]
+    pub async fn pause(&mut self, backend: &dyn ContainerBackend) -> Result<(), BoaError> {
+        debug!("pausing container...");
+
+        backend.pause(&self.container_id).await?;
+
+        self.paused = true;
+        info!("paused container");
+
+        Ok(())
+    }
+
+    #[instrument(skip_all, fields(container_id = %self.container_id))]
+    pub async fn resume(&mut self, backend: &dyn ContainerBackend) -> Result<(), BoaError> {
+        debug!("resuming container...");
+
+        backend.resume(&self.container_id).await?;
+
+        self.paused = false;
+        info!("resumed container");
+
+        Ok(())
+    }
+
+    #[instrument(skip_all, fields(container_id = %self.container_id))]
+    pub async fn commit(
+        &self,
+        backend: &dyn ContainerBackend,
+        repo: &str,
+        tag: &str,
+        owner: &str,
+    ) -> Result<(), BoaError> {
+        debug!(tag, "committing container...");
+
+        backend.commit(&self.container_id, repo, tag, owner).await?;
+
+        info!(tag, "committed container");
+
+        Ok(())
+    }
+
+    #[instrument(skip_all, fields(container_id = %self.container_id))]
     pub async fn signal(
         &mut self,
-        docker: &Docker,
-        signal: ProcessControlSignal,
-    ) -> Result<(), String> {
+        backend: &dyn ContainerBackend,
+        signal: StopSignal,
+    ) -> Result<(), BoaError> {
         let signal = match signal {
-            ProcessControlSignal::Interrupt => "SIGINT",
-            ProcessControlSignal::Terminate => "SIGTERM",
-            _ => unreachable!(),
+            StopSignal::Interrupt => "SIGINT",
+            StopSignal::Terminate => "SIGTERM",
         };
 
-        self.logger
-            .log(format!("stopping with signal {}...", signal.bold()), "");
+        debug!(signal, "stopping container...");
 
-        docker
-            .stop_container(
-                &self.container_id,
-                Some(StopContainerOptionsBuilder::new().signal(signal).build()),
-            )
-            .await
-            .map_err(|e| format!("failed to stop container: {e}"))?;
+        backend.signal(&self.container_id, signal).await?;
 
-        self.logger.log("container stopped", "");
+        info!(signal, "stopped container");
 
         Ok(())
     }
 }
 
 impl BoaContainer {
-    pub async fn exec_file(
-        &mut self,
-        docker: &Docker,
-        file_path: String,
+    /// Total CPU time used by the container since it started, in nanoseconds
+    #[instrument(skip_all, fields(container_id = %self.container_id))]
+    pub async fn cpu_usage_ns(&self, backend: &dyn ContainerBackend) -> Result<u64, BoaError> {
+        backend.cpu_usage_ns(&self.container_id).await
+    }
+}
+
+impl BoaContainer {
+    /// Sha256 of the file at `path` if it was uploaded through the server
+    pub fn file_hash(&self, path: &str, working_dir: &str) -> Option<String> {
+        let file_name = path
+            .strip_prefix(working_dir)
+            .map_or(path, |path| path.trim_start_matches('/'));
+
+        self.file_hashes
+            .get(file_name)
+            .or_else(|| self.file_hashes.get(path.trim_start_matches("./")))
+            .cloned()
+    }
+
+    /// Runs `kind` in the container, streaming its output to `sender`, and returns the exit code
+    #[instrument(skip_all, fields(container_id = %self.container_id, kind = kind.name()))]
+    pub async fn exec(
+        &self,
+        backend: &dyn ContainerBackend,
+        exec_id: Uuid,
+        kind: &ExecKind,
+        timeout_secs: u64,
         sender: UnboundedSender<WsOutbound>,
-    ) -> Result<i64, String> {
-        self.logger.log("creating exec_file command...", "");
+    ) -> Result<ExecOutput, BoaError> {
+        let name = kind.name();
+
+        if self.paused {
+            return Err(BoaError::Refused(
+                ServerError::Paused,
+                "container is paused".to_string(),
+            ));
+        }
 
-        let inspect = docker
-            .inspect_container(&self.container_id, None::<InspectContainerOptions>)
-            .await
-            .map_err(|e| format!("failed to inspect container: {e}"))?;
+        debug!("starting {name} command...");
 
-        let is_running = inspect
-            .state
-            .as_ref()
-            .and_then(|s| s.status.as_ref())
-            .map(|status| *status == ContainerStateStatusEnum::RUNNING)
-            .unwrap_or(false);
+        let mut events = backend
+            .exec(&self.container_id, exec_id, kind.cmd(), timeout_secs)
+            .await?;
 
-        if !is_running {
-            return Err("container is not started".to_string());
+        info!("running {name} command...");
+        let started_at = Instant::now();
+        let mut exec_output = ExecOutput {
+            exit_code: -1,
+            ..ExecOutput::default()
+        };
+
+        while let Some(event) = events.next().await {
+            forward_output(event?, &sender, &mut exec_output);
         }
 
-        let exec = docker
-            .create_exec(
-                &self.container_id,
-                CreateExecOptions {
-                    attach_stdout: Some(true),
-                    attach_stderr: Some(true),
-                    attach_stdin: Some(false),
-                    tty: Some(false),
-                    cmd: Some(vec!["python".to_string(), file_path]),
-                    ..Default::default()
-                },
-            )
-            .await
-            .map_err(|e| format!("failed to create exec: {e}"))?;
+        let exit_code = exec_output.exit_code;
 
-        self.logger.log("created exec_file command", "");
+        METRICS
+            .exec_duration
+            .with_label_values(&[name])
+            .observe(started_at.elapsed().as_secs_f64());
+        METRICS
+            .exec_exit_codes
+            .with_label_values(&[&exit_code.to_string()])
+            .inc();
 
-        self.executing = true;
+        info!(exit_code, "{name} command finished");
 
-        let output = docker
-            .start_exec(&exec.id, None)
-            .await
-            .map_err(|e| format!("failed to start exec: {e}"))?;
-
-        self.logger.log("running exec_file command...", "");
-
-        match output {
-            StartExecResults::Attached { mut output, .. } => {
-                while let Some(msg) = output.next().await {
-                    match msg {
-                        Ok(bollard::container::LogOutput::StdOut { message }) => {
-                            let text = String::from_utf8_lossy(&message);
-                            self.logger.log(format!("stdout: {text}"), "");
-
-                            sender
-                                .send(WsOutbound::Packet(ServerPacket::ProcessOutput(
-                                    ProcessOutputPacket::StdOut(text.to_string()),
-                                )))
-                                .ok();
-                        }
-                        Ok(bollard::container::LogOutput::StdErr { message }) => {
-                            let text = String::from_utf8_lossy(&message);
-                            self.logger.log(format!("stderr: {text}"), "");
-
-                            sender
-                                .send(WsOutbound::Packet(ServerPacket::ProcessOutput(
-                                    ProcessOutputPacket::StdErr(text.to_string()),
-                                )))
-                                .ok();
-                        }
-                        _ => {}
-                    }
-                }
-            }
-            StartExecResults::Detached => {
-                self.logger.log_style(
-                    "why is this running in detached mode?",
-                    Style::new().bright_red(),
-                    "",
-                );
-            }
-        }
+        Ok(exec_output)
+    }
 
-        let inspect = docker
-            .inspect_exec(&exec.id)
-            .await
-            .map_err(|e| format!("failed to inspect exec: {e}"))?;
+    /// Kills the process group of the exec started with `exec_id`
+    #[instrument(skip_all, fields(container_id = %self.container_id, exec_id = %exec_id))]
+    pub async fn kill_exec(
+        &self,
+        backend: &dyn ContainerBackend,
+        exec_id: Uuid,
+    ) -> Result<(), BoaError> {
+        debug!("killing exec...");
 
-        let exit_code = inspect.exit_code.unwrap_or(-1);
+        backend.kill_exec(&self.container_id, exec_id).await?;
 
-        self.logger.log(
-            format!(
-                "run of exec_file command finished, got exit_code={}",
-                exit_code.bold()
-            ),
-            "",
-        );
+        info!("killed exec");
 
-        Ok(exit_code)
+        Ok(())
     }
 }
 
 impl BoaContainer {
+    #[instrument(skip_all, fields(container_id = %self.container_id))]
+    /// Uploads the file at `host_path` into `container_path`, returning its sha256
     pub async fn upload_file(
         &self,
-        docker: &Docker,
+        backend: &dyn ContainerBackend,
         host_path: &Path,
         container_path: &str,
         file_name: &str,
-    ) -> Result<(), String> {
-        self.logger.log(
-            format!(
-                "started upload of file {} from {:?} to {:?}",
-                file_name.bold(),
-                host_path.bold(),
-                container_path.bold()
-            ),
-            "",
+    ) -> Result<String, BoaError> {
+        debug!(
+            file_name,
+            host_path = %host_path.display(),
+            container_path,
+            "uploading file..."
         );
 
-        let mut tar_data = Vec::new();
-        {
-            let mut file =
-                File::open(host_path).map_err(|e| format!("open host file failed: {e}"))?;
-            let mut builder = tar::Builder::new(&mut tar_data);
-            builder
-                .append_file(file_name, &mut file)
-                .map_err(|e| format!("tar append failed: {e}"))?;
-            builder.finish().map_err(|e| e.to_string())?;
-        }
-
-        docker
-            .upload_to_container(
-                &self.container_id,
-                Some(
-                    UploadToContainerOptionsBuilder::new()
-                        .path(container_path)
-                        .build(),
-                ),
-                body_full(tar_data.into()),
-            )
+        let contents = tokio::fs::read(host_path)
             .await
-            .map_err(|e| format!("docker upload failed: {e}"))?;
-
-        self.logger.log(
-            format!(
-                "upload of file {} from {:?} to {:?} finished",
-                file_name.bold(),
-                host_path.bold(),
-                container_path.bold()
-            ),
-            "",
-        );
+            .map_err(|e| BoaError::Failed(format!("read host file failed: {e}")))?;
+        let sha256 = format!("{:x}", Sha256::digest(&contents));
 
-        Ok(())
+        backend
+            .upload(&self.container_id, container_path, file_name, contents)
+            .await?;
+
+        info!(file_name, container_path, sha256, "uploaded file");
+
+        Ok(sha256)
+    }
+
+    /// Reads the file at `path` from the container, relative paths are in `working_dir`
+    #[instrument(skip_all, fields(container_id = %self.container_id))]
+    pub async fn download_file(
+        &self,
+        backend: &dyn ContainerBackend,
+        path: &str,
+        working_dir: &str,
+    ) -> Result<Vec<u8>, BoaError> {
+        let path = if path.starts_with('/') {
+            path.to_string()
+        } else {
+            format!("{}/{path}", working_dir.trim_end_matches('/'))
+        };
+
+        debug!(path, "downloading file...");
+
+        backend.download(&self.container_id, &path).await
     }
 }
@@ -0,0 +1,92 @@
+use std::fmt;
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use boa_core::packets::server::error::ServerError;
+
+/// Why a request failed. Every error is reported to the client as the `ServerError` it maps to.
+#[derive(Debug, Clone)]
+pub enum BoaError {
+    /// The backend could not be reached, e.g. the docker daemon is down
+    BackendUnavailable(String),
+    /// The container is not running yet
+    NotStarted(String),
+    /// The container, file, exec or image does not exist
+    NotFound(String),
+    /// The backend did not answer in time
+    Timeout(String),
+    /// A request the server refused, reported as the given error
+    Refused(ServerError, String),
+    /// Anything else, reported as the error of the request it failed, see [`BoaError::or`]
+    Failed(String),
+}
+
+impl BoaError {
+    /// Reports a `Failed` error as `err`, the more specific kinds keep their own
+    #[must_use]
+    pub fn or(self, err: ServerError) -> BoaError {
+        match self {
+            BoaError::Failed(message) => BoaError::Refused(err, message),
+            other => other,
+        }
+    }
+
+    pub fn server_error(&self) -> ServerError {
+        match self {
+            BoaError::BackendUnavailable(_) => ServerError::BackendUnavailable,
+            BoaError::NotStarted(_) => ServerError::NotStarted,
+            BoaError::NotFound(_) => ServerError::NotFound,
+            BoaError::Timeout(_) => ServerError::Timeout,
+            BoaError::Refused(err, _) => err.clone(),
+            BoaError::Failed(_) => ServerError::Internal,
+        }
+    }
+
+    /// Broken connection limits close the connection after the error is sent
+    pub fn closes_connection(&self) -> bool {
+        matches!(
+            self,
+            BoaError::Refused(
+                ServerError::MessageTooLarge
+                    | ServerError::RateLimited
+                    | ServerError::TooManyConnections,
+                _
+            )
+        )
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            BoaError::BackendUnavailable(message)
+            | BoaError::NotStarted(message)
+            | BoaError::NotFound(message)
+            | BoaError::Timeout(message)
+            | BoaError::Refused(_, message)
+            | BoaError::Failed(message) => message,
+        }
+    }
+}
+
+impl fmt::Display for BoaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+/// For the admin api
+impl IntoResponse for BoaError {
+    fn into_response(self) -> Response {
+        let status = match self {
+            BoaError::BackendUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            BoaError::NotStarted(_) => StatusCode::CONFLICT,
+            BoaError::NotFound(_) => StatusCode::NOT_FOUND,
+            BoaError::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            BoaError::Refused(..) => StatusCode::BAD_REQUEST,
+            BoaError::Failed(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+
+        (status, self.to_string()).into_response()
+    }
+}
@@ -0,0 +1,136 @@
+use std::{net::SocketAddr, time::Instant};
+
+use boa_core::packets::server::error::ServerError;
+
+use crate::{
+    auth::{AuthMethod, Identity},
+    config::ConnectionsConfig,
+    error::BoaError,
+    state::ServerState,
+};
+
+/// Refills `rate` tokens per second, holding at most a second's worth
+struct TokenBucket {
+    rate: f64,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: u32) -> TokenBucket {
+        TokenBucket {
+            rate: f64::from(rate),
+            tokens: f64::from(rate),
+            refilled_at: Instant::now(),
+        }
+    }
+
+    fn take(&mut self) -> bool {
+        let now = Instant::now();
+        let refill = now.duration_since(self.refilled_at).as_secs_f64() * self.rate;
+
+        self.tokens = (self.tokens + refill).min(self.rate);
+        self.refilled_at = now;
+
+        if self.tokens < 1.0 {
+            return false;
+        }
+
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+/// Limits on what one connection sends, read from the config when it opens
+pub struct ConnectionLimits {
+    max_text_frame_bytes: usize,
+    packets: Option<TokenBucket>,
+}
+
+impl ConnectionLimits {
+    pub fn new(config: &ConnectionsConfig) -> ConnectionLimits {
+        ConnectionLimits {
+            max_text_frame_bytes: config.max_text_frame_bytes,
+            packets: Some(config.packets_per_sec)
+                .filter(|rate| *rate > 0)
+                .map(TokenBucket::new),
+        }
+    }
+
+    /// Checks a text frame against the frame size and the packet rate
+    pub fn check_text(&mut self, len: usize) -> Result<(), BoaError> {
+        if self.max_text_frame_bytes > 0 && len > self.max_text_frame_bytes {
+            return Err(BoaError::Refused(
+                ServerError::MessageTooLarge,
+                format!(
+                    "text frame of {len} bytes is over the limit of {} bytes",
+                    self.max_text_frame_bytes
+                ),
+            ));
+        }
+
+        if let Some(packets) = &mut self.packets
+            && !packets.take()
+        {
+            return Err(BoaError::Refused(
+                ServerError::RateLimited,
+                format!("more than {} packets per second", packets.rate),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Largest WebSocket message to read at all, so an oversized frame is never buffered whole
+pub fn max_message_size(config: &ConnectionsConfig) -> Option<usize> {
+    let max_upload_bytes = usize::try_from(config.max_upload_bytes).ok()?;
+
+    (config.max_text_frame_bytes > 0 && max_upload_bytes > 0)
+        .then(|| config.max_text_frame_bytes.max(max_upload_bytes))
+}
+
+/// Rejects a new connection once its IP address or identity has as many open as it may.
+/// The identity limit does not apply to anonymous connections, they share one identity.
+pub fn check_connection_count(
+    state: &ServerState,
+    remote_addr: SocketAddr,
+    identity: &Identity,
+) -> Result<(), BoaError> {
+    let config = &state.config.connections;
+
+    let from_ip = state
+        .connections
+        .values()
+        .filter(|connection| connection.remote_addr.ip() == remote_addr.ip())
+        .count();
+
+    if config.max_per_ip > 0 && from_ip >= config.max_per_ip {
+        return Err(BoaError::Refused(
+            ServerError::TooManyConnections,
+            format!(
+                "{from_ip} connections are already open from {}",
+                remote_addr.ip()
+            ),
+        ));
+    }
+
+    if identity.method == AuthMethod::Anonymous {
+        return Ok(());
+    }
+
+    let of_identity = state
+        .connections
+        .values()
+        .filter(|connection| connection.identity.name == identity.name)
+        .count();
+
+    if config.max_per_identity > 0 && of_identity >= config.max_per_identity {
+        return Err(BoaError::Refused(
+            ServerError::TooManyConnections,
+            format!("{of_identity} connections are already open as {identity}"),
+        ));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,108 @@
+use clap::{Args, ValueEnum};
+use tracing_subscriber::{EnvFilter, Layer, fmt, layer::SubscriberExt, util::SubscriberInitExt};
+
+#[derive(Debug, Clone, Copy, Default, ValueEnum)]
+pub enum LogFormat {
+    /// Human readable lines with colors when writing to a terminal
+    #[default]
+    Pretty,
+    /// One JSON object per line, with the fields of every enclosing span
+    Json,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct LogArgs {
+    /// Log filter, e.g. `info` or `info,boa_server::container=debug`
+    #[arg(long, global = true, env = "BOA_LOG", default_value = "info")]
+    pub log: String,
+
+    /// Log output format
+    #[arg(
+        long,
+        global = true,
+        env = "BOA_LOG_FORMAT",
+        value_enum,
+        default_value_t
+    )]
+    pub log_format: LogFormat,
+
+    /// OTLP gRPC endpoint to export spans to, e.g. `http://localhost:4317`
+    #[cfg(feature = "otlp")]
+    #[arg(long, global = true, env = "BOA_OTLP_ENDPOINT")]
+    pub otlp_endpoint: Option<String>,
+}
+
+/// Installs the global tracing subscriber, logging to stdout
+pub fn init(args: &LogArgs) -> Result<(), String> {
+    let filter = EnvFilter::try_new(&args.log)
+        .map_err(|e| format!("invalid log filter {:?}: {e}", args.log))?;
+
+    let fmt_layer = match args.log_format {
+        LogFormat::Pretty => fmt::layer().boxed(),
+        LogFormat::Json => fmt::layer()
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .boxed(),
+    };
+
+    let registry = tracing_subscriber::registry().with(fmt_layer);
+
+    #[cfg(feature = "otlp")]
+    let registry = registry.with(otlp::layer(args.otlp_endpoint.as_deref())?);
+
+    registry
+        .with(filter)
+        .try_init()
+        .map_err(|e| format!("failed to install log subscriber: {e}"))
+}
+
+/// Flushes spans that have not been exported yet
+pub fn shutdown() {
+    #[cfg(feature = "otlp")]
+    otlp::shutdown();
+}
+
+#[cfg(feature = "otlp")]
+mod otlp {
+    use std::sync::OnceLock;
+
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+    use opentelemetry_sdk::{Resource, trace::SdkTracerProvider};
+    use tracing::Subscriber;
+    use tracing_subscriber::{Layer, registry::LookupSpan};
+
+    static PROVIDER: OnceLock<SdkTracerProvider> = OnceLock::new();
+
+    pub fn layer<S>(endpoint: Option<&str>) -> Result<Option<impl Layer<S>>, String>
+    where
+        S: Subscriber + for<'span> LookupSpan<'span>,
+    {
+        let Some(endpoint) = endpoint else {
+            return Ok(None);
+        };
+
+        let exporter = SpanExporter::builder()
+            .with_tonic()
+            .with_endpoint(endpoint)
+            .build()
+            .map_err(|e| format!("failed to create OTLP exporter for {endpoint}: {e}"))?;
+
+        let provider = SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(Resource::builder().with_service_name("boa-server").build())
+            .build();
+
+        let tracer = provider.tracer("boa-server");
+        PROVIDER.get_or_init(|| provider);
+
+        Ok(Some(tracing_opentelemetry::layer().with_tracer(tracer)))
+    }
+
+    pub fn shutdown() {
+        if let Some(provider) = PROVIDER.get() {
+            provider.shutdown().ok();
+        }
+    }
+}
@@ -1,94 +1,394 @@
+mod audit;
+mod auth;
+mod backend;
+mod cli;
+mod config;
 mod container;
-mod logger;
+mod error;
+mod limits;
+mod logging;
+mod metrics;
+mod pool;
+mod quota;
+mod reload;
 mod routes;
+mod shutdown;
+mod snapshot;
 mod state;
+#[cfg(test)]
+mod tests;
+mod tls;
 
-use std::{env, process::exit, sync::Arc};
+use std::{net::SocketAddr, process::exit, sync::Arc};
 
-use axum::{Router, routing::get};
-use bollard::Docker;
-use owo_colors::Style;
+use axum::{
+    Router,
+    extract::{ConnectInfo, Path, Query},
+    http::{StatusCode, header},
+    response::IntoResponse,
+    routing::{delete, get, post},
+    serve::ListenerExt,
+};
+use clap::Parser;
 use tokio::{net::TcpListener, sync::Mutex};
+use tracing::{error, info, warn};
 
-use crate::{logger::Logger, state::ServerState};
+use crate::{
+    audit::{AuditLog, AuditQuery},
+    auth::Authenticator,
+    backend::SharedBackend,
+    cli::{Command, ConfigCommand, ImagesCommand},
+    config::{BackendKind, Config, ConfigOverrides},
+    logging::LogArgs,
+    state::{ServerState, ShareableServerState},
+    tls::{TlsCertificate, TlsListener},
+};
+
+/// A lightweight containerized python code runner
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    overrides: ConfigOverrides,
+
+    #[command(flatten)]
+    log: LogArgs,
+}
 
 #[tokio::main]
 async fn main() {
-    let logger = Logger::new("boa-server".to_string());
+    let cli = Cli::parse();
+
+    if let Err(e) = logging::init(&cli.log) {
+        eprintln!("{e}");
+        exit(1);
+    }
+
+    let config = match Config::load(&cli.overrides) {
+        Ok(config) => config,
+        Err(errors) => {
+            for e in errors {
+                error!("invalid config: {e}");
+            }
+            exit(1);
+        }
+    };
+
+    let command = cli.command.unwrap_or(Command::Serve);
 
-    let server_port = match env::var("BOA_SERVER_PORT") {
-        Ok(port) => port,
+    match &command {
+        Command::Config(ConfigCommand::Check) => finish(cli::config_check(&config)),
+        Command::Token { identity, ttl_secs } => finish(cli::token(&config, identity, *ttl_secs)),
+        _ => {}
+    }
+
+    if let Command::Serve = command {
+        let backend = match backend::connect(&config).await {
+            Ok(backend) => backend,
+            Err(e) => {
+                error!("{e}");
+                exit(1);
+            }
+        };
+
+        serve(backend, config, cli.overrides).await;
+        return;
+    }
+
+    if config.backend != BackendKind::Docker {
+        error!(
+            "this command only works with the docker backend, the sandbox and wasi backends keep no \
+             containers or images outside of a running server"
+        );
+        exit(1);
+    }
+
+    let docker = match backend::docker::connect(&config.docker).await {
+        Ok(docker) => docker,
         Err(e) => {
-            logger.err(format!("failed to read BOA_SERVER_PORT: {e}!"), "~!");
+            error!("{e}");
             exit(1);
         }
     };
 
-    let server_port = match server_port.parse::<u32>() {
-        Ok(port) => port,
-        Err(_) => {
-            logger.err("failed to parse BOA_SERVER_PORT!", "~!");
+    let result = match command {
+        Command::Serve | Command::Config(ConfigCommand::Check) | Command::Token { .. } => {
+            unreachable!()
+        }
+        Command::List => cli::list(&docker, &config).await,
+        Command::Gc { force, dry_run } => cli::gc(&docker, &config, force, dry_run).await,
+        Command::Inspect { id } => cli::inspect(&docker, &config, &id).await,
+        Command::Images(ImagesCommand::Pull) => cli::images_pull(&docker, &config).await,
+    };
+
+    finish(result);
+}
+
+/// Exits with the outcome of a one-off command
+fn finish(result: Result<(), String>) -> ! {
+    match result {
+        Ok(()) => exit(0),
+        Err(e) => {
+            error!("{e}");
+            exit(1);
+        }
+    }
+}
+
+async fn serve(backend: SharedBackend, config: Config, overrides: ConfigOverrides) {
+    let server_url = config.server.bind;
+
+    let audit = match config
+        .audit
+        .path
+        .as_deref()
+        .filter(|path| !path.as_os_str().is_empty())
+        .map(AuditLog::open)
+        .transpose()
+    {
+        Ok(audit) => audit,
+        Err(e) => {
+            error!("{e}");
             exit(1);
         }
     };
 
-    let container_prefix = match env::var("BOA_CONTAINER_PREFIX") {
-        Ok(container_name) => container_name,
+    let auth = match Authenticator::new(&config.auth) {
+        Ok(auth) => auth,
         Err(e) => {
-            logger.err(format!("failed to read BOA_CONTAINER_PREFIX: {e}!"), "");
+            error!("{e}");
             exit(1);
         }
     };
 
-    let docker = match Docker::connect_with_local_defaults() {
-        Ok(docker) => docker,
+    if !auth.is_enabled() {
+        warn!("no auth method is configured, /ws lets every connection in as `anonymous`");
+    }
+
+    let certificate = match config
+        .server
+        .tls
+        .as_ref()
+        .map(TlsCertificate::load)
+        .transpose()
+    {
+        Ok(certificate) => certificate.map(Arc::new),
         Err(e) => {
-            logger.err(format!("failed to connect to docker daemon: {e}!"), "");
+            error!("{e}");
             exit(1);
         }
     };
 
     let server_state = Arc::new(Mutex::new(ServerState::new(
-        docker,
-        server_port,
-        container_prefix,
+        backend, audit, auth, config, overrides,
     )));
 
-    let server_url = format!("0.0.0.0:{server_port}");
+    tokio::spawn(pool::refill(Arc::clone(&server_state)));
+    tokio::spawn(reload::reload_on_sighup(Arc::clone(&server_state)));
 
-    let router = Router::new()
-        .route("/", get(|| async { "Hello world!" }))
-        .route(
-            "/ws",
-            get(async |ws| {
-                let route = Arc::new(routes::ws::BoaWsRoute::new(server_state));
-
-                route.ws_handler(ws)
-            }),
-        );
+    let app = router(&server_state).into_make_service_with_connect_info::<SocketAddr>();
 
     let listener = match TcpListener::bind(&server_url).await {
         Ok(listener) => listener,
         Err(e) => {
-            logger.err(
-                format!("failed to bind TCP listener to {server_url}: {e}!"),
-                "",
-            );
+            error!("failed to bind TCP listener to {server_url}: {e}");
             exit(1);
         }
     };
 
-    logger.log_style(
-        format!("server listening on {server_url}"),
-        Style::new().bright_green(),
-        "",
-    );
+    info!(%server_url, tls = certificate.is_some(), "server listening");
+
+    let shutdown = async move {
+        shutdown::drain_requested(Arc::clone(&server_state)).await;
+        shutdown::drain(server_state).await;
+    };
+
+    let result = match certificate {
+        Some(certificate) => {
+            tokio::spawn(tls::reload_on_change(Arc::clone(&certificate)));
 
-    if let Err(e) = axum::serve(listener, router).await {
-        logger.err(format!("failed to serve router: {e}!"), "");
+            let listener = match TlsListener::new(listener, certificate) {
+                Ok(listener) => listener,
+                Err(e) => {
+                    error!("failed to start TLS listener on {server_url}: {e}");
+                    exit(1);
+                }
+            };
+
+            // small frames like exec output shouldn't wait for Nagle's algorithm under TLS
+            let listener = listener.tap_io(|stream| {
+                stream.get_ref().0.set_nodelay(true).ok();
+            });
+
+            axum::serve(listener, app)
+                .with_graceful_shutdown(shutdown)
+                .await
+        }
+        None => {
+            axum::serve(listener, app)
+                .with_graceful_shutdown(shutdown)
+                .await
+        }
+    };
+
+    if let Err(e) = result {
+        error!("failed to serve router: {e}");
         exit(1);
     }
 
-    logger.log("all done, exiting", "");
+    info!("all done, exiting");
+    logging::shutdown();
     exit(0);
 }
+
+fn router(server_state: &ShareableServerState) -> Router {
+    let ws_state = Arc::clone(server_state);
+    let readyz_state = Arc::clone(server_state);
+
+    Router::new()
+        .route(
+            "/healthz",
+            get(async || routes::health::BoaHealthRoute::healthz_handler()),
+        )
+        .route(
+            "/readyz",
+            get(async || {
+                let route = Arc::new(routes::health::BoaHealthRoute::new(readyz_state));
+
+                route.readyz_handler().await
+            }),
+        )
+        .route(
+            "/metrics",
+            get(async || match metrics::METRICS.render() {
+                Ok(metrics) => {
+                    ([(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)], metrics).into_response()
+                }
+                Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+            }),
+        )
+        .route(
+            "/ws",
+            get(
+                async |ConnectInfo(remote_addr),
+                       credentials: routes::ws::WsCredentials,
+                       origin: routes::ws::WsOrigin,
+                       ws| {
+                    let route = Arc::new(routes::ws::BoaWsRoute::new(ws_state, remote_addr));
+
+                    route.ws_handler(credentials, origin, ws).await
+                },
+            ),
+        )
+        .merge(admin_router(server_state))
+}
+
+fn admin_router(server_state: &ShareableServerState) -> Router {
+    let drain_state = Arc::clone(server_state);
+    let pool_state = Arc::clone(server_state);
+    let reload_state = Arc::clone(server_state);
+    let connections_state = Arc::clone(server_state);
+    let disconnect_state = Arc::clone(server_state);
+    let containers_state = Arc::clone(server_state);
+    let remove_container_state = Arc::clone(server_state);
+    let download_file_state = Arc::clone(server_state);
+    let kill_exec_state = Arc::clone(server_state);
+    let audit_state = Arc::clone(server_state);
+
+    Router::new()
+        .route(
+            "/admin/drain",
+            post(async |auth: routes::admin::AdminAuth| {
+                let route = Arc::new(routes::admin::BoaAdminRoute::new(drain_state));
+
+                route.drain_handler(auth).await
+            }),
+        )
+        .route(
+            "/admin/pool",
+            get(async |auth: routes::admin::AdminAuth| {
+                let route = Arc::new(routes::admin::BoaAdminRoute::new(pool_state));
+
+                route.pool_handler(auth).await
+            }),
+        )
+        .route(
+            "/admin/reload",
+            post(async |auth: routes::admin::AdminAuth| {
+                let route = Arc::new(routes::admin::BoaAdminRoute::new(reload_state));
+
+                route.reload_handler(auth).await
+            }),
+        )
+        .route(
+            "/admin/connections",
+            get(async |auth: routes::admin::AdminAuth| {
+                let route = Arc::new(routes::admin::BoaAdminRoute::new(connections_state));
+
+                route.connections_handler(auth).await
+            }),
+        )
+        .route(
+            "/admin/connections/{id}",
+            delete(
+                async |auth: routes::admin::AdminAuth, Path(id): Path<String>| {
+                    let route = Arc::new(routes::admin::BoaAdminRoute::new(disconnect_state));
+
+                    route.disconnect_handler(auth, id).await
+                },
+            ),
+        )
+        .route(
+            "/admin/containers",
+            get(async |auth: routes::admin::AdminAuth| {
+                let route = Arc::new(routes::admin::BoaAdminRoute::new(containers_state));
+
+                route.containers_handler(auth).await
+            }),
+        )
+        .route(
+            "/admin/containers/{id}",
+            delete(
+                async |auth: routes::admin::AdminAuth, Path(id): Path<String>| {
+                    let route = Arc::new(routes::admin::BoaAdminRoute::new(remove_container_state));
+
+                    route.remove_container_handler(auth, id).await
+                },
+            ),
+        )
+        .route(
+            "/admin/containers/{id}/files",
+            get(
+                async |auth: routes::admin::AdminAuth,
+                       Path(id): Path<String>,
+                       Query(query): Query<routes::admin::FileQuery>| {
+                    let route = Arc::new(routes::admin::BoaAdminRoute::new(download_file_state));
+
+                    route.download_file_handler(auth, id, query).await
+                },
+            ),
+        )
+        .route(
+            "/admin/execs/{id}",
+            delete(
+                async |auth: routes::admin::AdminAuth, Path(id): Path<String>| {
+                    let route = Arc::new(routes::admin::BoaAdminRoute::new(kill_exec_state));
+
+                    route.kill_exec_handler(auth, id).await
+                },
+            ),
+        )
+        .route(
+            "/admin/audit",
+            get(
+                async |auth: routes::admin::AdminAuth, Query(query): Query<AuditQuery>| {
+                    let route = Arc::new(routes::admin::BoaAdminRoute::new(audit_state));
+
+                    route.audit_handler(auth, query).await
+                },
+            ),
+        )
+}
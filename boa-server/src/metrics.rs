@@ -0,0 +1,195 @@
+use std::sync::LazyLock;
+
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts,
+    Registry, TextEncoder,
+};
+
+use crate::error::BoaError;
+
+/// Buckets in seconds, from a warm pool hit up to a long pip install
+const DURATION_BUCKETS: &[f64] = &[
+    0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0,
+];
+
+pub struct Metrics {
+    registry: Registry,
+
+    pub containers_created: IntCounter,
+    pub containers_removed: IntCounter,
+    pub open_duration: Histogram,
+    pub start_duration: Histogram,
+
+    pub exec_duration: HistogramVec,
+    pub exec_exit_codes: IntCounterVec,
+    pub exec_timeouts: IntCounter,
+
+    pub upload_bytes: IntCounter,
+    pub output_bytes: IntCounterVec,
+
+    pub active_connections: IntGauge,
+    pub auth_failures: IntCounterVec,
+    pub rejected_origins: IntCounter,
+
+    pub docker_errors: IntCounterVec,
+    pub server_errors: IntCounterVec,
+}
+
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some("boa".to_string()), None)
+            .expect("the registry prefix is valid");
+
+        // every metric below has a unique, valid name, so none of these can fail
+        let metrics = Self {
+            containers_created: IntCounter::new(
+                "containers_created_total",
+                "Containers created, including pooled ones",
+            )
+            .expect("valid metric"),
+            containers_removed: IntCounter::new("containers_removed_total", "Containers removed")
+                .expect("valid metric"),
+            open_duration: Histogram::with_opts(
+                HistogramOpts::new(
+                    "open_duration_seconds",
+                    "Time to answer a ProcessOpen with a container",
+                )
+                .buckets(DURATION_BUCKETS.to_vec()),
+            )
+            .expect("valid metric"),
+            start_duration: Histogram::with_opts(
+                HistogramOpts::new("start_duration_seconds", "Time to start a container")
+                    .buckets(DURATION_BUCKETS.to_vec()),
+            )
+            .expect("valid metric"),
+            exec_duration: HistogramVec::new(
+                HistogramOpts::new("exec_duration_seconds", "Time execs ran for, by kind")
+                    .buckets(DURATION_BUCKETS.to_vec()),
+                &["kind"],
+            )
+            .expect("valid metric"),
+            exec_exit_codes: IntCounterVec::new(
+                Opts::new("exec_exit_codes_total", "Finished execs, by exit code"),
+                &["exit_code"],
+            )
+            .expect("valid metric"),
+            exec_timeouts: IntCounter::new(
+                "exec_timeouts_total",
+                "Execs killed for running longer than the exec timeout",
+            )
+            .expect("valid metric"),
+            upload_bytes: IntCounter::new("upload_bytes_total", "Bytes uploaded by clients")
+                .expect("valid metric"),
+            output_bytes: IntCounterVec::new(
+                Opts::new(
+                    "output_bytes_total",
+                    "Bytes of exec output sent to clients, by stream",
+                ),
+                &["stream"],
+            )
+            .expect("valid metric"),
+            active_connections: IntGauge::new("active_connections", "Open WebSocket connections")
+                .expect("valid metric"),
+            auth_failures: IntCounterVec::new(
+                Opts::new(
+                    "auth_failures_total",
+                    "WebSocket upgrades rejected for missing or invalid credentials, by reason",
+                ),
+                &["reason"],
+            )
+            .expect("valid metric"),
+            rejected_origins: IntCounter::new(
+                "rejected_origins_total",
+                "WebSocket upgrades rejected for an origin not in server.allowed_origins",
+            )
+            .expect("valid metric"),
+            docker_errors: IntCounterVec::new(
+                Opts::new(
+                    "docker_errors_total",
+                    "Failed Docker API calls, by operation",
+                ),
+                &["operation"],
+            )
+            .expect("valid metric"),
+            server_errors: IntCounterVec::new(
+                Opts::new(
+                    "server_errors_total",
+                    "Errors sent to clients, by ServerError variant",
+                ),
+                &["error"],
+            )
+            .expect("valid metric"),
+            registry,
+        };
+
+        metrics.register();
+        metrics
+    }
+
+    /// Registers every metric with the registry, so they are rendered
+    fn register(&self) {
+        let collectors: [Box<dyn prometheus::core::Collector>; 14] = [
+            Box::new(self.containers_created.clone()),
+            Box::new(self.containers_removed.clone()),
+            Box::new(self.open_duration.clone()),
+            Box::new(self.start_duration.clone()),
+            Box::new(self.exec_duration.clone()),
+            Box::new(self.exec_exit_codes.clone()),
+            Box::new(self.exec_timeouts.clone()),
+            Box::new(self.upload_bytes.clone()),
+            Box::new(self.output_bytes.clone()),
+            Box::new(self.active_connections.clone()),
+            Box::new(self.auth_failures.clone()),
+            Box::new(self.rejected_origins.clone()),
+            Box::new(self.docker_errors.clone()),
+            Box::new(self.server_errors.clone()),
+        ];
+
+        for collector in collectors {
+            self.registry
+                .register(collector)
+                .expect("metric names are unique");
+        }
+    }
+
+    /// Renders every metric in the Prometheus text format
+    pub fn render(&self) -> Result<String, String> {
+        let mut buffer = Vec::new();
+
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .map_err(|e| format!("failed to encode metrics: {e}"))?;
+
+        String::from_utf8(buffer).map_err(|e| format!("failed to encode metrics: {e}"))
+    }
+}
+
+/// Counts a failed Docker API call and sorts its error by what went wrong, for use in `map_err`
+pub fn docker_error(
+    operation: &'static str,
+    message: &'static str,
+) -> impl FnOnce(bollard::errors::Error) -> BoaError {
+    move |e| {
+        METRICS.docker_errors.with_label_values(&[operation]).inc();
+        let message = format!("{message}: {e}");
+
+        match e {
+            bollard::errors::Error::DockerResponseServerError {
+                status_code: 404, ..
+            } => BoaError::NotFound(message),
+            bollard::errors::Error::DockerResponseServerError {
+                status_code: 409,
+                message: response,
+            } if response.contains("is not running") => BoaError::NotStarted(message),
+            bollard::errors::Error::RequestTimeoutError => BoaError::Timeout(message),
+            bollard::errors::Error::IOError { .. }
+            | bollard::errors::Error::SocketNotFoundError(_)
+            | bollard::errors::Error::HyperLegacyError { .. } => {
+                BoaError::BackendUnavailable(message)
+            }
+            _ => BoaError::Failed(message),
+        }
+    }
+}
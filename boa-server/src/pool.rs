@@ -0,0 +1,233 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+    time::Duration,
+};
+
+use futures_util::future::join_all;
+use serde::Serialize;
+use tokio::sync::Notify;
+use tracing::{error, info, instrument};
+
+use crate::{
+    backend::ContainerBackend, config::Config, container::BoaContainer, error::BoaError,
+    state::ShareableServerState,
+};
+
+#[derive(Clone, Default)]
+struct ImagePool {
+    idle: VecDeque<BoaContainer>,
+    hits: u64,
+    misses: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PoolStats {
+    pub image: String,
+    pub idle: usize,
+    pub target: usize,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// Started, idle containers that can be handed out on `ProcessOpen` without waiting for the backend
+#[derive(Clone)]
+pub struct ContainerPool {
+    images: HashMap<String, ImagePool>,
+    target_size: usize,
+    /// Bumped on every reconfigure, so containers created with old settings are not pooled
+    generation: u64,
+    refill_notify: Arc<Notify>,
+}
+
+impl ContainerPool {
+    pub fn new(images: &[&str], target_size: usize) -> ContainerPool {
+        ContainerPool {
+            images: images
+                .iter()
+                .map(|image| ((*image).to_string(), ImagePool::default()))
+                .collect(),
+            target_size,
+            generation: 0,
+            refill_notify: Arc::new(Notify::new()),
+        }
+    }
+}
+
+impl ContainerPool {
+    /// Takes an idle container for `image` if one is available, and wakes up the refill task
+    pub fn take(&mut self, image: &str) -> Option<BoaContainer> {
+        let pool = self.images.get_mut(image)?;
+
+        let container = pool.idle.pop_front();
+
+        if container.is_some() {
+            pool.hits += 1;
+        } else {
+            pool.misses += 1;
+        }
+
+        self.refill_notify.notify_one();
+
+        container
+    }
+
+    /// Replaces the pooled images and target size, returning the ids of the idle containers
+    /// that were created with the old settings and have to be removed
+    pub fn reconfigure(&mut self, images: &[&str], target_size: usize) -> Vec<String> {
+        let stale = self.drain();
+
+        self.images
+            .retain(|image, _| images.contains(&image.as_str()));
+        for image in images {
+            self.images.entry((*image).to_string()).or_default();
+        }
+
+        self.target_size = target_size;
+        self.generation += 1;
+        self.refill_notify.notify_one();
+
+        stale
+    }
+
+    /// Removes every idle container from the pool, returning their ids
+    pub fn drain(&mut self) -> Vec<String> {
+        self.images
+            .values_mut()
+            .flat_map(|pool| pool.idle.drain(..))
+            .map(|container| container.container_id)
+            .collect()
+    }
+
+    pub fn stats(&self) -> Vec<PoolStats> {
+        self.images
+            .iter()
+            .map(|(image, pool)| PoolStats {
+                image: image.clone(),
+                idle: pool.idle.len(),
+                target: self.target_size,
+                hits: pool.hits,
+                misses: pool.misses,
+            })
+            .collect()
+    }
+
+    fn deficits(&self) -> Vec<(String, usize)> {
+        self.images
+            .iter()
+            .filter(|(_, pool)| pool.idle.len() < self.target_size)
+            .map(|(image, pool)| (image.clone(), self.target_size - pool.idle.len()))
+            .collect()
+    }
+
+    /// Adds a container to its image pool, handing it back if it is no longer wanted
+    fn put(&mut self, container: BoaContainer, generation: u64) -> Option<BoaContainer> {
+        if generation != self.generation {
+            return Some(container);
+        }
+
+        match self.images.get_mut(&container.image) {
+            Some(pool) => {
+                pool.idle.push_back(container);
+                None
+            }
+            None => Some(container),
+        }
+    }
+}
+
+async fn create_started(
+    backend: &dyn ContainerBackend,
+    config: &Config,
+    image: &str,
+) -> Result<BoaContainer, BoaError> {
+    let (_, mut container) = BoaContainer::new(backend, config, image).await?;
+
+    if let Err(e) = container.start(backend).await {
+        container.remove(backend).await.ok();
+        return Err(e);
+    }
+
+    Ok(container)
+}
+
+/// Keeps every image pool topped up to its target size until the server starts draining
+#[instrument(name = "pool", skip_all)]
+pub async fn refill(server_state: ShareableServerState) {
+    loop {
+        let (backend, config, deficits, generation, refill_notify) = {
+            let state = server_state.lock().await;
+
+            if state.draining {
+                return;
+            }
+
+            (
+                Arc::clone(&state.backend),
+                state.config.clone(),
+                state.pool.deficits(),
+                state.pool.generation,
+                Arc::clone(&state.pool.refill_notify),
+            )
+        };
+
+        let missing = deficits
+            .iter()
+            .flat_map(|(image, missing)| std::iter::repeat_n(image.as_str(), *missing));
+
+        let created = join_all(missing.map(|image| {
+            let backend = &*backend;
+            let config = &config;
+            async move {
+                (
+                    image.to_string(),
+                    create_started(backend, config, image).await,
+                )
+            }
+        }))
+        .await;
+
+        let mut failed = false;
+
+        for (image, container) in created {
+            let container = match container {
+                Ok(container) => container,
+                Err(e) => {
+                    error!(image, "failed to refill pool: {e}");
+                    failed = true;
+                    continue;
+                }
+            };
+
+            let container_id = container.container_id.clone();
+
+            let rejected = {
+                let mut state = server_state.lock().await;
+
+                if state.draining {
+                    Some(container)
+                } else {
+                    state.pool.put(container, generation)
+                }
+            };
+
+            if let Some(container) = rejected {
+                container.remove(&*backend).await.ok();
+            } else {
+                info!(container_id, image, "added container to the pool");
+            }
+        }
+
+        // Back off after failures so a broken backend is not hammered
+        let retry = if failed {
+            Duration::from_secs(5)
+        } else {
+            Duration::from_secs(30)
+        };
+
+        tokio::select! {
+            () = refill_notify.notified() => {}
+            () = tokio::time::sleep(retry) => {}
+        }
+    }
+}
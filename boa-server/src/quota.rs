@@ -0,0 +1,257 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use boa_core::packets::server::{
+    ServerPacket,
+    error::ServerError,
+    quota::{QuotaStatusPacket, QuotaUsage},
+};
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::warn;
+
+use crate::{
+    backend::ContainerBackend,
+    config::QuotaLimits,
+    container::BoaContainer,
+    error::BoaError,
+    routes::ws::WsOutbound,
+    state::{ServerState, ShareableServerState},
+};
+
+const HOUR: Duration = Duration::from_hours(1);
+const DAY: Duration = Duration::from_hours(24);
+
+/// Execs and CPU time of one identity within the rolling windows of its limits
+#[derive(Clone, Default)]
+struct IdentityUsage {
+    exec_starts: VecDeque<Instant>,
+    cpu: VecDeque<(Instant, Duration)>,
+}
+
+impl IdentityUsage {
+    fn prune(&mut self, now: Instant) {
+        while self
+            .exec_starts
+            .front()
+            .is_some_and(|started| now.duration_since(*started) > HOUR)
+        {
+            self.exec_starts.pop_front();
+        }
+
+        while self
+            .cpu
+            .front()
+            .is_some_and(|(charged, _)| now.duration_since(*charged) > DAY)
+        {
+            self.cpu.pop_front();
+        }
+    }
+}
+
+/// Usage that outlives containers and execs, per identity. Open containers and running execs
+/// are counted from the server state instead.
+#[derive(Clone, Default)]
+pub struct QuotaTracker {
+    usage: HashMap<String, IdentityUsage>,
+    /// Containers being created, which count as open until they are or failed to
+    opening: HashMap<String, u64>,
+}
+
+impl QuotaTracker {
+    fn usage(&mut self, identity: &str) -> &mut IdentityUsage {
+        let usage = self.usage.entry(identity.to_string()).or_default();
+        usage.prune(Instant::now());
+        usage
+    }
+
+    /// Holds a container slot of `identity` while its container is created
+    pub fn reserve_open(&mut self, identity: &str) {
+        *self.opening.entry(identity.to_string()).or_default() += 1;
+    }
+
+    pub fn release_open(&mut self, identity: &str) {
+        if let Some(opening) = self.opening.get_mut(identity) {
+            *opening -= 1;
+
+            if *opening == 0 {
+                self.opening.remove(identity);
+            }
+        }
+    }
+
+    pub fn record_exec_start(&mut self, identity: &str) {
+        self.usage(identity).exec_starts.push_back(Instant::now());
+    }
+
+    pub fn record_cpu(&mut self, identity: &str, cpu: Duration) {
+        self.usage(identity).cpu.push_back((Instant::now(), cpu));
+    }
+
+    fn execs_last_hour(&mut self, identity: &str) -> u64 {
+        self.usage(identity).exec_starts.len() as u64
+    }
+
+    fn cpu_secs_last_day(&mut self, identity: &str) -> u64 {
+        self.usage(identity)
+            .cpu
+            .iter()
+            .map(|(_, cpu)| *cpu)
+            .sum::<Duration>()
+            .as_secs()
+    }
+}
+
+impl ServerState {
+    /// Limits that apply to `identity`. Without auth every connection is `anonymous`, so the
+    /// default limits would be server-wide and only the ones configured for it apply.
+    pub fn quota_limits(&self, identity: &str) -> QuotaLimits {
+        if self.auth.is_enabled() || self.config.quotas.identities.contains_key(identity) {
+            self.config.quotas.limits(identity).clone()
+        } else {
+            QuotaLimits::unlimited()
+        }
+    }
+
+    /// Usage of `identity` against its `quota_limits`, which `check_open` and `check_exec`
+    /// enforce, so clients are sent the limits they are held to
+    pub fn quota_status(&mut self, identity: &str) -> QuotaStatusPacket {
+        let limits = self.quota_limits(identity);
+
+        let owned_by =
+            |container: &BoaContainer| container.owner_identity.as_deref() == Some(identity);
+
+        let containers = self.containers.values().filter(|c| owned_by(c)).count() as u64
+            + self
+                .quotas
+                .opening
+                .get(identity)
+                .copied()
+                .unwrap_or_default();
+        let execs = self
+            .execs
+            .values()
+            .filter(|exec| {
+                self.containers
+                    .get(&exec.container_id)
+                    .is_some_and(owned_by)
+            })
+            .count() as u64;
+
+        QuotaStatusPacket {
+            identity: identity.to_string(),
+            containers: QuotaUsage::new(containers, limits.max_containers),
+            execs: QuotaUsage::new(execs, limits.max_execs),
+            execs_per_hour: QuotaUsage::new(
+                self.quotas.execs_last_hour(identity),
+                limits.execs_per_hour,
+            ),
+            cpu_secs_per_day: QuotaUsage::new(
+                self.quotas.cpu_secs_last_day(identity),
+                limits.cpu_secs_per_day,
+            ),
+        }
+    }
+}
+
+/// Rejects another container once the identity has as many open as it may
+pub fn check_open(status: &QuotaStatusPacket) -> Result<(), BoaError> {
+    if status.containers.is_exhausted() {
+        return Err(BoaError::Refused(
+            ServerError::QuotaExceeded,
+            format!(
+                "quota exceeded: {} containers open at once",
+                status.containers.used
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Rejects another exec once any of the exec or CPU limits of the identity is used up
+pub fn check_exec(status: &QuotaStatusPacket) -> Result<(), BoaError> {
+    if status.execs.is_exhausted() {
+        return Err(BoaError::Refused(
+            ServerError::QuotaExceeded,
+            format!(
+                "quota exceeded: {} execs running at once",
+                status.execs.used
+            ),
+        ));
+    }
+    if status.execs_per_hour.is_exhausted() {
+        return Err(BoaError::Refused(
+            ServerError::QuotaExceeded,
+            format!(
+                "quota exceeded: {} execs started in the last hour",
+                status.execs_per_hour.used
+            ),
+        ));
+    }
+    if status.cpu_secs_per_day.is_exhausted() {
+        return Err(BoaError::Refused(
+            ServerError::QuotaExceeded,
+            format!(
+                "quota exceeded: {} cpu seconds used in the last 24 hours",
+                status.cpu_secs_per_day.used
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Charges the CPU time `container` used since it was last charged to its owner. Falls back to
+/// the wall time of the exec when the backend has no stats for the container.
+pub async fn charge_cpu(
+    server_state: &ShareableServerState,
+    backend: &dyn ContainerBackend,
+    container: &BoaContainer,
+    wall_time: Duration,
+) {
+    let Some(identity) = &container.owner_identity else {
+        return;
+    };
+
+    let cpu_usage_ns = match container.cpu_usage_ns(backend).await {
+        Ok(cpu_usage_ns) => Some(cpu_usage_ns),
+        Err(e) => {
+            warn!("charging the wall time of the exec, failed to get its cpu time: {e}");
+            None
+        }
+    };
+
+    let mut state_lock = server_state.lock().await;
+    let state = &mut *state_lock;
+
+    // the container may have been closed while the exec ran, its stored copy is gone then
+    let cpu = match (
+        cpu_usage_ns,
+        state.containers.get_mut(&container.container_id),
+    ) {
+        (Some(cpu_usage_ns), Some(stored)) => {
+            let charged = cpu_usage_ns.saturating_sub(stored.cpu_charged_ns);
+            stored.cpu_charged_ns = cpu_usage_ns;
+            Duration::from_nanos(charged)
+        }
+        (Some(cpu_usage_ns), None) => {
+            Duration::from_nanos(cpu_usage_ns.saturating_sub(container.cpu_charged_ns))
+        }
+        (None, _) => wall_time,
+    };
+
+    state.quotas.record_cpu(identity, cpu);
+}
+
+pub async fn send_status(
+    server_state: &ShareableServerState,
+    identity: &str,
+    tx: &UnboundedSender<WsOutbound>,
+) {
+    let status = server_state.lock().await.quota_status(identity);
+
+    tx.send(WsOutbound::Packet(ServerPacket::QuotaStatus(status)))
+        .ok();
+}
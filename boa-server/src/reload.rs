@@ -0,0 +1,103 @@
+use std::sync::Arc;
+
+use tokio::signal::unix::{SignalKind, signal};
+use tracing::{error, info, instrument, warn};
+
+use crate::{auth::Authenticator, config::Config, container, state::ShareableServerState};
+
+/// Reloads the config from the same file, environment and command line the server was started
+/// with. Runtimes, limits and other per container settings apply to new containers and execs,
+/// auth applies to new connections, open connections and containers are left alone.
+pub async fn reload(server_state: &ShareableServerState) -> Result<(), Vec<String>> {
+    let overrides = server_state.lock().await.config_overrides.clone();
+
+    let mut config = Config::load(&overrides)?;
+    let auth = Authenticator::new(&config.auth).map_err(|e| vec![e])?;
+
+    let (backend, stale_containers) = {
+        let mut state = server_state.lock().await;
+
+        // The listener, its tls paths, the backend and the audit log are created once at startup
+        if config.server.bind != state.config.server.bind {
+            warn!("server.bind changed, restart the server to apply it");
+            config.server.bind = state.config.server.bind;
+        }
+        if config.server.tls != state.config.server.tls {
+            warn!("server.tls changed, restart the server to apply it");
+            config.server.tls.clone_from(&state.config.server.tls);
+        }
+        if config.backend != state.config.backend {
+            warn!("backend changed, restart the server to apply it");
+            config.backend = state.config.backend;
+        }
+        if config.sandbox.dir != state.config.sandbox.dir
+            || config.sandbox.cgroup != state.config.sandbox.cgroup
+        {
+            warn!("sandbox.dir or sandbox.cgroup changed, restart the server to apply it");
+            config.sandbox.dir.clone_from(&state.config.sandbox.dir);
+            config
+                .sandbox
+                .cgroup
+                .clone_from(&state.config.sandbox.cgroup);
+        }
+        if config.wasi != state.config.wasi {
+            warn!("wasi changed, restart the server to apply it");
+            config.wasi.clone_from(&state.config.wasi);
+        }
+        if config.docker != state.config.docker {
+            warn!("docker changed, restart the server to apply it");
+            config.docker.clone_from(&state.config.docker);
+        }
+        if config.audit.path != state.config.audit.path {
+            warn!("audit.path changed, restart the server to apply it");
+            config.audit.path.clone_from(&state.config.audit.path);
+        }
+
+        if state.auth.is_enabled() && !auth.is_enabled() {
+            warn!(
+                "no auth method is configured anymore, /ws lets every connection in as `anonymous`"
+            );
+        }
+
+        let stale_containers = state
+            .pool
+            .reconfigure(&config.runtime_images(), config.containers.pool_size);
+
+        state.auth = auth;
+        state.config = config;
+
+        (Arc::clone(&state.backend), stale_containers)
+    };
+
+    for container_id in stale_containers {
+        container::remove_container(&*backend, &container_id)
+            .await
+            .ok();
+    }
+
+    info!("reloaded config");
+
+    Ok(())
+}
+
+/// Reloads the config every time the server receives SIGHUP
+#[instrument(name = "reload", skip_all)]
+pub async fn reload_on_sighup(server_state: ShareableServerState) {
+    let mut sighup = match signal(SignalKind::hangup()) {
+        Ok(sighup) => sighup,
+        Err(e) => {
+            error!("failed to install SIGHUP handler: {e}");
+            return;
+        }
+    };
+
+    while sighup.recv().await.is_some() {
+        info!("received SIGHUP");
+
+        if let Err(errors) = reload(&server_state).await {
+            for e in errors {
+                error!("invalid config, keeping the old one: {e}");
+            }
+        }
+    }
+}
@@ -0,0 +1,389 @@
+use std::{sync::Arc, time::SystemTime};
+
+use axum::{
+    Json,
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use axum_extra::{
+    TypedHeader,
+    headers::{Authorization, authorization::Bearer},
+};
+use boa_core::packets::server::{ServerPacket, process::ProcessCloseResultPacket};
+use serde::{Deserialize, Serialize};
+use subtle::ConstantTimeEq;
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::{
+    audit::AuditQuery,
+    auth::Identity,
+    reload,
+    routes::ws::WsOutbound,
+    state::{ServerState, ShareableServerState},
+};
+
+pub type AdminAuth = Option<TypedHeader<Authorization<Bearer>>>;
+
+#[derive(Serialize)]
+pub struct ConnectionInfo {
+    id: String,
+    remote_addr: String,
+    identity: Identity,
+    age_secs: u64,
+    containers: Vec<String>,
+    execs: usize,
+}
+
+#[derive(Serialize)]
+pub struct ContainerInfo {
+    id: String,
+    image: String,
+    owner: Option<String>,
+    /// Identity of the owning connection, whose quota the container counts against
+    owner_identity: Option<String>,
+    age_secs: u64,
+    state: &'static str,
+    execs: Vec<ExecInfo>,
+}
+
+#[derive(Serialize)]
+pub struct ExecInfo {
+    id: String,
+    kind: &'static str,
+    path: String,
+    age_secs: u64,
+}
+
+/// File to read from a container, relative paths are in the working directory
+#[derive(Deserialize)]
+pub struct FileQuery {
+    path: String,
+}
+
+fn age_secs(since: SystemTime) -> u64 {
+    since.elapsed().map_or(0, |age| age.as_secs())
+}
+
+#[derive(Clone)]
+pub struct BoaAdminRoute {
+    server_state: ShareableServerState,
+}
+
+impl BoaAdminRoute {
+    pub fn new(server_state: ShareableServerState) -> BoaAdminRoute {
+        BoaAdminRoute { server_state }
+    }
+}
+
+impl BoaAdminRoute {
+    async fn authorize(&self, auth: AdminAuth) -> Result<(), (StatusCode, &'static str)> {
+        let state = self.server_state.lock().await;
+
+        let Some(admin_token) = state.config.server.admin_token.as_ref() else {
+            return Err((StatusCode::FORBIDDEN, "admin api is disabled"));
+        };
+
+        match auth {
+            Some(TypedHeader(Authorization(bearer)))
+                if bool::from(bearer.token().as_bytes().ct_eq(admin_token.as_bytes())) =>
+            {
+                Ok(())
+            }
+            _ => Err((StatusCode::UNAUTHORIZED, "invalid admin token")),
+        }
+    }
+
+    pub async fn drain_handler(self: Arc<Self>, auth: AdminAuth) -> impl IntoResponse {
+        if let Err(rejection) = self.authorize(auth).await {
+            return rejection;
+        }
+
+        let state = self.server_state.lock().await;
+
+        if state.draining {
+            return (StatusCode::CONFLICT, "server is already draining");
+        }
+
+        info!("drain requested");
+        state.drain_notify.notify_one();
+
+        (StatusCode::ACCEPTED, "draining")
+    }
+
+    pub async fn pool_handler(self: Arc<Self>, auth: AdminAuth) -> Response {
+        if let Err(rejection) = self.authorize(auth).await {
+            return rejection.into_response();
+        }
+
+        Json(self.server_state.lock().await.pool.stats()).into_response()
+    }
+
+    pub async fn reload_handler(self: Arc<Self>, auth: AdminAuth) -> Response {
+        if let Err(rejection) = self.authorize(auth).await {
+            return rejection.into_response();
+        }
+
+        info!("reload requested");
+
+        match reload::reload(&self.server_state).await {
+            Ok(()) => (StatusCode::OK, "reloaded").into_response(),
+            Err(errors) => (StatusCode::UNPROCESSABLE_ENTITY, Json(errors)).into_response(),
+        }
+    }
+}
+
+impl BoaAdminRoute {
+    pub async fn connections_handler(self: Arc<Self>, auth: AdminAuth) -> Response {
+        if let Err(rejection) = self.authorize(auth).await {
+            return rejection.into_response();
+        }
+
+        let state = self.server_state.lock().await;
+
+        let mut connections = state
+            .connections
+            .iter()
+            .map(|(id, connection)| ConnectionInfo {
+                id: id.to_string(),
+                remote_addr: connection.remote_addr.to_string(),
+                identity: connection.identity.clone(),
+                age_secs: age_secs(connection.opened_at),
+                containers: state
+                    .containers
+                    .values()
+                    .filter(|container| container.owner == Some(*id))
+                    .map(|container| container.container_id.clone())
+                    .collect(),
+                execs: state
+                    .execs
+                    .values()
+                    .filter(|exec| exec.connection_id == *id)
+                    .count(),
+            })
+            .collect::<Vec<_>>();
+
+        connections.sort_by_key(|connection| std::cmp::Reverse(connection.age_secs));
+
+        Json(connections).into_response()
+    }
+
+    pub async fn containers_handler(self: Arc<Self>, auth: AdminAuth) -> Response {
+        if let Err(rejection) = self.authorize(auth).await {
+            return rejection.into_response();
+        }
+
+        let state = self.server_state.lock().await;
+
+        let mut containers = state
+            .containers
+            .values()
+            .map(|container| {
+                let execs = container_execs(&state, &container.container_id);
+
+                ContainerInfo {
+                    id: container.container_id.clone(),
+                    image: container.image.clone(),
+                    owner: container.owner.map(|owner| owner.to_string()),
+                    owner_identity: container.owner_identity.clone(),
+                    age_secs: age_secs(container.created_at),
+                    state: if container.paused {
+                        "paused"
+                    } else if execs.is_empty() {
+                        "idle"
+                    } else {
+                        "executing"
+                    },
+                    execs,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        containers.sort_by_key(|container| std::cmp::Reverse(container.age_secs));
+
+        Json(containers).into_response()
+    }
+
+    pub async fn kill_exec_handler(self: Arc<Self>, auth: AdminAuth, exec_id: String) -> Response {
+        if let Err(rejection) = self.authorize(auth).await {
+            return rejection.into_response();
+        }
+
+        let (container, backend, exec_id) = {
+            let mut state = self.server_state.lock().await;
+
+            let Some((exec_id, exec)) = Uuid::parse_str(&exec_id)
+                .ok()
+                .and_then(|exec_id| state.execs.get_key_value(&exec_id))
+            else {
+                return (StatusCode::NOT_FOUND, "no such exec").into_response();
+            };
+            let exec_id = *exec_id;
+
+            let Some(container) = state.containers.get(&exec.container_id).cloned() else {
+                return (StatusCode::NOT_FOUND, "container of the exec is gone").into_response();
+            };
+
+            if let Some(exec) = state.execs.get_mut(&exec_id) {
+                exec.kill_reason = Some("killed through the admin api".to_string());
+            }
+
+            (container, Arc::clone(&state.backend), exec_id)
+        };
+
+        info!(%exec_id, "killing exec");
+
+        match container.kill_exec(&*backend, exec_id).await {
+            Ok(()) => (StatusCode::OK, "killed").into_response(),
+            Err(e) => {
+                error!(%exec_id, "failed to kill exec: {e}");
+                e.into_response()
+            }
+        }
+    }
+
+    pub async fn remove_container_handler(
+        self: Arc<Self>,
+        auth: AdminAuth,
+        container_id: String,
+    ) -> Response {
+        if let Err(rejection) = self.authorize(auth).await {
+            return rejection.into_response();
+        }
+
+        let (container, backend, owner) = {
+            let mut state = self.server_state.lock().await;
+
+            let Some(container) = state.containers.remove(&container_id) else {
+                return (StatusCode::NOT_FOUND, "no such container").into_response();
+            };
+
+            state.mark_killed(&container_id, "container removed through the admin api");
+
+            let owner = container
+                .owner
+                .and_then(|owner| state.connections.get(&owner))
+                .map(|connection| connection.sender.clone());
+
+            (container, Arc::clone(&state.backend), owner)
+        };
+
+        info!(container_id, "removing container");
+
+        if let Err(e) = container.remove(&*backend).await {
+            error!(container_id, "{e}");
+            return e.into_response();
+        }
+
+        if let Some(owner) = owner {
+            owner
+                .send(WsOutbound::Packet(ServerPacket::ProcessCloseResult(
+                    ProcessCloseResultPacket { success: true },
+                )))
+                .ok();
+        }
+
+        (StatusCode::OK, "removed").into_response()
+    }
+
+    pub async fn download_file_handler(
+        self: Arc<Self>,
+        auth: AdminAuth,
+        container_id: String,
+        query: FileQuery,
+    ) -> Response {
+        if let Err(rejection) = self.authorize(auth).await {
+            return rejection.into_response();
+        }
+
+        let (container, backend, working_dir) = {
+            let state = self.server_state.lock().await;
+
+            let Some(container) = state.containers.get(&container_id).cloned() else {
+                return (StatusCode::NOT_FOUND, "no such container").into_response();
+            };
+
+            (
+                container,
+                Arc::clone(&state.backend),
+                state.config.containers.working_dir.clone(),
+            )
+        };
+
+        match container
+            .download_file(&*backend, &query.path, &working_dir)
+            .await
+        {
+            Ok(contents) => (
+                [(header::CONTENT_TYPE, "application/octet-stream")],
+                contents,
+            )
+                .into_response(),
+            Err(e) => {
+                error!(container_id, path = query.path, "{e}");
+                e.into_response()
+            }
+        }
+    }
+
+    pub async fn disconnect_handler(
+        self: Arc<Self>,
+        auth: AdminAuth,
+        connection_id: String,
+    ) -> Response {
+        if let Err(rejection) = self.authorize(auth).await {
+            return rejection.into_response();
+        }
+
+        let state = self.server_state.lock().await;
+
+        let Some(connection) = Uuid::parse_str(&connection_id)
+            .ok()
+            .and_then(|connection_id| state.connections.get(&connection_id))
+        else {
+            return (StatusCode::NOT_FOUND, "no such connection").into_response();
+        };
+
+        info!(connection_id, "disconnecting");
+        connection.disconnect();
+
+        (StatusCode::OK, "disconnected").into_response()
+    }
+}
+
+impl BoaAdminRoute {
+    pub async fn audit_handler(self: Arc<Self>, auth: AdminAuth, query: AuditQuery) -> Response {
+        if let Err(rejection) = self.authorize(auth).await {
+            return rejection.into_response();
+        }
+
+        let Some(audit) = self.server_state.lock().await.audit.clone() else {
+            return (StatusCode::NOT_FOUND, "audit log is disabled").into_response();
+        };
+
+        match audit.query(query).await {
+            Ok(records) => Json(records).into_response(),
+            Err(e) => {
+                error!("{e}");
+                e.into_response()
+            }
+        }
+    }
+}
+
+fn container_execs(state: &ServerState, container_id: &str) -> Vec<ExecInfo> {
+    let mut execs = state
+        .execs
+        .iter()
+        .filter(|(_, exec)| exec.container_id == container_id)
+        .map(|(id, exec)| ExecInfo {
+            id: id.to_string(),
+            kind: exec.kind.name(),
+            path: exec.kind.path().to_string(),
+            age_secs: age_secs(exec.started_at),
+        })
+        .collect::<Vec<_>>();
+
+    execs.sort_by_key(|exec| std::cmp::Reverse(exec.age_secs));
+    execs
+}
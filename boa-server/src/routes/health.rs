@@ -0,0 +1,125 @@
+use std::{sync::Arc, time::Duration};
+
+use axum::{
+    Json,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use tokio::time::timeout;
+
+use crate::{pool::PoolStats, state::ShareableServerState};
+
+/// How long a readiness check waits on the docker daemon
+const DOCKER_CHECK_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Serialize)]
+pub struct Health {
+    status: &'static str,
+}
+
+#[derive(Serialize)]
+pub struct Readiness {
+    ready: bool,
+    draining: bool,
+    docker: DockerCheck,
+    images: Vec<ImageCheck>,
+    pool: Vec<PoolStats>,
+}
+
+#[derive(Serialize)]
+pub struct DockerCheck {
+    ok: bool,
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct ImageCheck {
+    image: String,
+    present: bool,
+}
+
+#[derive(Clone)]
+pub struct BoaHealthRoute {
+    server_state: ShareableServerState,
+}
+
+impl BoaHealthRoute {
+    pub fn new(server_state: ShareableServerState) -> BoaHealthRoute {
+        BoaHealthRoute { server_state }
+    }
+}
+
+impl BoaHealthRoute {
+    /// The server is alive as long as it can answer
+    pub fn healthz_handler() -> Json<Health> {
+        Json(Health { status: "ok" })
+    }
+
+    /// Ready when docker answers, every runtime image is present locally and the server is not draining
+    pub async fn readyz_handler(self: Arc<Self>) -> Response {
+        let (backend, images, pool, draining) = {
+            let state = self.server_state.lock().await;
+            (
+                Arc::clone(&state.backend),
+                state
+                    .config
+                    .runtime_images()
+                    .into_iter()
+                    .map(str::to_string)
+                    .collect::<Vec<_>>(),
+                state.pool.stats(),
+                state.draining,
+            )
+        };
+
+        let docker_check = match timeout(DOCKER_CHECK_TIMEOUT, backend.ping()).await {
+            Ok(Ok(())) => DockerCheck {
+                ok: true,
+                error: None,
+            },
+            Ok(Err(e)) => DockerCheck {
+                ok: false,
+                error: Some(e.to_string()),
+            },
+            Err(_) => DockerCheck {
+                ok: false,
+                error: Some(format!(
+                    "docker did not answer within {}s",
+                    DOCKER_CHECK_TIMEOUT.as_secs()
+                )),
+            },
+        };
+
+        let mut image_checks = Vec::with_capacity(images.len());
+
+        for image in images {
+            let present = docker_check.ok
+                && timeout(DOCKER_CHECK_TIMEOUT, backend.image_size(&image))
+                    .await
+                    .is_ok_and(|inspect| inspect.is_ok());
+
+            image_checks.push(ImageCheck { image, present });
+        }
+
+        let ready = !draining && docker_check.ok && image_checks.iter().all(|image| image.present);
+
+        let status = if ready {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        };
+
+        (
+            status,
+            Json(Readiness {
+                ready,
+                draining,
+                docker: docker_check,
+                images: image_checks,
+                pool,
+            }),
+        )
+            .into_response()
+    }
+}
@@ -1,47 +1,135 @@
-use std::sync::Arc;
+use std::{
+    convert::Infallible,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Instant, SystemTime},
+};
 
 use axum::{
-    extract::ws::{Message, WebSocketUpgrade},
-    response::IntoResponse,
+    extract::{
+        FromRequestParts, Query,
+        ws::{Message, WebSocket, WebSocketUpgrade},
+    },
+    http::{StatusCode, header, request::Parts},
+    response::{IntoResponse, Response},
 };
+use axum_extra::headers::{Authorization, HeaderMapExt, authorization::Bearer};
 
 use boa_core::packets::{
-    client::{ClientPacket, process::ProcessControlSignal},
+    client::{
+        ClientPacket,
+        process::{ProcessControlSignal, ProcessControlSignalPacket, ProcessOpenPacket},
+    },
     server::{
         ServerPacket,
         error::{ServerError, ServerErrorPacket},
-        process::{ProcessCloseResultPacket, ProcessEventPacket, ProcessOpenResultPacket},
+        process::{
+            ProcessCloseResultPacket, ProcessEventPacket, ProcessOpenResultPacket,
+            ProcessSnapshotResultPacket,
+        },
     },
 };
 
-use bollard::query_parameters::RemoveContainerOptions;
-use futures_util::{SinkExt, StreamExt};
+use futures_util::{SinkExt, StreamExt, stream::SplitSink};
 
+use serde::Deserialize;
 use tokio::{
     io::AsyncWriteExt,
-    sync::mpsc::{self, UnboundedSender},
+    sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
+};
+use tracing::{Instrument, error, info, info_span, warn};
+use uuid::Uuid;
+
+use crate::{
+    audit::{self, ExecFinish, ExecOutcome, ExecRecord},
+    auth::Identity,
+    backend::{ContainerBackend, SharedBackend},
+    config::Config,
+    container::{self, BoaContainer, ExecKind, ExecOutput, StopSignal},
+    error::BoaError,
+    limits::{self, ConnectionLimits},
+    metrics::METRICS,
+    quota, snapshot,
+    state::{Connection, RunningExec, ServerState, ShareableServerState},
 };
-
-use crate::{container::BoaContainer, logger::Logger, state::ShareableServerState};
 
 #[derive(Clone)]
 pub struct BoaWsRoute {
-    logger: Arc<Logger>,
     server_state: ShareableServerState,
+    connection_id: Uuid,
+    remote_addr: SocketAddr,
 }
 
 impl BoaWsRoute {
-    pub fn new(server_state: ShareableServerState) -> BoaWsRoute {
+    pub fn new(server_state: ShareableServerState, remote_addr: SocketAddr) -> BoaWsRoute {
         BoaWsRoute {
-            logger: Arc::new(Logger::new("boa-server~/ws".to_string())),
             server_state,
+            connection_id: Uuid::new_v4(),
+            remote_addr,
         }
     }
 }
 
+/// Token a client upgrades with, from an `Authorization: Bearer` header or the `token` query
+/// parameter, since browsers cannot set headers on a WebSocket
+pub struct WsCredentials {
+    token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TokenQuery {
+    token: Option<String>,
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for WsCredentials {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let token = match parts.headers.typed_get::<Authorization<Bearer>>() {
+            Some(Authorization(bearer)) => Some(bearer.token().to_string()),
+            None => Query::<TokenQuery>::try_from_uri(&parts.uri)
+                .ok()
+                .and_then(|Query(query)| query.token),
+        };
+
+        Ok(WsCredentials { token })
+    }
+}
+
+/// `Origin` header of an upgrade, only sent by browsers
+pub struct WsOrigin(Option<String>);
+
+impl<S: Send + Sync> FromRequestParts<S> for WsOrigin {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let origin = parts
+            .headers
+            .get(header::ORIGIN)
+            .map(|origin| String::from_utf8_lossy(origin.as_bytes()).into_owned());
+
+        Ok(WsOrigin(origin))
+    }
+}
+
 pub enum WsOutbound {
     Packet(ServerPacket),
     Pong(Vec<u8>),
+    Close,
+}
+
+impl WsOutbound {
+    pub fn error(err: ServerError, message: impl Into<String>) -> WsOutbound {
+        METRICS
+            .server_errors
+            .with_label_values(&[&format!("{err:?}")])
+            .inc();
+
+        WsOutbound::Packet(ServerPacket::ServerError(ServerErrorPacket {
+            err,
+            message: message.into(),
+        }))
+    }
 }
 
 struct UploadState {
@@ -53,175 +141,313 @@ struct UploadState {
 }
 
 impl BoaWsRoute {
-    pub fn ws_handler(self: Arc<Self>, ws: WebSocketUpgrade) -> impl IntoResponse {
-        self.logger.log("new connection opened", "");
+    pub async fn ws_handler(
+        self: Arc<Self>,
+        credentials: WsCredentials,
+        WsOrigin(origin): WsOrigin,
+        ws: WebSocketUpgrade,
+    ) -> Response {
+        let (origin_allowed, identity, max_message_size) = {
+            let state = self.server_state.lock().await;
+            (
+                origin
+                    .as_deref()
+                    .is_none_or(|origin| state.config.server.is_origin_allowed(origin)),
+                state.auth.authenticate(credentials.token.as_deref()),
+                limits::max_message_size(&state.config.connections),
+            )
+        };
+
+        // browsers let any page open a WebSocket, and send cookies and client certs with it
+        if !origin_allowed {
+            METRICS.rejected_origins.inc();
+            warn!(remote_addr = %self.remote_addr, ?origin, "rejected connection: origin not allowed");
+
+            return (StatusCode::FORBIDDEN, "origin not allowed").into_response();
+        }
 
-        ws.on_upgrade(move |socket| {
-            let this = Arc::clone(&self);
-            async move {
-                let (mut ws_tx, mut ws_rx) = socket.split();
-
-                let (packet_tx, mut packet_rx) = mpsc::unbounded_channel::<WsOutbound>();
-
-                let writer = tokio::spawn(async move {
-                    while let Some(msg) = packet_rx.recv().await {
-                        match msg {
-                            WsOutbound::Packet(packet) => {
-                                let Ok(text) = serde_json::to_string(&packet) else {
-                                    continue;
-                                };
-                                if ws_tx.send(Message::Text(text.into())).await.is_err() {
-                                    break;
-                                }
-                            }
-                            WsOutbound::Pong(p) => {
-                                if ws_tx.send(Message::Pong(p.into())).await.is_err() {
-                                    break;
-                                }
-                            }
+        let identity = match identity {
+            Ok(identity) => identity,
+            Err(reason) => {
+                METRICS.auth_failures.with_label_values(&[reason]).inc();
+                warn!(remote_addr = %self.remote_addr, "rejected connection: {reason}");
+
+                return (
+                    StatusCode::UNAUTHORIZED,
+                    [(header::WWW_AUTHENTICATE, "Bearer")],
+                    reason,
+                )
+                    .into_response();
+            }
+        };
+
+        let span = info_span!(
+            "connection",
+            connection_id = %self.connection_id,
+            remote_addr = %self.remote_addr,
+            identity = %identity
+        );
+        span.in_scope(|| info!(auth_method = ?identity.method, "new connection opened"));
+
+        let ws = match max_message_size {
+            Some(max_message_size) => ws.max_message_size(max_message_size),
+            None => ws,
+        };
+
+        ws.on_upgrade(move |socket| self.handle_socket(socket, identity).instrument(span))
+    }
+
+    async fn handle_socket(self: Arc<Self>, socket: WebSocket, identity: Identity) {
+        let (ws_tx, mut ws_rx) = socket.split();
+
+        let (packet_tx, packet_rx) = mpsc::unbounded_channel::<WsOutbound>();
+        let writer = tokio::spawn(write_packets(ws_tx, packet_rx));
+
+        let identity_name = identity.name.clone();
+        let registered = {
+            let mut state = self.server_state.lock().await;
+
+            limits::check_connection_count(&state, self.remote_addr, &identity).map(|()| {
+                let connection = Connection::new(packet_tx.clone(), self.remote_addr, identity);
+                let disconnect_notify = Arc::clone(&connection.disconnect_notify);
+
+                state.connections.insert(self.connection_id, connection);
+                (
+                    disconnect_notify,
+                    ConnectionLimits::new(&state.config.connections),
+                )
+            })
+        };
+
+        let (disconnect_notify, mut limits) = match registered {
+            Ok(registered) => registered,
+            Err(e) => {
+                report_error(&packet_tx, &e);
+                drop(packet_tx);
+                let _ = writer.await;
+                return;
+            }
+        };
+        METRICS.active_connections.inc();
+
+        quota::send_status(&self.server_state, &identity_name, &packet_tx).await;
+
+        let mut upload_state: Option<UploadState> = None;
+
+        loop {
+            let msg = tokio::select! {
+                msg = ws_rx.next() => msg,
+                () = disconnect_notify.notified() => break,
+            };
+
+            let Some(Ok(msg)) = msg else { break };
+
+            match msg {
+                Message::Text(t) => {
+                    let result = match limits.check_text(t.len()) {
+                        Ok(()) => self.handle_text(&t, &mut upload_state, &packet_tx).await,
+                        Err(e) => Err(e),
+                    };
+
+                    if let Err(e) = result {
+                        report_error(&packet_tx, &e);
+                        if e.closes_connection() {
+                            break;
                         }
                     }
-                });
+                }
 
-                let mut upload_state: Option<UploadState> = None;
-
-                while let Some(msg) = ws_rx.next().await {
-                    let Ok(msg) = msg else { break };
-
-                    match msg {
-                        Message::Text(t) => {
-                            let packet = match serde_json::from_str::<ClientPacket>(&t) {
-                                Ok(p) => p,
-                                Err(e) => {
-                                    let _ = packet_tx.send(WsOutbound::Packet(
-                                        ServerPacket::ServerError(ServerErrorPacket {
-                                            err: ServerError::InvalidJson,
-                                            message: e.to_string(),
-                                        }),
-                                    ));
-                                    break;
-                                }
-                            };
-
-                            match packet {
-                                ClientPacket::UploadStart {
-                                    container_id,
-                                    path,
-                                    size,
-                                } => {
-                                    if upload_state.is_some() {
-                                        let _ = packet_tx.send(WsOutbound::Packet(
-                                            ServerPacket::ServerError(ServerErrorPacket {
-                                                err: ServerError::UploadAlreadyInProgress,
-                                                message: "upload already in progress".into(),
-                                            }),
-                                        ));
-                                        continue;
-                                    }
-
-                                    let temp_file = tempfile::NamedTempFile::new()
-                                        .map_err(|e| e.to_string())
-                                        .expect("Temp file creation should always succeed");
-
-                                    upload_state = Some(UploadState {
-                                        container_id,
-                                        temp_file,
-                                        container_path: "/src".to_string(),
-                                        file_name: path,
-                                        remaining: size,
-                                    });
-                                }
+                Message::Binary(bytes) => {
+                    if let Err(e) = self.handle_binary(&bytes, &mut upload_state).await {
+                        report_error(&packet_tx, &e);
+                        if e.closes_connection() {
+                            break;
+                        }
+                    }
+                }
 
-                                ClientPacket::UploadFinish { .. } => {
-                                    if let Some(state) = upload_state.take() {
-                                        let docker = self.server_state.lock().await.docker.clone();
-
-                                        let container = {
-                                            let state_lock = self.server_state.lock().await;
-                                            state_lock.containers.get(&state.container_id).cloned()
-                                        };
-
-                                        if let Some(container) = container {
-                                            let docker = docker.clone();
-
-                                            let temp_file = state.temp_file;
-                                            let container_path = state.container_path;
-                                            let file_name = state.file_name;
-
-                                            tokio::spawn(async move {
-                                                if let Err(e) = container
-                                                    .upload_file(
-                                                        &docker,
-                                                        temp_file.path(),
-                                                        &container_path,
-                                                        &file_name,
-                                                    )
-                                                    .await
-                                                {
-                                                    eprintln!("upload failed: {e}");
-                                                }
-                                            });
-                                        } else {
-                                            eprintln!("Container not found for upload");
-                                        }
-                                    }
-                                }
+                Message::Ping(p) => {
+                    let _ = packet_tx.send(WsOutbound::Pong(p.to_vec()));
+                }
 
-                                other => {
-                                    if let Err(e) =
-                                        this.handle_client_packet(other, packet_tx.clone()).await
-                                    {
-                                        this.logger.err(e, "~!");
-                                        break;
-                                    }
-                                }
-                            }
-                        }
+                Message::Close(_) => break,
+                Message::Pong(_) => {}
+            }
+        }
+
+        let (backend, owned_containers) = {
+            let mut state = self.server_state.lock().await;
+            state.connections.remove(&self.connection_id);
+
+            (
+                Arc::clone(&state.backend),
+                state.take_owned_containers(self.connection_id, "client disconnected"),
+            )
+        };
+        METRICS.active_connections.dec();
+
+        for container_id in owned_containers {
+            match container::remove_container(&*backend, &container_id).await {
+                Ok(()) => info!(container_id, "removed container of closed connection"),
+                Err(e) => error!(container_id, "failed to remove container: {e}"),
+            }
+        }
 
-                        Message::Binary(bytes) => {
-                            if let Some(state) = upload_state.as_mut() {
-                                let mut file = tokio::fs::OpenOptions::new()
-                                    .write(true)
-                                    .open(state.temp_file.path())
+        drop(packet_tx);
+        let _ = writer.await;
+    }
+
+    async fn handle_text(
+        &self,
+        text: &str,
+        upload_state: &mut Option<UploadState>,
+        packet_tx: &UnboundedSender<WsOutbound>,
+    ) -> Result<(), BoaError> {
+        let packet = serde_json::from_str::<ClientPacket>(text)
+            .map_err(|e| BoaError::Refused(ServerError::InvalidJson, e.to_string()))?;
+
+        self.handle_client_packet(packet, upload_state, packet_tx.clone())
+            .await
+    }
+
+    async fn start_upload(
+        &self,
+        container_id: String,
+        path: String,
+        size: u64,
+        upload_state: &mut Option<UploadState>,
+    ) -> Result<(), BoaError> {
+        if upload_state.is_some() {
+            return Err(BoaError::Refused(
+                ServerError::UploadAlreadyInProgress,
+                "upload already in progress".to_string(),
+            ));
+        }
+
+        if self.owned_container(&container_id).await.is_none() {
+            return Err(invalid_container_id(&container_id));
+        }
+
+        let (max_upload_bytes, container_path) = {
+            let state = self.server_state.lock().await;
+            (
+                state.config.connections.max_upload_bytes,
+                state.config.containers.working_dir.clone(),
+            )
+        };
+
+        if max_upload_bytes > 0 && size > max_upload_bytes {
+            return Err(BoaError::Refused(
+                ServerError::MessageTooLarge,
+                format!("upload of {size} bytes is over the limit of {max_upload_bytes} bytes"),
+            ));
+        }
+
+        let temp_file = tempfile::NamedTempFile::new().map_err(|e| {
+            BoaError::Refused(
+                ServerError::TempFileCreationFailed,
+                format!("failed to create a temporary file: {e}"),
+            )
+        })?;
+
+        *upload_state = Some(UploadState {
+            container_id,
+            temp_file,
+            container_path,
+            file_name: path,
+            remaining: size,
+        });
+
+        Ok(())
+    }
+
+    async fn finish_upload(
+        &self,
+        upload_state: &mut Option<UploadState>,
+        packet_tx: &UnboundedSender<WsOutbound>,
+    ) -> Result<(), BoaError> {
+        if let Some(state) = upload_state.take() {
+            let backend = Arc::clone(&self.server_state.lock().await.backend);
+
+            let container = self.owned_container(&state.container_id).await;
+
+            if let Some(container) = container {
+                let temp_file = state.temp_file;
+                let container_path = state.container_path;
+                let file_name = state.file_name;
+                let server_state = Arc::clone(&self.server_state);
+                let tx = packet_tx.clone();
+
+                tokio::spawn(
+                    async move {
+                        match container
+                            .upload_file(&*backend, temp_file.path(), &container_path, &file_name)
+                            .await
+                        {
+                            Ok(sha256) => {
+                                if let Some(container) = server_state
+                                    .lock()
                                     .await
-                                    .expect("Temporary files should always exist");
-
-                                if let Err(e) = file.write_all(&bytes).await {
-                                    let _ = packet_tx.send(WsOutbound::Packet(
-                                        ServerPacket::ServerError(ServerErrorPacket {
-                                            err: ServerError::UploadFailed,
-                                            message: e.to_string(),
-                                        }),
-                                    ));
-                                    upload_state = None;
-                                    continue;
+                                    .containers
+                                    .get_mut(&container.container_id)
+                                {
+                                    container.file_hashes.insert(file_name, sha256);
                                 }
-
-                                state.remaining =
-                                    state.remaining.saturating_sub(bytes.len() as u64);
-                            } else {
-                                let _ = packet_tx.send(WsOutbound::Packet(
-                                    ServerPacket::ServerError(ServerErrorPacket {
-                                        err: ServerError::UnexpectedBinaryFrame,
-                                        message: "binary frame without upload".into(),
-                                    }),
-                                ));
+                            }
+                            Err(e) => {
+                                report_error(&tx, &e.or(ServerError::UploadFailed));
                             }
                         }
+                    }
+                    .in_current_span(),
+                );
+            } else {
+                // closed while the upload was in flight
+                return Err(invalid_container_id(&state.container_id));
+            }
+        }
 
-                        Message::Ping(p) => {
-                            let _ = packet_tx.send(WsOutbound::Pong(p.to_vec()));
-                        }
+        Ok(())
+    }
 
-                        Message::Close(_) => break,
-                        _ => {}
-                    }
-                }
+    async fn handle_binary(
+        &self,
+        bytes: &[u8],
+        upload_state: &mut Option<UploadState>,
+    ) -> Result<(), BoaError> {
+        let Some(state) = upload_state.as_mut() else {
+            return Err(BoaError::Refused(
+                ServerError::UnexpectedBinaryFrame,
+                "binary frame without upload".to_string(),
+            ));
+        };
+
+        // the declared size was checked against the upload limit
+        if bytes.len() as u64 > state.remaining {
+            return Err(BoaError::Refused(
+                ServerError::MessageTooLarge,
+                "upload is larger than its declared size".to_string(),
+            ));
+        }
 
-                drop(packet_tx);
-                let _ = writer.await;
-            }
-        })
+        let written = match tokio::fs::OpenOptions::new()
+            .append(true)
+            .open(state.temp_file.path())
+            .await
+        {
+            Ok(mut file) => file.write_all(bytes).await,
+            Err(e) => Err(e),
+        };
+
+        if let Err(e) = written {
+            *upload_state = None;
+            return Err(BoaError::Refused(ServerError::UploadFailed, e.to_string()));
+        }
+
+        METRICS.upload_bytes.inc_by(bytes.len() as u64);
+        state.remaining = state.remaining.saturating_sub(bytes.len() as u64);
+
+        Ok(())
     }
 }
 
@@ -229,134 +455,540 @@ impl BoaWsRoute {
     async fn handle_client_packet(
         &self,
         packet: ClientPacket,
+        upload_state: &mut Option<UploadState>,
         tx: UnboundedSender<WsOutbound>,
-    ) -> Result<(), String> {
+    ) -> Result<(), BoaError> {
         match packet {
-            ClientPacket::ProcessOpen(_) => {
-                let (container_id, container) = {
-                    let state = self.server_state.lock().await;
-                    BoaContainer::new(&state.docker, state.container_prefix.clone()).await?
+            ClientPacket::UploadStart {
+                container_id,
+                path,
+                size,
+            } => {
+                self.start_upload(container_id, path, size, upload_state)
+                    .await?;
+            }
+            ClientPacket::UploadFinish { .. } => self.finish_upload(upload_state, &tx).await?,
+            ClientPacket::ProcessOpen(pkt) => self.handle_open(pkt, tx).await?,
+            ClientPacket::ProcessControlSignal(pkt) => {
+                self.handle_control_signal(pkt, tx).await?;
+            }
+            ClientPacket::ProcessClose(pkt) => {
+                let (backend, identity) = {
+                    let mut state = self.server_state.lock().await;
+
+                    if state
+                        .owned_container(self.connection_id, &pkt.container_id)
+                        .is_none()
+                    {
+                        return Err(invalid_container_id(&pkt.container_id));
+                    }
+
+                    state.containers.remove(&pkt.container_id);
+                    state.mark_killed(&pkt.container_id, "container closed by the client");
+                    (Arc::clone(&state.backend), self.identity(&state))
                 };
 
-                self.server_state
-                    .lock()
+                let success = container::remove_container(&*backend, &pkt.container_id)
                     .await
-                    .containers
-                    .insert(container_id.clone(), container);
+                    .is_ok();
 
-                tx.send(WsOutbound::Packet(ServerPacket::ProcessOpenResult(
-                    ProcessOpenResultPacket { container_id },
+                tx.send(WsOutbound::Packet(ServerPacket::ProcessCloseResult(
+                    ProcessCloseResultPacket { success },
                 )))
                 .ok();
+                quota::send_status(&self.server_state, &identity, &tx).await;
             }
-            ClientPacket::ProcessControlSignal(pkt) => {
-                let (mut container, docker) = {
-                    let state = self.server_state.lock().await;
-                    (
-                        state
-                            .containers
-                            .get(&pkt.container_id)
-                            .cloned()
-                            .ok_or("invalid container id")?,
-                        state.docker.clone(),
-                    )
+        }
+
+        Ok(())
+    }
+
+    async fn handle_open(
+        &self,
+        pkt: ProcessOpenPacket,
+        tx: UnboundedSender<WsOutbound>,
+    ) -> Result<(), BoaError> {
+        let timer = METRICS.open_duration.start_timer();
+
+        // the slot is held from the check on, so concurrent opens can't go over the quota
+        let (backend, config, identity) = {
+            let mut state = self.server_state.lock().await;
+
+            if state.draining {
+                return Err(server_draining());
+            }
+
+            let identity = self.identity(&state);
+            quota::check_open(&state.quota_status(&identity))?;
+            state.quotas.reserve_open(&identity);
+
+            (Arc::clone(&state.backend), state.config.clone(), identity)
+        };
+
+        let opened = self.open_container(&*backend, &config, pkt).await;
+
+        let (container_id, draining) = {
+            let mut state = self.server_state.lock().await;
+            state.quotas.release_open(&identity);
+            let (container_id, mut container) = opened?;
+
+            if state.draining {
+                (container_id, true)
+            } else {
+                container.owner = Some(self.connection_id);
+                container.owner_identity = Some(identity.clone());
+                state.containers.insert(container_id.clone(), container);
+                (container_id, false)
+            }
+        };
+
+        // a drain that started meanwhile already removed the containers it knew of
+        if draining {
+            container::remove_container(&*backend, &container_id)
+                .await
+                .ok();
+
+            return Err(server_draining());
+        }
+
+        timer.observe_duration();
+
+        tx.send(WsOutbound::Packet(ServerPacket::ProcessOpenResult(
+            ProcessOpenResultPacket { container_id },
+        )))
+        .ok();
+        quota::send_status(&self.server_state, &identity, &tx).await;
+
+        Ok(())
+    }
+
+    /// Creates the container `pkt` asks for, or takes a warm one from the pool
+    async fn open_container(
+        &self,
+        backend: &dyn ContainerBackend,
+        config: &Config,
+        pkt: ProcessOpenPacket,
+    ) -> Result<(String, BoaContainer), BoaError> {
+        let prefix = &config.containers.prefix;
+
+        let image = match (pkt.snapshot, pkt.runtime) {
+            (Some(tag), _) => {
+                if !snapshot::exists(backend, prefix, &tag).await {
+                    return Err(BoaError::Refused(
+                        ServerError::SnapshotNotFound,
+                        format!("snapshot {tag:?} does not exist"),
+                    ));
+                }
+
+                snapshot::image(prefix, &tag)
+            }
+            (None, runtime) => {
+                let Some(image) = config.runtime_image(runtime.as_deref()) else {
+                    return Err(BoaError::Refused(
+                        ServerError::InvalidRuntime,
+                        format!("runtime {runtime:?} is not configured"),
+                    ));
                 };
 
-                match pkt.control_signal {
-                    ProcessControlSignal::Start => {
-                        tx.send(WsOutbound::Packet(ServerPacket::ProcessEvent(
-                            ProcessEventPacket::Started,
-                        )))
-                        .ok();
+                image.to_string()
+            }
+        };
 
-                        tokio::spawn(async move {
-                            match container.start(&docker).await {
-                                Err(e) => {
-                                    tx.send(WsOutbound::Packet(ServerPacket::ServerError(
-                                        ServerErrorPacket {
-                                            err: ServerError::ProcessStartFailed,
-                                            message: format!("failed to start: {e}"),
-                                        },
-                                    )))
-                                    .ok();
-                                }
-                                _ => {
-                                    tx.send(WsOutbound::Packet(ServerPacket::ProcessEvent(
-                                        ProcessEventPacket::Started,
-                                    )))
-                                    .ok();
-                                }
-                            }
-                        });
-                    }
+        if let Some(container) = self.server_state.lock().await.pool.take(&image) {
+            return Ok((container.container_id.clone(), container));
+        }
 
-                    ProcessControlSignal::Exec(file_path) => {
-                        let _ = tx.send(WsOutbound::Packet(ServerPacket::ProcessEvent(
-                            ProcessEventPacket::Started,
-                        )));
-
-                        let docker = docker.clone();
-                        tokio::spawn(async move {
-                            match container.exec_file(&docker, file_path, tx.clone()).await {
-                                Ok(exit_code) => {
-                                    let _ =
-                                        tx.send(WsOutbound::Packet(ServerPacket::ProcessEvent(
-                                            ProcessEventPacket::Finished { exit_code },
-                                        )));
-                                }
-                                Err(e) => {
-                                    let _ = tx.send(WsOutbound::Packet(ServerPacket::ServerError(
-                                        ServerErrorPacket {
-                                            err: ServerError::ProcessStartFailed,
-                                            message: e,
-                                        },
-                                    )));
-                                }
+        BoaContainer::new(backend, config, &image)
+            .await
+            .map_err(|e| e.or(ServerError::ProcessStartFailed))
+    }
+
+    async fn handle_control_signal(
+        &self,
+        pkt: ProcessControlSignalPacket,
+        tx: UnboundedSender<WsOutbound>,
+    ) -> Result<(), BoaError> {
+        let Some(mut container) = self.owned_container(&pkt.container_id).await else {
+            return Err(invalid_container_id(&pkt.container_id));
+        };
+        let backend = Arc::clone(&self.server_state.lock().await.backend);
+
+        match pkt.control_signal {
+            ProcessControlSignal::Start => {
+                tx.send(WsOutbound::Packet(ServerPacket::ProcessEvent(
+                    ProcessEventPacket::Started,
+                )))
+                .ok();
+
+                tokio::spawn(
+                    async move {
+                        match container.start(&*backend).await {
+                            Err(e) => {
+                                report_error(&tx, &e.or(ServerError::ProcessStartFailed));
                             }
-                        });
+                            _ => {
+                                tx.send(WsOutbound::Packet(ServerPacket::ProcessEvent(
+                                    ProcessEventPacket::Started,
+                                )))
+                                .ok();
+                            }
+                        }
                     }
+                    .in_current_span(),
+                );
+            }
 
-                    ProcessControlSignal::Interrupt => {
-                        container
-                            .signal(&docker, ProcessControlSignal::Interrupt)
-                            .await?;
-                    }
+            ProcessControlSignal::Exec(file_path) => {
+                self.spawn_exec(container, backend, ExecKind::File(file_path), tx)
+                    .await;
+            }
 
-                    ProcessControlSignal::Terminate => {
-                        container
-                            .signal(&docker, ProcessControlSignal::Terminate)
-                            .await?;
-                    }
-                }
+            ProcessControlSignal::InstallRequirements(requirements_path) => {
+                self.spawn_exec(
+                    container,
+                    backend,
+                    ExecKind::Requirements(requirements_path),
+                    tx,
+                )
+                .await;
             }
-            ClientPacket::ProcessClose(pkt) => {
-                let docker = self.server_state.lock().await.docker.clone();
 
+            ProcessControlSignal::Interrupt => {
                 self.server_state
                     .lock()
                     .await
-                    .containers
-                    .remove(&pkt.container_id);
-
-                let success = docker
-                    .remove_container(
-                        &pkt.container_id,
-                        Some(RemoveContainerOptions {
-                            force: true,
-                            ..Default::default()
-                        }),
-                    )
+                    .mark_killed(&pkt.container_id, "interrupted by the client");
+
+                container.signal(&*backend, StopSignal::Interrupt).await?;
+            }
+
+            ProcessControlSignal::Terminate => {
+                self.server_state
+                    .lock()
                     .await
-                    .is_ok();
+                    .mark_killed(&pkt.container_id, "terminated by the client");
 
-                tx.send(WsOutbound::Packet(ServerPacket::ProcessCloseResult(
-                    ProcessCloseResultPacket { success },
+                container.signal(&*backend, StopSignal::Terminate).await?;
+            }
+
+            ProcessControlSignal::Snapshot(tag) => {
+                self.spawn_snapshot(container, backend, tag, tx).await;
+            }
+
+            ProcessControlSignal::Pause => {
+                container.pause(&*backend).await?;
+                self.set_paused(&pkt.container_id, true).await;
+
+                tx.send(WsOutbound::Packet(ServerPacket::ProcessEvent(
+                    ProcessEventPacket::Paused,
+                )))
+                .ok();
+            }
+
+            ProcessControlSignal::Resume => {
+                container.resume(&*backend).await?;
+                self.set_paused(&pkt.container_id, false).await;
+
+                tx.send(WsOutbound::Packet(ServerPacket::ProcessEvent(
+                    ProcessEventPacket::Resumed,
                 )))
                 .ok();
             }
-            ClientPacket::UploadStart { .. } | ClientPacket::UploadFinish { .. } => unreachable!(),
         }
 
         Ok(())
     }
+
+    fn exec_record(
+        &self,
+        exec_id: Uuid,
+        container: &BoaContainer,
+        kind: &ExecKind,
+        code_sha256: Option<String>,
+    ) -> ExecRecord {
+        ExecRecord {
+            exec_id: exec_id.to_string(),
+            connection_id: self.connection_id.to_string(),
+            remote_addr: Some(self.remote_addr.to_string()),
+            identity: None,
+            container_id: container.container_id.clone(),
+            image: container.image.clone(),
+            kind: kind.name().to_string(),
+            path: kind.path().to_string(),
+            code_sha256,
+            started_at: audit::now_millis(),
+            finished_at: None,
+            outcome: ExecOutcome::Running,
+            exit_code: None,
+            kill_reason: None,
+            error: None,
+            stdout_bytes: 0,
+            stderr_bytes: 0,
+        }
+    }
+
+    async fn spawn_exec(
+        &self,
+        container: BoaContainer,
+        backend: SharedBackend,
+        kind: ExecKind,
+        tx: UnboundedSender<WsOutbound>,
+    ) {
+        let exec_id = Uuid::new_v4();
+
+        let (timeout_secs, audit, identity, code_sha256) = {
+            let mut state = self.server_state.lock().await;
+
+            let identity = self.identity(&state);
+            if let Err(e) = quota::check_exec(&state.quota_status(&identity)) {
+                report_error(&tx, &e);
+                return;
+            }
+            state.quotas.record_exec_start(&identity);
+
+            state.execs.insert(
+                exec_id,
+                RunningExec {
+                    container_id: container.container_id.clone(),
+                    connection_id: self.connection_id,
+                    kind: kind.clone(),
+                    started_at: SystemTime::now(),
+                    kill_reason: None,
+                },
+            );
+
+            // the stored copy of the container has the hashes of uploads that finished since
+            let code_sha256 = state
+                .containers
+                .get(&container.container_id)
+                .and_then(|stored| {
+                    stored.file_hash(kind.path(), &state.config.containers.working_dir)
+                });
+
+            (
+                state.config.limits.exec_timeout_secs,
+                state.audit.clone(),
+                identity,
+                code_sha256,
+            )
+        };
+
+        let _ = tx.send(WsOutbound::Packet(ServerPacket::ProcessEvent(
+            ProcessEventPacket::Started,
+        )));
+
+        let mut record = self.exec_record(exec_id, &container, &kind, code_sha256);
+        record.identity = Some(identity.clone());
+
+        let exec_span = info_span!(
+            "exec",
+            %exec_id,
+            container_id = container.container_id,
+            kind = kind.name(),
+            path = kind.path()
+        );
+
+        let server_state = Arc::clone(&self.server_state);
+        tokio::spawn(
+            async move {
+                if let Some(audit) = &audit
+                    && let Err(e) = audit.record_start(record).await
+                {
+                    error!("failed to record exec in the audit log: {e}");
+                }
+
+                let started_at = Instant::now();
+                let result = container
+                    .exec(&*backend, exec_id, &kind, timeout_secs, tx.clone())
+                    .await;
+
+                quota::charge_cpu(&server_state, &*backend, &container, started_at.elapsed()).await;
+
+                let kill_reason = server_state
+                    .lock()
+                    .await
+                    .execs
+                    .remove(&exec_id)
+                    .and_then(|exec| exec.kill_reason);
+
+                if let Some(audit) = &audit
+                    && let Err(e) = audit
+                        .record_finish(
+                            exec_id.to_string(),
+                            ExecFinish::new(&result, timeout_secs, kill_reason),
+                        )
+                        .await
+                {
+                    error!("failed to record exec in the audit log: {e}");
+                }
+
+                send_exec_result(result, timeout_secs, &tx);
+                quota::send_status(&server_state, &identity, &tx).await;
+            }
+            .instrument(exec_span),
+        );
+    }
+
+    async fn owned_container(&self, container_id: &str) -> Option<BoaContainer> {
+        self.server_state
+            .lock()
+            .await
+            .owned_container(self.connection_id, container_id)
+            .cloned()
+    }
+
+    /// Identity the connection authenticated as, quotas are shared by all of its connections
+    fn identity(&self, state: &ServerState) -> String {
+        state.connections.get(&self.connection_id).map_or_else(
+            || "anonymous".to_string(),
+            |connection| connection.identity.name.clone(),
+        )
+    }
+
+    async fn spawn_snapshot(
+        &self,
+        container: BoaContainer,
+        backend: SharedBackend,
+        tag: String,
+        tx: UnboundedSender<WsOutbound>,
+    ) {
+        let (container_prefix, identity, limits) = {
+            let state = self.server_state.lock().await;
+            (
+                state.config.containers.prefix.clone(),
+                self.identity(&state),
+                state.config.limits,
+            )
+        };
+
+        tokio::spawn(
+            async move {
+                match snapshot::create(
+                    &*backend,
+                    &container,
+                    &container_prefix,
+                    &tag,
+                    &identity,
+                    limits,
+                )
+                .await
+                {
+                    Ok(snapshot) => {
+                        tx.send(WsOutbound::Packet(ServerPacket::ProcessSnapshotResult(
+                            ProcessSnapshotResultPacket {
+                                snapshot: snapshot.tag,
+                                size: snapshot.size,
+                            },
+                        )))
+                        .ok();
+                    }
+                    Err(e) => {
+                        report_error(&tx, &e.or(ServerError::SnapshotFailed));
+                    }
+                }
+            }
+            .in_current_span(),
+        );
+    }
+
+    async fn set_paused(&self, container_id: &str, paused: bool) {
+        if let Some(container) = self
+            .server_state
+            .lock()
+            .await
+            .containers
+            .get_mut(container_id)
+        {
+            container.paused = paused;
+        }
+    }
+}
+
+fn send_exec_result(
+    result: Result<ExecOutput, BoaError>,
+    timeout_secs: u64,
+    tx: &UnboundedSender<WsOutbound>,
+) {
+    match result.map(|output| output.result(timeout_secs)) {
+        Ok(Ok(exit_code)) => {
+            let _ = tx.send(WsOutbound::Packet(ServerPacket::ProcessEvent(
+                ProcessEventPacket::Finished { exit_code },
+            )));
+        }
+        // the exec ran and ended, clients get an event for it rather than an error
+        Ok(Err(e)) => {
+            METRICS.exec_timeouts.inc();
+            warn!(timeout_secs, "{e}");
+            let _ = tx.send(WsOutbound::Packet(ServerPacket::ProcessEvent(
+                ProcessEventPacket::TimedOut,
+            )));
+        }
+        Err(e) => {
+            report_error(tx, &e.or(ServerError::ProcessStartFailed));
+        }
+    }
+}
+
+fn server_draining() -> BoaError {
+    BoaError::Refused(
+        ServerError::ServerDraining,
+        "server is draining, not accepting new processes".to_string(),
+    )
+}
+
+/// Sent for ids that do not exist and for containers of other connections alike, so ids of
+/// other clients cannot be probed
+fn invalid_container_id(container_id: &str) -> BoaError {
+    BoaError::Refused(
+        ServerError::InvalidContainerId,
+        format!("no container with id {container_id:?}"),
+    )
+}
+
+/// Sends the packets of a connection until it is closed
+async fn write_packets(
+    mut ws_tx: SplitSink<WebSocket, Message>,
+    mut packet_rx: UnboundedReceiver<WsOutbound>,
+) {
+    while let Some(msg) = packet_rx.recv().await {
+        match msg {
+            WsOutbound::Packet(packet) => {
+                let Ok(text) = serde_json::to_string(&packet) else {
+                    continue;
+                };
+                if ws_tx.send(Message::Text(text.into())).await.is_err() {
+                    break;
+                }
+            }
+            WsOutbound::Pong(p) => {
+                if ws_tx.send(Message::Pong(p.into())).await.is_err() {
+                    break;
+                }
+            }
+            WsOutbound::Close => {
+                let _ = ws_tx.send(Message::Close(None)).await;
+                break;
+            }
+        }
+    }
+}
+
+/// Sends `e` to the client. A broken limit also closes the socket, and the caller stops
+/// reading from it.
+fn report_error(tx: &UnboundedSender<WsOutbound>, e: &BoaError) {
+    tx.send(WsOutbound::error(e.server_error(), e.message()))
+        .ok();
+
+    if e.closes_connection() {
+        warn!("closing connection: {e}");
+        tx.send(WsOutbound::Close).ok();
+        return;
+    }
+
+    match e {
+        BoaError::Refused(..) | BoaError::NotFound(_) | BoaError::NotStarted(_) => {
+            warn!("request failed: {e}");
+        }
+        _ => error!("request failed: {e}"),
+    }
 }
@@ -0,0 +1,116 @@
+use std::{sync::Arc, time::Duration};
+
+use boa_core::packets::server::{ServerPacket, event::ServerEventPacket};
+use tokio::{
+    signal::unix::{SignalKind, signal},
+    time::{Instant, sleep},
+};
+use tracing::{error, info, instrument, warn};
+
+use crate::{container, routes::ws::WsOutbound, state::ShareableServerState};
+
+/// Resolves once the server receives SIGTERM, SIGINT or a drain request from the admin api
+pub async fn drain_requested(server_state: ShareableServerState) {
+    let drain_notify = Arc::clone(&server_state.lock().await.drain_notify);
+
+    let mut sigterm = match signal(SignalKind::terminate()) {
+        Ok(sigterm) => sigterm,
+        Err(e) => {
+            error!("failed to install SIGTERM handler: {e}");
+            drain_notify.notified().await;
+            return;
+        }
+    };
+
+    tokio::select! {
+        _ = sigterm.recv() => info!("received SIGTERM"),
+        _ = tokio::signal::ctrl_c() => info!("received SIGINT"),
+        () = drain_notify.notified() => info!("received drain request"),
+    }
+}
+
+/// Rejects new processes, waits for running execs up to the drain timeout,
+/// then removes every container owned by this server and closes all connections
+#[instrument(name = "drain", skip_all)]
+pub async fn drain(server_state: ShareableServerState) {
+    let drain_timeout = {
+        let mut state = server_state.lock().await;
+        state.draining = true;
+
+        let packet = ServerPacket::ServerEvent(ServerEventPacket::Draining {
+            deadline_secs: state.config.server.drain_timeout_secs,
+        });
+
+        for connection in state.connections.values() {
+            connection
+                .sender
+                .send(WsOutbound::Packet(packet.clone()))
+                .ok();
+        }
+
+        Duration::from_secs(state.config.server.drain_timeout_secs)
+    };
+
+    info!(
+        drain_timeout_secs = drain_timeout.as_secs(),
+        "draining, waiting for running execs..."
+    );
+
+    let deadline = Instant::now() + drain_timeout;
+
+    loop {
+        let running_execs = server_state.lock().await.execs.len();
+
+        if running_execs == 0 {
+            break;
+        }
+
+        if Instant::now() >= deadline {
+            warn!(
+                running_execs,
+                "drain deadline reached with execs still running"
+            );
+            break;
+        }
+
+        sleep(Duration::from_millis(250)).await;
+    }
+
+    let (backend, container_ids, connections) = {
+        let mut state_lock = server_state.lock().await;
+        let state = &mut *state_lock;
+
+        for exec in state.execs.values_mut() {
+            exec.kill_reason
+                .get_or_insert_with(|| "server shut down".to_string());
+        }
+
+        (
+            Arc::clone(&state.backend),
+            state
+                .containers
+                .drain()
+                .map(|(id, _)| id)
+                .chain(state.pool.drain())
+                .collect::<Vec<_>>(),
+            state
+                .connections
+                .drain()
+                .map(|(_, connection)| connection)
+                .collect::<Vec<_>>(),
+        )
+    };
+
+    for container_id in container_ids {
+        match container::remove_container(&*backend, &container_id).await {
+            Ok(()) => info!(container_id, "removed container"),
+            Err(e) => error!(container_id, "failed to remove container: {e}"),
+        }
+    }
+
+    for connection in connections {
+        connection.disconnect();
+    }
+
+    info!("drain finished");
+}
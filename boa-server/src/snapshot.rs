@@ -0,0 +1,138 @@
+use boa_core::packets::server::error::ServerError;
+use uuid::Uuid;
+
+use crate::{
+    backend::ContainerBackend, config::LimitsConfig, container::BoaContainer, error::BoaError,
+};
+
+pub struct Snapshot {
+    pub tag: String,
+    pub size: u64,
+}
+
+/// Image repository that snapshots of containers with `container_prefix` are committed to
+pub fn repository(container_prefix: &str) -> String {
+    format!("{container_prefix}-snapshot")
+}
+
+/// Repository snapshots are committed to until their size is checked
+fn pending_repository(container_prefix: &str) -> String {
+    format!("{}-pending", repository(container_prefix))
+}
+
+pub fn image(container_prefix: &str, tag: &str) -> String {
+    format!("{}:{tag}", repository(container_prefix))
+}
+
+/// Checks that `tag` is a valid docker image tag
+pub fn validate_tag(tag: &str) -> Result<(), BoaError> {
+    let valid = !tag.is_empty()
+        && tag.len() <= 128
+        && !tag.starts_with(['.', '-'])
+        && tag
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'));
+
+    if valid {
+        Ok(())
+    } else {
+        Err(BoaError::Refused(
+            ServerError::SnapshotFailed,
+            format!("invalid snapshot tag {tag:?}"),
+        ))
+    }
+}
+
+pub async fn exists(backend: &dyn ContainerBackend, container_prefix: &str, tag: &str) -> bool {
+    backend
+        .image_size(&image(container_prefix, tag))
+        .await
+        .is_ok()
+}
+
+/// Refuses to replace `image` unless it was committed by `owner`. Snapshots the server did not
+/// commit were published by an admin and are never replaced.
+async fn check_owner(
+    backend: &dyn ContainerBackend,
+    image: &str,
+    tag: &str,
+    owner: &str,
+) -> Result<(), BoaError> {
+    match backend.image_owner(image).await {
+        Ok(Some(image_owner)) if image_owner == owner => Ok(()),
+        Ok(_) => Err(BoaError::Refused(
+            ServerError::SnapshotFailed,
+            format!("snapshot {tag} belongs to someone else, use another tag"),
+        )),
+        Err(BoaError::NotFound(_)) => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Commits `container` to a snapshot tagged `tag` owned by `owner`, enforcing the count and size
+/// limits. The commit is checked under a pending tag first, so an oversized one keeps the
+/// previous snapshot.
+pub async fn create(
+    backend: &dyn ContainerBackend,
+    container: &BoaContainer,
+    container_prefix: &str,
+    tag: &str,
+    owner: &str,
+    limits: LimitsConfig,
+) -> Result<Snapshot, BoaError> {
+    validate_tag(tag)?;
+
+    let image = image(container_prefix, tag);
+    check_owner(backend, &image, tag, owner).await?;
+
+    let tags = backend.image_tags(&repository(container_prefix)).await?;
+
+    if !tags.contains(&image) && tags.len() >= limits.snapshot_max_count {
+        return Err(BoaError::Refused(
+            ServerError::SnapshotFailed,
+            format!(
+                "snapshot limit of {} reached, remove a snapshot first",
+                limits.snapshot_max_count
+            ),
+        ));
+    }
+
+    let pending_repository = pending_repository(container_prefix);
+    let pending_tag = Uuid::new_v4().simple().to_string();
+    let pending_image = format!("{pending_repository}:{pending_tag}");
+
+    container
+        .commit(backend, &pending_repository, &pending_tag, owner)
+        .await?;
+
+    let result = async {
+        let size = backend.image_size(&pending_image).await?;
+        let max_size_bytes = limits.snapshot_max_size_mb * 1024 * 1024;
+
+        if size > max_size_bytes {
+            return Err(BoaError::Refused(
+                ServerError::SnapshotFailed,
+                format!("snapshot is {size} bytes, over the limit of {max_size_bytes} bytes"),
+            ));
+        }
+
+        // another identity may have taken the tag during the commit
+        check_owner(backend, &image, tag, owner).await?;
+
+        backend
+            .retag(&pending_image, &repository(container_prefix), tag)
+            .await?;
+
+        Ok(Snapshot {
+            tag: tag.to_string(),
+            size,
+        })
+    }
+    .await;
+
+    if result.is_err() {
+        backend.remove_image(&pending_image).await.ok();
+    }
+
+    result
+}
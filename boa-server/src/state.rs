@@ -1,30 +1,155 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, net::SocketAddr, sync::Arc, time::SystemTime};
 
-use bollard::Docker;
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, Notify, mpsc::UnboundedSender};
+use uuid::Uuid;
 
-use crate::container::BoaContainer;
+use crate::{
+    audit::AuditLog,
+    auth::{AuthMethod, Authenticator, Identity},
+    backend::SharedBackend,
+    config::{Config, ConfigOverrides},
+    container::{BoaContainer, ExecKind},
+    pool::ContainerPool,
+    quota::QuotaTracker,
+    routes::ws::WsOutbound,
+};
 
 pub type ShareableServerState = Arc<Mutex<ServerState>>;
 
+#[derive(Clone)]
+pub struct Connection {
+    pub sender: UnboundedSender<WsOutbound>,
+    pub remote_addr: SocketAddr,
+    pub identity: Identity,
+    pub opened_at: SystemTime,
+    /// Ends the read loop of the connection, even if the client never answers the close frame
+    pub disconnect_notify: Arc<Notify>,
+}
+
+impl Connection {
+    pub fn new(
+        sender: UnboundedSender<WsOutbound>,
+        remote_addr: SocketAddr,
+        identity: Identity,
+    ) -> Self {
+        Self {
+            sender,
+            remote_addr,
+            identity,
+            opened_at: SystemTime::now(),
+            disconnect_notify: Arc::new(Notify::new()),
+        }
+    }
+
+    pub fn disconnect(&self) {
+        self.sender.send(WsOutbound::Close).ok();
+        self.disconnect_notify.notify_one();
+    }
+}
+
+/// An exec that is running, or about to be started, in one of the containers
+#[derive(Clone)]
+pub struct RunningExec {
+    pub container_id: String,
+    pub connection_id: Uuid,
+    pub kind: ExecKind,
+    pub started_at: SystemTime,
+    /// Why the exec was stopped early, recorded in the audit log
+    pub kill_reason: Option<String>,
+}
+
 #[derive(Clone)]
 pub struct ServerState {
     pub containers: HashMap<String, BoaContainer>,
-    pub docker: Docker,
+    pub connections: HashMap<Uuid, Connection>,
+    pub backend: SharedBackend,
+    pub pool: ContainerPool,
 
-    // Environment vars
-    pub server_port: u32,
-    pub container_prefix: String,
+    pub execs: HashMap<Uuid, RunningExec>,
+    pub quotas: QuotaTracker,
+    pub draining: bool,
+    pub drain_notify: Arc<Notify>,
+
+    pub audit: Option<AuditLog>,
+    pub auth: Authenticator,
+
+    pub config: Config,
+    /// Environment and command line settings the config is reloaded with
+    pub config_overrides: ConfigOverrides,
 }
 
 impl ServerState {
-    pub fn new(docker: Docker, server_port: u32, container_prefix: String) -> Self {
+    pub fn new(
+        backend: SharedBackend,
+        audit: Option<AuditLog>,
+        auth: Authenticator,
+        config: Config,
+        config_overrides: ConfigOverrides,
+    ) -> Self {
         Self {
             containers: HashMap::new(),
-            docker,
+            connections: HashMap::new(),
+            backend,
+            pool: ContainerPool::new(&config.runtime_images(), config.containers.pool_size),
+
+            execs: HashMap::new(),
+            quotas: QuotaTracker::default(),
+            draining: false,
+            drain_notify: Arc::new(Notify::new()),
+
+            audit,
+            auth,
+
+            config,
+            config_overrides,
+        }
+    }
+}
+
+impl ServerState {
+    /// The container if `connection_id` may use it: the connection opened it, or authenticated
+    /// as the same identity as the one that did. Anonymous connections only get their own.
+    pub fn owned_container(
+        &self,
+        connection_id: Uuid,
+        container_id: &str,
+    ) -> Option<&BoaContainer> {
+        let container = self.containers.get(container_id)?;
+
+        if container.owner == Some(connection_id) {
+            return Some(container);
+        }
+
+        let identity = &self.connections.get(&connection_id)?.identity;
+
+        (identity.method != AuthMethod::Anonymous
+            && container.owner_identity.as_deref() == Some(identity.name.as_str()))
+        .then_some(container)
+    }
+
+    /// Forgets the containers `connection_id` opened, returning their ids so they can be removed
+    pub fn take_owned_containers(&mut self, connection_id: Uuid, reason: &str) -> Vec<String> {
+        let container_ids = self
+            .containers
+            .values()
+            .filter(|container| container.owner == Some(connection_id))
+            .map(|container| container.container_id.clone())
+            .collect::<Vec<_>>();
+
+        for container_id in &container_ids {
+            self.containers.remove(container_id);
+            self.mark_killed(container_id, reason);
+        }
+
+        container_ids
+    }
 
-            server_port,
-            container_prefix,
+    /// Records why the execs running in `container_id` are about to be stopped
+    pub fn mark_killed(&mut self, container_id: &str, reason: &str) {
+        for exec in self.execs.values_mut() {
+            if exec.container_id == container_id && exec.kill_reason.is_none() {
+                exec.kill_reason = Some(reason.to_string());
+            }
         }
     }
 }
@@ -0,0 +1,299 @@
+mod admin;
+mod sandbox;
+#[cfg(feature = "wasi")]
+mod wasi;
+mod ws;
+
+use std::{mem, net::SocketAddr, sync::Arc, time::Duration};
+
+use boa_core::packets::{
+    client::{
+        ClientPacket,
+        process::{ProcessControlSignal, ProcessControlSignalPacket, ProcessOpenPacket},
+    },
+    server::{ServerPacket, error::ServerError, process::ProcessOpenResultPacket},
+};
+use futures_util::{SinkExt, StreamExt};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::Mutex,
+    time,
+};
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream, connect_async, tungstenite::Message};
+
+use crate::{
+    auth::Authenticator,
+    backend::fake::FakeBackend,
+    config::{Config, ConfigOverrides},
+    state::{ServerState, ShareableServerState},
+};
+
+/// How long a test waits for a packet or a condition before it fails
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The real router, served on a random local port with a fake backend
+pub struct TestServer {
+    addr: SocketAddr,
+    pub state: ShareableServerState,
+    pub backend: FakeBackend,
+}
+
+impl TestServer {
+    pub async fn start() -> TestServer {
+        TestServer::with_config(|_| {}).await
+    }
+
+    /// Starts a server with the default config changed by `configure`, without an audit log
+    pub async fn with_config(configure: impl FnOnce(&mut Config)) -> TestServer {
+        let mut config = Config::default();
+        config.audit.path = None;
+        configure(&mut config);
+
+        let backend = FakeBackend::default();
+        let auth = Authenticator::new(&config.auth).expect("test auth config is valid");
+        let state = Arc::new(Mutex::new(ServerState::new(
+            Arc::new(backend.clone()),
+            None,
+            auth,
+            config,
+            ConfigOverrides::default(),
+        )));
+
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("failed to bind test listener");
+        let addr = listener.local_addr().expect("listener has an address");
+
+        let app = crate::router(&state).into_make_service_with_connect_info::<SocketAddr>();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        TestServer {
+            addr,
+            state,
+            backend,
+        }
+    }
+
+    /// Opens a WebSocket to `/ws`, past the quota status every connection starts with
+    pub async fn connect(&self) -> TestClient {
+        self.connect_to(&format!("ws://{}/ws", self.addr)).await
+    }
+
+    /// Connects like `connect`, authenticated with `token`
+    pub async fn connect_with_token(&self, token: &str) -> TestClient {
+        self.connect_to(&format!("ws://{}/ws?token={token}", self.addr))
+            .await
+    }
+
+    /// Status code and body of a `GET` of the admin api, authorized with `admin_token`
+    pub async fn admin_get(&self, path: &str, admin_token: &str) -> (u16, String) {
+        let mut stream = TcpStream::connect(self.addr)
+            .await
+            .expect("failed to connect to the test server");
+        let request = format!(
+            "GET {path} HTTP/1.1\r\nHost: {}\r\nAuthorization: Bearer {admin_token}\r\n\
+             Connection: close\r\n\r\n",
+            self.addr
+        );
+        stream
+            .write_all(request.as_bytes())
+            .await
+            .expect("failed to send the request");
+
+        let mut response = String::new();
+        time::timeout(TIMEOUT, stream.read_to_string(&mut response))
+            .await
+            .expect("timed out waiting for the response")
+            .expect("failed to read the response");
+
+        let status = response
+            .split(' ')
+            .nth(1)
+            .and_then(|status| status.parse().ok())
+            .unwrap_or_else(|| panic!("invalid response: {response}"));
+        let body = response
+            .split_once("\r\n\r\n")
+            .map(|(_, body)| body.to_string())
+            .unwrap_or_default();
+
+        (status, body)
+    }
+
+    async fn connect_to(&self, url: &str) -> TestClient {
+        let (ws, _) = connect_async(url)
+            .await
+            .expect("failed to connect to the test server");
+
+        let mut client = TestClient { ws };
+        let packet = client.recv_any().await;
+        assert!(
+            matches!(packet, ServerPacket::QuotaStatus(_)),
+            "expected a quota status, got {packet:?}"
+        );
+
+        client
+    }
+}
+
+/// Polls `condition` until it holds, failing the test with `what` after `TIMEOUT`
+pub async fn wait_until(what: &str, condition: impl AsyncFn() -> bool) {
+    let polling = async {
+        while !condition().await {
+            time::sleep(Duration::from_millis(10)).await;
+        }
+    };
+
+    time::timeout(TIMEOUT, polling)
+        .await
+        .unwrap_or_else(|_| panic!("timed out waiting until {what}"));
+}
+
+pub struct TestClient {
+    ws: WebSocketStream<MaybeTlsStream<TcpStream>>,
+}
+
+impl TestClient {
+    pub async fn send(&mut self, packet: ClientPacket) {
+        let text = serde_json::to_string(&packet).expect("client packets serialize");
+        self.send_text(&text).await;
+    }
+
+    pub async fn send_text(&mut self, text: &str) {
+        self.ws
+            .send(Message::text(text))
+            .await
+            .expect("failed to send text frame");
+    }
+
+    pub async fn send_binary(&mut self, bytes: &[u8]) {
+        self.ws
+            .send(Message::binary(bytes.to_vec()))
+            .await
+            .expect("failed to send binary frame");
+    }
+
+    pub async fn signal(&mut self, container_id: &str, control_signal: ProcessControlSignal) {
+        self.send(ClientPacket::ProcessControlSignal(
+            ProcessControlSignalPacket {
+                container_id: container_id.to_string(),
+                control_signal,
+            },
+        ))
+        .await;
+    }
+
+    /// Opens a container of the default runtime and returns its id
+    pub async fn open(&mut self) -> String {
+        self.send(ClientPacket::ProcessOpen(ProcessOpenPacket {
+            runtime: None,
+            snapshot: None,
+        }))
+        .await;
+
+        match self.recv().await {
+            ServerPacket::ProcessOpenResult(ProcessOpenResultPacket { container_id }) => {
+                container_id
+            }
+            packet => panic!("expected an open result, got {packet:?}"),
+        }
+    }
+
+    /// Opens and starts a container, returning once it runs
+    pub async fn open_started(&mut self) -> String {
+        let container_id = self.open().await;
+        self.signal(&container_id, ProcessControlSignal::Start)
+            .await;
+
+        // sent once when the start is requested and once when it is done
+        for _ in 0..2 {
+            let packet = self.recv().await;
+            assert!(
+                matches!(packet, ServerPacket::ProcessEvent(_)),
+                "expected a started event, got {packet:?}"
+            );
+        }
+
+        container_id
+    }
+
+    /// Uploads `chunks` as one file at `path`, in a binary frame each
+    pub async fn upload(&mut self, container_id: &str, path: &str, chunks: &[&[u8]]) {
+        self.send(ClientPacket::UploadStart {
+            container_id: container_id.to_string(),
+            path: path.to_string(),
+            size: chunks.iter().map(|chunk| chunk.len() as u64).sum(),
+        })
+        .await;
+
+        for chunk in chunks {
+            self.send_binary(chunk).await;
+        }
+
+        self.send(ClientPacket::UploadFinish {
+            container_id: container_id.to_string(),
+        })
+        .await;
+    }
+
+    /// Next packet, including quota statuses
+    pub async fn recv_any(&mut self) -> ServerPacket {
+        loop {
+            let message = time::timeout(TIMEOUT, self.ws.next())
+                .await
+                .expect("timed out waiting for a packet")
+                .expect("connection closed while waiting for a packet")
+                .expect("failed to read from the connection");
+
+            match message {
+                Message::Text(text) => {
+                    return serde_json::from_str(&text).expect("server sent an invalid packet");
+                }
+                Message::Close(_) => panic!("connection closed while waiting for a packet"),
+                _ => {}
+            }
+        }
+    }
+
+    /// Next packet that is not a quota status, those are sent after most requests
+    pub async fn recv(&mut self) -> ServerPacket {
+        loop {
+            match self.recv_any().await {
+                ServerPacket::QuotaStatus(_) => {}
+                packet => return packet,
+            }
+        }
+    }
+
+    /// Receives an error of the kind of `expected`, returning its message
+    pub async fn expect_error(&mut self, expected: ServerError) -> String {
+        match self.recv().await {
+            ServerPacket::ServerError(error)
+                if mem::discriminant(&error.err) == mem::discriminant(&expected) =>
+            {
+                error.message
+            }
+            packet => panic!("expected a {expected:?} error, got {packet:?}"),
+        }
+    }
+
+    /// Waits for the server to close the connection, ignoring packets sent before
+    pub async fn expect_closed(&mut self) {
+        let closing = async {
+            loop {
+                match self.ws.next().await {
+                    None | Some(Err(_) | Ok(Message::Close(_))) => return,
+                    Some(Ok(_)) => {}
+                }
+            }
+        };
+
+        time::timeout(TIMEOUT, closing)
+            .await
+            .expect("timed out waiting for the connection to close");
+    }
+
+    pub async fn close(mut self) {
+        self.ws.close(None).await.ok();
+    }
+}
@@ -0,0 +1,44 @@
+use crate::{audit::AuditLog, tests::TestServer};
+
+const ADMIN_TOKEN: &str = "admin-token";
+
+#[tokio::test]
+async fn failed_audit_query_is_an_internal_error() {
+    let dir = tempfile::tempdir().expect("failed to create a temporary directory");
+    let path = dir.path().join("audit.db");
+
+    let server = TestServer::with_config(|config| {
+        config.server.admin_token = Some(ADMIN_TOKEN.to_string());
+    })
+    .await;
+    server.state.lock().await.audit =
+        Some(AuditLog::open(&path).expect("failed to open the audit log"));
+
+    let (status, body) = server.admin_get("/admin/audit", ADMIN_TOKEN).await;
+    assert_eq!(status, 200, "{body}");
+
+    rusqlite::Connection::open(&path)
+        .and_then(|connection| connection.execute_batch("DROP TABLE execs"))
+        .expect("failed to break the audit log");
+
+    let (status, body) = server.admin_get("/admin/audit", ADMIN_TOKEN).await;
+    assert_eq!(status, 500, "{body}");
+    assert!(body.contains("audit log query failed"), "{body}");
+}
+
+#[tokio::test]
+async fn containers_are_listed_with_their_owner_identity() {
+    let server = TestServer::with_config(|config| {
+        config.server.admin_token = Some(ADMIN_TOKEN.to_string());
+    })
+    .await;
+    let mut client = server.connect().await;
+    let container_id = client.open().await;
+
+    let (status, body) = server.admin_get("/admin/containers", ADMIN_TOKEN).await;
+    assert_eq!(status, 200, "{body}");
+
+    let containers: serde_json::Value = serde_json::from_str(&body).expect("invalid listing");
+    assert_eq!(containers[0]["id"], container_id.as_str(), "{body}");
+    assert_eq!(containers[0]["owner_identity"], "anonymous", "{body}");
+}
@@ -0,0 +1,321 @@
+use boa_core::packets::server::error::ServerError;
+use std::time::{Duration, Instant};
+
+use futures_util::StreamExt;
+use tempfile::TempDir;
+use tokio::time;
+use uuid::Uuid;
+
+use crate::{
+    backend::{ContainerBackend, ExecEvent, ExecStream, sandbox::SandboxBackend},
+    config::Config,
+    container::TIMED_OUT_EXIT_CODE,
+    error::BoaError,
+};
+
+/// A started sandbox in a temporary directory, `None` where user namespaces are not available
+async fn sandbox() -> Option<(SandboxBackend, String, TempDir)> {
+    let dir = tempfile::tempdir().expect("failed to create a temporary directory");
+    let mut config = Config::default();
+    config.sandbox.dir = dir.path().join("sandbox");
+
+    let backend = match SandboxBackend::new(&config).await {
+        Ok(backend) => backend,
+        Err(e) => {
+            eprintln!("skipping sandbox test: {e}");
+            return None;
+        }
+    };
+
+    let name = "boa-runner-test".to_string();
+    let image = config
+        .runtime_image(None)
+        .expect("the default runtime exists");
+    backend
+        .create(&name, image, &config)
+        .await
+        .expect("failed to create the sandbox");
+    backend
+        .start(&name)
+        .await
+        .expect("failed to start the sandbox");
+
+    Some((backend, name, dir))
+}
+
+/// Output and exit code of `cmd` run in the sandbox
+async fn exec(backend: &SandboxBackend, name: &str, cmd: &[&str]) -> (String, i64) {
+    exec_with_timeout(backend, name, cmd, 10).await
+}
+
+async fn exec_with_timeout(
+    backend: &SandboxBackend,
+    name: &str,
+    cmd: &[&str],
+    timeout_secs: u64,
+) -> (String, i64) {
+    let mut events = backend
+        .exec(
+            name,
+            Uuid::new_v4(),
+            cmd.iter().map(ToString::to_string).collect(),
+            timeout_secs,
+        )
+        .await
+        .expect("failed to start the exec");
+
+    let mut output = String::new();
+    while let Some(event) = events.next().await {
+        match event.expect("exec failed") {
+            ExecEvent::Stdout(bytes) | ExecEvent::Stderr(bytes) => {
+                output.push_str(&String::from_utf8_lossy(&bytes));
+            }
+            ExecEvent::Exited(exit_code) => return (output, exit_code),
+        }
+    }
+
+    panic!("exec ended without an exit code, output: {output}");
+}
+
+#[tokio::test]
+async fn exec_cannot_read_outside_its_workspace() {
+    let Some((backend, name, dir)) = sandbox().await else {
+        return;
+    };
+
+    let secret = dir.path().join("secret.txt");
+    std::fs::write(&secret, "not for sandboxes").expect("failed to write the secret");
+    let secret = secret.to_string_lossy();
+
+    for path in [secret.to_string(), format!("/.old_root{secret}")] {
+        let (output, exit_code) = exec(&backend, &name, &["cat", &path]).await;
+        assert_ne!(exit_code, 0, "read {path}: {output}");
+        assert!(
+            !output.contains("not for sandboxes"),
+            "read {path}: {output}"
+        );
+    }
+
+    backend
+        .remove(&name)
+        .await
+        .expect("failed to remove the sandbox");
+}
+
+#[tokio::test]
+async fn upload_does_not_follow_symlinks() {
+    let Some((backend, name, dir)) = sandbox().await else {
+        return;
+    };
+
+    let target = dir.path().join("authorized_keys");
+    std::fs::write(&target, "host key").expect("failed to write the target");
+    let target = target.to_string_lossy();
+
+    // the sandbox cannot see the target, but the host would follow the links
+    let (output, exit_code) = exec(
+        &backend,
+        &name,
+        &[
+            "sh",
+            "-c",
+            &format!(
+                "ln -s {target} link && ln -s {} linked_dir",
+                dir.path().display()
+            ),
+        ],
+    )
+    .await;
+    assert_eq!(exit_code, 0, "{output}");
+
+    for (dir, file_name) in [("/src", "link"), ("/src/linked_dir", "authorized_keys")] {
+        let result = backend
+            .upload(&name, dir, file_name, b"sandbox key".to_vec())
+            .await;
+        assert!(
+            matches!(result, Err(BoaError::Refused(ServerError::UploadFailed, _))),
+            "uploaded {dir}/{file_name}: {result:?}"
+        );
+    }
+    assert_eq!(
+        std::fs::read_to_string(&*target).expect("failed to read the target"),
+        "host key"
+    );
+
+    for path in ["/src/link", "/src/linked_dir/authorized_keys"] {
+        let result = backend.download(&name, path).await;
+        assert!(result.is_err(), "downloaded {path}: {result:?}");
+    }
+
+    backend
+        .remove(&name)
+        .await
+        .expect("failed to remove the sandbox");
+}
+
+#[tokio::test]
+async fn exec_runs_unprivileged() {
+    let Some((backend, name, _dir)) = sandbox().await else {
+        return;
+    };
+
+    let (output, exit_code) = exec(
+        &backend,
+        &name,
+        &[
+            "sh",
+            "-c",
+            "id -u && grep -E '^Cap(Eff|Bnd)' /proc/self/status",
+        ],
+    )
+    .await;
+    assert_eq!(exit_code, 0, "{output}");
+
+    let mut lines = output.lines();
+    assert_eq!(lines.next(), Some("1000"), "{output}");
+    for line in lines {
+        assert!(line.ends_with("0000000000000000"), "{output}");
+    }
+
+    backend
+        .remove(&name)
+        .await
+        .expect("failed to remove the sandbox");
+}
+
+/// Starts `script` in the sandbox and waits until it printed `ready`
+async fn exec_until_ready(backend: &SandboxBackend, name: &str, script: &str) -> ExecStream {
+    let mut events = backend
+        .exec(
+            name,
+            Uuid::new_v4(),
+            vec!["sh".to_string(), "-c".to_string(), script.to_string()],
+            60,
+        )
+        .await
+        .expect("failed to start the exec");
+
+    let mut output = String::new();
+    while !output.contains("ready") {
+        match events.next().await {
+            Some(Ok(ExecEvent::Stdout(bytes))) => output.push_str(&String::from_utf8_lossy(&bytes)),
+            _ => panic!("exec did not get ready, output: {output}"),
+        }
+    }
+
+    events
+}
+
+#[tokio::test]
+async fn signal_reaches_the_exec() {
+    let Some((backend, name, _dir)) = sandbox().await else {
+        return;
+    };
+
+    let mut events = exec_until_ready(
+        &backend,
+        &name,
+        "trap 'echo terminated; exit 3' TERM; echo ready; while true; do sleep 0.1; done",
+    )
+    .await;
+
+    let started = Instant::now();
+    backend
+        .signal(&name, "SIGTERM")
+        .await
+        .expect("failed to signal the sandbox");
+    assert!(
+        started.elapsed() < Duration::from_secs(5),
+        "the exec was killed after {:?}",
+        started.elapsed()
+    );
+
+    let mut output = String::new();
+    while let Some(event) = events.next().await {
+        match event.expect("exec failed") {
+            ExecEvent::Stdout(bytes) | ExecEvent::Stderr(bytes) => {
+                output.push_str(&String::from_utf8_lossy(&bytes));
+            }
+            ExecEvent::Exited(exit_code) => {
+                assert_eq!(exit_code, 3, "{output}");
+                break;
+            }
+        }
+    }
+    assert!(output.contains("terminated"), "{output}");
+
+    backend
+        .remove(&name)
+        .await
+        .expect("failed to remove the sandbox");
+}
+
+#[tokio::test]
+async fn timeout_stops_background_processes() {
+    let Some((backend, name, _dir)) = sandbox().await else {
+        return;
+    };
+
+    let started = Instant::now();
+    let (output, exit_code) =
+        exec_with_timeout(&backend, &name, &["sh", "-c", "sleep 60 & sleep 60"], 1).await;
+    assert_eq!(exit_code, TIMED_OUT_EXIT_CODE, "{output}");
+    assert!(
+        started.elapsed() < Duration::from_secs(10),
+        "the exec ran for {:?}",
+        started.elapsed()
+    );
+
+    backend
+        .remove(&name)
+        .await
+        .expect("failed to remove the sandbox");
+}
+
+#[tokio::test]
+async fn pause_stops_the_exec() {
+    let Some((backend, name, _dir)) = sandbox().await else {
+        return;
+    };
+
+    let mut events = exec_until_ready(
+        &backend,
+        &name,
+        "echo ready; while true; do echo tick; sleep 0.05; done",
+    )
+    .await;
+
+    backend
+        .pause(&name)
+        .await
+        .expect("failed to pause the sandbox");
+    // output written before the pause took effect
+    while time::timeout(Duration::from_millis(300), events.next())
+        .await
+        .is_ok()
+    {}
+
+    assert!(
+        time::timeout(Duration::from_millis(500), events.next())
+            .await
+            .is_err(),
+        "the paused exec kept running"
+    );
+
+    backend
+        .resume(&name)
+        .await
+        .expect("failed to resume the sandbox");
+    assert!(
+        matches!(
+            time::timeout(Duration::from_secs(2), events.next()).await,
+            Ok(Some(Ok(ExecEvent::Stdout(_))))
+        ),
+        "the resumed exec did not run"
+    );
+
+    backend
+        .remove(&name)
+        .await
+        .expect("failed to remove the sandbox");
+}
@@ -0,0 +1,181 @@
+use std::time::{Duration, Instant};
+
+use futures_util::StreamExt;
+use tempfile::TempDir;
+use tokio::time;
+use uuid::Uuid;
+
+use crate::{
+    backend::{ContainerBackend, ExecEvent, ExecStream, wasi::WasiBackend},
+    config::Config,
+    container::TIMED_OUT_EXIT_CODE,
+    error::BoaError,
+};
+
+/// Never yields by itself, only the epoch deadline stops it
+const SPIN: &str = r#"(module
+    (memory (export "memory") 1)
+    (func (export "_start") (loop br 0)))"#;
+
+/// Exits with 3 when its memory cannot grow by 2MiB, 0 when it can
+const GROW: &str = r#"(module
+    (import "wasi_snapshot_preview1" "proc_exit" (func $exit (param i32)))
+    (memory (export "memory") 1)
+    (func (export "_start")
+        (if (i32.eq (memory.grow (i32.const 32)) (i32.const -1))
+            (then (call $exit (i32.const 3))))
+        (call $exit (i32.const 0))))"#;
+
+/// Writes `tick` to stdout forever
+const TICK: &str = r#"(module
+    (import "wasi_snapshot_preview1" "fd_write"
+        (func $fd_write (param i32 i32 i32 i32) (result i32)))
+    (memory (export "memory") 1)
+    (data (i32.const 16) "tick\n")
+    (func (export "_start")
+        (i32.store (i32.const 0) (i32.const 16))
+        (i32.store (i32.const 4) (i32.const 5))
+        (loop
+            (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))
+            (br 0))))"#;
+
+/// A started wasi container in a temporary directory, with `module` uploaded as `main.wasm`
+async fn wasi(module: &str, memory_mb: u64) -> (WasiBackend, String, TempDir) {
+    let dir = tempfile::tempdir().expect("failed to create a temporary directory");
+    let mut config = Config::default();
+    config.wasi.dir = dir.path().join("wasi");
+    config.limits.memory_mb = memory_mb;
+
+    let backend = WasiBackend::new(&config)
+        .await
+        .expect("failed to create the wasi backend");
+
+    let name = "boa-runner-test".to_string();
+    let image = config
+        .runtime_image(None)
+        .expect("the default runtime exists");
+    backend
+        .create(&name, image, &config)
+        .await
+        .expect("failed to create the container");
+    backend
+        .start(&name)
+        .await
+        .expect("failed to start the container");
+    backend
+        .upload(
+            &name,
+            &config.containers.working_dir,
+            "main.wasm",
+            module.as_bytes().to_vec(),
+        )
+        .await
+        .expect("failed to upload the module");
+
+    (backend, name, dir)
+}
+
+async fn run(backend: &WasiBackend, name: &str, timeout_secs: u64) -> ExecStream {
+    backend
+        .exec(
+            name,
+            Uuid::new_v4(),
+            vec!["run".to_string(), "main.wasm".to_string()],
+            timeout_secs,
+        )
+        .await
+        .expect("failed to start the module")
+}
+
+/// Exit code of a module, skipping its output
+async fn exit_code(events: &mut ExecStream) -> i64 {
+    while let Some(event) = events.next().await {
+        if let ExecEvent::Exited(exit_code) = event.expect("exec failed") {
+            return exit_code;
+        }
+    }
+
+    panic!("exec ended without an exit code");
+}
+
+#[tokio::test]
+async fn module_that_never_yields_times_out() {
+    let (backend, name, _dir) = wasi(SPIN, 64).await;
+
+    let started = Instant::now();
+    let mut events = run(&backend, &name, 1).await;
+    assert_eq!(exit_code(&mut events).await, TIMED_OUT_EXIT_CODE);
+    assert!(
+        started.elapsed() < Duration::from_secs(5),
+        "the module ran for {:?}",
+        started.elapsed()
+    );
+}
+
+#[tokio::test]
+async fn memory_cannot_grow_over_the_limit() {
+    let (backend, name, _dir) = wasi(GROW, 1).await;
+    let mut events = run(&backend, &name, 10).await;
+    assert_eq!(exit_code(&mut events).await, 3);
+
+    let (backend, name, _dir) = wasi(GROW, 64).await;
+    let mut events = run(&backend, &name, 10).await;
+    assert_eq!(exit_code(&mut events).await, 0);
+}
+
+#[tokio::test]
+async fn paused_module_stops_until_resumed() {
+    let (backend, name, _dir) = wasi(TICK, 64).await;
+    let mut events = run(&backend, &name, 0).await;
+
+    assert!(matches!(
+        events.next().await,
+        Some(Ok(ExecEvent::Stdout(_)))
+    ));
+
+    backend
+        .pause(&name)
+        .await
+        .expect("failed to pause the container");
+    // output the module buffered before it was paused
+    while time::timeout(Duration::from_millis(300), events.next())
+        .await
+        .is_ok()
+    {}
+
+    assert!(
+        time::timeout(Duration::from_millis(500), events.next())
+            .await
+            .is_err(),
+        "the paused module kept running"
+    );
+
+    backend
+        .resume(&name)
+        .await
+        .expect("failed to resume the container");
+    assert!(
+        matches!(
+            time::timeout(Duration::from_secs(2), events.next()).await,
+            Ok(Some(Ok(ExecEvent::Stdout(_))))
+        ),
+        "the resumed module did not run"
+    );
+
+    backend
+        .remove(&name)
+        .await
+        .expect("failed to remove the container");
+}
+
+#[tokio::test]
+async fn ping_fails_without_the_workspaces() {
+    let (backend, _name, dir) = wasi(SPIN, 64).await;
+    backend.ping().await.expect("ping failed");
+
+    std::fs::remove_dir_all(dir.path().join("wasi")).expect("failed to remove the workspaces");
+    assert!(matches!(
+        backend.ping().await,
+        Err(BoaError::BackendUnavailable(_))
+    ));
+}
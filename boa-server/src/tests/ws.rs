@@ -0,0 +1,744 @@
+use std::time::Duration;
+
+use boa_core::packets::{
+    client::{
+        ClientPacket,
+        process::{ProcessClosePacket, ProcessControlSignal, ProcessOpenPacket},
+    },
+    server::{
+        ServerPacket,
+        error::ServerError,
+        process::{ProcessCloseResultPacket, ProcessEventPacket, ProcessOutputPacket},
+    },
+};
+
+use crate::{
+    backend::fake::{KILLED_EXIT_CODE, Script},
+    config::QuotaLimits,
+    tests::{TestServer, wait_until},
+};
+
+/// Receives the events of an exec up to the one it ended with, and the output sent meanwhile
+async fn exec_result(client: &mut crate::tests::TestClient) -> (Vec<String>, ServerPacket) {
+    let mut output = Vec::new();
+
+    loop {
+        match client.recv().await {
+            ServerPacket::ProcessEvent(ProcessEventPacket::Started) => {}
+            ServerPacket::ProcessOutput(ProcessOutputPacket::StdOut(text)) => {
+                output.push(format!("stdout: {text}"));
+            }
+            ServerPacket::ProcessOutput(ProcessOutputPacket::StdErr(text)) => {
+                output.push(format!("stderr: {text}"));
+            }
+            packet => return (output, packet),
+        }
+    }
+}
+
+#[tokio::test]
+async fn open_upload_start_exec_close() {
+    let server = TestServer::start().await;
+    server.backend.script(
+        "main.py",
+        Script::default()
+            .stdout("hello\n")
+            .sleep(Duration::from_millis(20))
+            .stderr("oops\n")
+            .exit_code(3),
+    );
+    let mut client = server.connect().await;
+
+    let container_id = client.open_started().await;
+    client
+        .upload(&container_id, "main.py", &[b"print(", b"'hello')\n"])
+        .await;
+
+    wait_until("the upload reaches the container", async || {
+        server
+            .backend
+            .container(&container_id)
+            .is_some_and(|container| container.files.contains_key("/src/main.py"))
+    })
+    .await;
+    let container = server.backend.container(&container_id).expect("was opened");
+    assert_eq!(container.image, "python:3.11-slim");
+    assert_eq!(container.files["/src/main.py"], b"print('hello')\n");
+    assert!(container.running);
+
+    client
+        .signal(
+            &container_id,
+            ProcessControlSignal::Exec("main.py".to_string()),
+        )
+        .await;
+
+    let (output, result) = exec_result(&mut client).await;
+    assert_eq!(output, ["stdout: hello\n", "stderr: oops\n"]);
+    assert!(matches!(
+        result,
+        ServerPacket::ProcessEvent(ProcessEventPacket::Finished { exit_code: 3 })
+    ));
+
+    client
+        .send(ClientPacket::ProcessClose(ProcessClosePacket {
+            container_id: container_id.clone(),
+        }))
+        .await;
+
+    assert!(matches!(
+        client.recv().await,
+        ServerPacket::ProcessCloseResult(ProcessCloseResultPacket { success: true })
+    ));
+    assert_eq!(server.backend.container_count(), 0);
+    assert!(server.state.lock().await.containers.is_empty());
+}
+
+#[tokio::test]
+async fn exec_timeout_is_reported() {
+    let server = TestServer::with_config(|config| config.limits.exec_timeout_secs = 1).await;
+    server.backend.script(
+        "main.py",
+        Script::default()
+            .stdout("started\n")
+            .sleep(Duration::from_secs(30)),
+    );
+    let mut client = server.connect().await;
+
+    let container_id = client.open_started().await;
+    client
+        .signal(
+            &container_id,
+            ProcessControlSignal::Exec("main.py".to_string()),
+        )
+        .await;
+
+    let (output, result) = exec_result(&mut client).await;
+    assert_eq!(output, ["stdout: started\n"]);
+    assert!(matches!(
+        result,
+        ServerPacket::ProcessEvent(ProcessEventPacket::TimedOut)
+    ));
+}
+
+#[tokio::test]
+async fn exec_failure_is_reported() {
+    let server = TestServer::start().await;
+    server.backend.script(
+        "main.py",
+        Script::default()
+            .stdout("partial\n")
+            .fail("exec stream broke"),
+    );
+    let mut client = server.connect().await;
+
+    let container_id = client.open_started().await;
+    client
+        .signal(
+            &container_id,
+            ProcessControlSignal::Exec("main.py".to_string()),
+        )
+        .await;
+
+    let (output, result) = exec_result(&mut client).await;
+    assert_eq!(output, ["stdout: partial\n"]);
+    match result {
+        ServerPacket::ServerError(error) => {
+            assert!(matches!(error.err, ServerError::ProcessStartFailed));
+            assert_eq!(error.message, "exec stream broke");
+        }
+        packet => panic!("expected an exec error, got {packet:?}"),
+    }
+}
+
+#[tokio::test]
+async fn start_failure_is_reported() {
+    let server = TestServer::start().await;
+    server.backend.fail("start", "no space left on device");
+    let mut client = server.connect().await;
+
+    let container_id = client.open().await;
+    client
+        .signal(&container_id, ProcessControlSignal::Start)
+        .await;
+
+    assert!(matches!(
+        client.recv().await,
+        ServerPacket::ProcessEvent(ProcessEventPacket::Started)
+    ));
+    let message = client.expect_error(ServerError::ProcessStartFailed).await;
+    assert!(message.contains("no space left on device"), "{message}");
+}
+
+#[tokio::test]
+async fn upload_to_unknown_container_is_rejected() {
+    let server = TestServer::start().await;
+    let mut client = server.connect().await;
+
+    client
+        .send(ClientPacket::UploadStart {
+            container_id: "boa-runner-missing".to_string(),
+            path: "main.py".to_string(),
+            size: 4,
+        })
+        .await;
+
+    client.expect_error(ServerError::InvalidContainerId).await;
+
+    // the upload never started, so its frames are unexpected
+    client.send_binary(b"data").await;
+    client
+        .expect_error(ServerError::UnexpectedBinaryFrame)
+        .await;
+}
+
+#[tokio::test]
+async fn second_upload_start_is_rejected() {
+    let server = TestServer::start().await;
+    let mut client = server.connect().await;
+    let container_id = client.open().await;
+
+    for path in ["main.py", "other.py"] {
+        client
+            .send(ClientPacket::UploadStart {
+                container_id: container_id.clone(),
+                path: path.to_string(),
+                size: 4,
+            })
+            .await;
+    }
+    client
+        .expect_error(ServerError::UploadAlreadyInProgress)
+        .await;
+
+    // the first upload is still the one in progress
+    client.send_binary(b"data").await;
+    client
+        .send(ClientPacket::UploadFinish {
+            container_id: container_id.clone(),
+        })
+        .await;
+
+    wait_until("the first upload reaches the container", async || {
+        server
+            .backend
+            .container(&container_id)
+            .is_some_and(|container| container.files.contains_key("/src/main.py"))
+    })
+    .await;
+    let container = server.backend.container(&container_id).expect("was opened");
+    assert!(!container.files.contains_key("/src/other.py"));
+}
+
+#[tokio::test]
+async fn upload_larger_than_declared_closes_connection() {
+    let server = TestServer::start().await;
+    let mut client = server.connect().await;
+    let container_id = client.open().await;
+
+    client
+        .send(ClientPacket::UploadStart {
+            container_id,
+            path: "main.py".to_string(),
+            size: 4,
+        })
+        .await;
+    client.send_binary(b"more than four bytes").await;
+
+    client.expect_error(ServerError::MessageTooLarge).await;
+    client.expect_closed().await;
+}
+
+#[tokio::test]
+async fn upload_over_limit_closes_connection() {
+    let server = TestServer::with_config(|config| config.connections.max_upload_bytes = 1024).await;
+    let mut client = server.connect().await;
+    let container_id = client.open().await;
+
+    client
+        .send(ClientPacket::UploadStart {
+            container_id,
+            path: "main.py".to_string(),
+            size: 1025,
+        })
+        .await;
+
+    let message = client.expect_error(ServerError::MessageTooLarge).await;
+    assert!(message.contains("1025 bytes"), "{message}");
+    client.expect_closed().await;
+}
+
+#[tokio::test]
+async fn invalid_json_is_reported() {
+    let server = TestServer::start().await;
+    let mut client = server.connect().await;
+
+    client.send_text("{\"type\": \"ProcessOpen\"").await;
+
+    client.expect_error(ServerError::InvalidJson).await;
+
+    // the connection stays open
+    client.open().await;
+}
+
+#[tokio::test]
+async fn exec_before_start_is_not_started() {
+    let server = TestServer::start().await;
+    let mut client = server.connect().await;
+
+    let container_id = client.open().await;
+    client
+        .signal(
+            &container_id,
+            ProcessControlSignal::Exec("main.py".to_string()),
+        )
+        .await;
+
+    let (_, result) = exec_result(&mut client).await;
+    match result {
+        ServerPacket::ServerError(error) => assert!(matches!(error.err, ServerError::NotStarted)),
+        packet => panic!("expected an exec error, got {packet:?}"),
+    }
+}
+
+#[tokio::test]
+async fn failed_request_is_reported_and_keeps_connection() {
+    let server = TestServer::start().await;
+    server.backend.fail("pause", "cgroup is gone");
+    let mut client = server.connect().await;
+
+    let container_id = client.open_started().await;
+    client
+        .signal(&container_id, ProcessControlSignal::Pause)
+        .await;
+
+    let message = client.expect_error(ServerError::Internal).await;
+    assert_eq!(message, "cgroup is gone");
+
+    client
+        .signal(&container_id, ProcessControlSignal::Terminate)
+        .await;
+    wait_until("the connection still handles requests", async || {
+        server
+            .backend
+            .container(&container_id)
+            .is_some_and(|container| !container.signals.is_empty())
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn upload_failure_is_reported() {
+    let server = TestServer::start().await;
+    server.backend.fail("upload", "disk full");
+    let mut client = server.connect().await;
+
+    let container_id = client.open_started().await;
+    client.upload(&container_id, "main.py", &[b"print()"]).await;
+
+    let message = client.expect_error(ServerError::UploadFailed).await;
+    assert_eq!(message, "disk full");
+}
+
+#[tokio::test]
+async fn unknown_packet_is_invalid_json() {
+    let server = TestServer::start().await;
+    let mut client = server.connect().await;
+
+    client
+        .send_text(r#"{"type": "ProcessExplode", "data": {}}"#)
+        .await;
+
+    let message = client.expect_error(ServerError::InvalidJson).await;
+    assert!(message.contains("ProcessExplode"), "{message}");
+}
+
+#[tokio::test]
+async fn interrupt_kills_running_exec() {
+    let server = TestServer::start().await;
+    server.backend.script(
+        "main.py",
+        Script::default()
+            .stdout("waiting\n")
+            .sleep(Duration::from_secs(30)),
+    );
+    let mut client = server.connect().await;
+
+    let container_id = client.open_started().await;
+    client
+        .signal(
+            &container_id,
+            ProcessControlSignal::Exec("main.py".to_string()),
+        )
+        .await;
+
+    assert!(matches!(
+        client.recv().await,
+        ServerPacket::ProcessEvent(ProcessEventPacket::Started)
+    ));
+    assert!(matches!(
+        client.recv().await,
+        ServerPacket::ProcessOutput(ProcessOutputPacket::StdOut(_))
+    ));
+
+    client
+        .signal(&container_id, ProcessControlSignal::Interrupt)
+        .await;
+
+    assert!(matches!(
+        client.recv().await,
+        ServerPacket::ProcessEvent(ProcessEventPacket::Finished {
+            exit_code: KILLED_EXIT_CODE
+        })
+    ));
+
+    let container = server.backend.container(&container_id).expect("still open");
+    assert_eq!(container.signals, ["SIGINT"]);
+    assert!(!container.running);
+}
+
+#[tokio::test]
+async fn paused_container_rejects_execs_until_resumed() {
+    let server = TestServer::start().await;
+    let mut client = server.connect().await;
+    let container_id = client.open_started().await;
+
+    client
+        .signal(&container_id, ProcessControlSignal::Pause)
+        .await;
+    assert!(matches!(
+        client.recv().await,
+        ServerPacket::ProcessEvent(ProcessEventPacket::Paused)
+    ));
+    assert!(
+        server
+            .backend
+            .container(&container_id)
+            .expect("open")
+            .paused
+    );
+
+    client
+        .signal(
+            &container_id,
+            ProcessControlSignal::Exec("main.py".to_string()),
+        )
+        .await;
+    let (_, result) = exec_result(&mut client).await;
+    match result {
+        ServerPacket::ServerError(error) => {
+            assert!(matches!(error.err, ServerError::Paused));
+            assert_eq!(error.message, "container is paused");
+        }
+        packet => panic!("expected an exec error, got {packet:?}"),
+    }
+
+    client
+        .signal(&container_id, ProcessControlSignal::Resume)
+        .await;
+    assert!(matches!(
+        client.recv().await,
+        ServerPacket::ProcessEvent(ProcessEventPacket::Resumed)
+    ));
+
+    client
+        .signal(
+            &container_id,
+            ProcessControlSignal::Exec("main.py".to_string()),
+        )
+        .await;
+    let (_, result) = exec_result(&mut client).await;
+    assert!(matches!(
+        result,
+        ServerPacket::ProcessEvent(ProcessEventPacket::Finished { exit_code: 0 })
+    ));
+}
+
+#[tokio::test]
+async fn containers_of_other_connections_are_rejected() {
+    let server = TestServer::start().await;
+    let mut owner = server.connect().await;
+    let mut other = server.connect().await;
+    let container_id = owner.open().await;
+
+    other
+        .signal(&container_id, ProcessControlSignal::Terminate)
+        .await;
+    other.expect_error(ServerError::InvalidContainerId).await;
+
+    other
+        .send(ClientPacket::ProcessClose(ProcessClosePacket {
+            container_id: container_id.clone(),
+        }))
+        .await;
+    other.expect_error(ServerError::InvalidContainerId).await;
+
+    let container = server.backend.container(&container_id).expect("still open");
+    assert!(container.signals.is_empty());
+}
+
+#[tokio::test]
+async fn disconnect_removes_containers() {
+    let server = TestServer::start().await;
+    server
+        .backend
+        .script("main.py", Script::default().sleep(Duration::from_secs(30)));
+    let mut client = server.connect().await;
+
+    let running = client.open_started().await;
+    client.open().await;
+    client
+        .signal(&running, ProcessControlSignal::Exec("main.py".to_string()))
+        .await;
+    assert!(matches!(
+        client.recv().await,
+        ServerPacket::ProcessEvent(ProcessEventPacket::Started)
+    ));
+    assert_eq!(server.backend.container_count(), 2);
+
+    client.close().await;
+
+    wait_until("the containers of the connection are removed", async || {
+        server.backend.container_count() == 0
+    })
+    .await;
+    wait_until("the exec and the connection are gone", async || {
+        let state = server.state.lock().await;
+        state.connections.is_empty() && state.containers.is_empty() && state.execs.is_empty()
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn oversized_snapshot_keeps_the_previous_one() {
+    let server = TestServer::with_config(|config| config.limits.snapshot_max_size_mb = 1).await;
+    let mut client = server.connect().await;
+    let container_id = client.open_started().await;
+
+    client.upload(&container_id, "main.py", &[b"small"]).await;
+    wait_until("the file is uploaded", async || {
+        server
+            .backend
+            .container(&container_id)
+            .is_some_and(|container| !container.files.is_empty())
+    })
+    .await;
+    client
+        .signal(
+            &container_id,
+            ProcessControlSignal::Snapshot("lab".to_string()),
+        )
+        .await;
+    match client.recv().await {
+        ServerPacket::ProcessSnapshotResult(result) => assert_eq!(result.size, 5),
+        packet => panic!("expected a snapshot result, got {packet:?}"),
+    }
+
+    let chunk = vec![0; 64 * 1024];
+    client
+        .upload(&container_id, "data.bin", &[chunk.as_slice(); 17])
+        .await;
+    wait_until("the large file is uploaded", async || {
+        server
+            .backend
+            .container(&container_id)
+            .is_some_and(|container| container.files.len() == 2)
+    })
+    .await;
+    client
+        .signal(
+            &container_id,
+            ProcessControlSignal::Snapshot("lab".to_string()),
+        )
+        .await;
+    let message = client.expect_error(ServerError::SnapshotFailed).await;
+    assert!(message.contains("over the limit"), "{message}");
+
+    // the pending image is gone and the tag still points at the first snapshot
+    let images = server.backend.images();
+    assert_eq!(images.len(), 1, "{images:?}");
+    assert_eq!(
+        images
+            .get("boa-runner-snapshot:lab")
+            .map(|image| image.size),
+        Some(5)
+    );
+}
+
+#[tokio::test]
+async fn snapshot_of_another_identity_is_not_replaced() {
+    let server = TestServer::with_config(|config| {
+        config.auth.api_keys = [("alice", "key-a"), ("bob", "key-b")]
+            .map(|(identity, key)| (identity.to_string(), key.to_string()))
+            .into();
+    })
+    .await;
+    let mut alice = server.connect_with_token("key-a").await;
+    let mut bob = server.connect_with_token("key-b").await;
+
+    let alice_container = alice.open_started().await;
+    alice
+        .signal(
+            &alice_container,
+            ProcessControlSignal::Snapshot("lab".to_string()),
+        )
+        .await;
+    assert!(matches!(
+        alice.recv().await,
+        ServerPacket::ProcessSnapshotResult(_)
+    ));
+
+    let bob_container = bob.open_started().await;
+    bob.signal(
+        &bob_container,
+        ProcessControlSignal::Snapshot("lab".to_string()),
+    )
+    .await;
+    let message = bob.expect_error(ServerError::SnapshotFailed).await;
+    assert!(message.contains("belongs to someone else"), "{message}");
+
+    // the owner can still replace it
+    alice
+        .signal(
+            &alice_container,
+            ProcessControlSignal::Snapshot("lab".to_string()),
+        )
+        .await;
+    assert!(matches!(
+        alice.recv().await,
+        ServerPacket::ProcessSnapshotResult(_)
+    ));
+
+    let images = server.backend.images();
+    assert_eq!(images.len(), 1, "{images:?}");
+    assert_eq!(
+        images["boa-runner-snapshot:lab"].owner.as_deref(),
+        Some("alice")
+    );
+}
+
+#[tokio::test]
+async fn published_snapshot_is_not_replaced() {
+    let server = TestServer::start().await;
+    server.backend.add_image("boa-runner-snapshot:base", 10);
+    let mut client = server.connect().await;
+    let container_id = client.open_started().await;
+
+    client
+        .signal(
+            &container_id,
+            ProcessControlSignal::Snapshot("base".to_string()),
+        )
+        .await;
+    client.expect_error(ServerError::SnapshotFailed).await;
+
+    assert_eq!(server.backend.images()["boa-runner-snapshot:base"].size, 10);
+}
+
+#[tokio::test]
+async fn concurrent_opens_stay_within_the_quota() {
+    let server = TestServer::with_config(|config| {
+        config.auth.api_keys = [("alice".to_string(), "key-a".to_string())].into();
+        config.quotas.default.max_containers = 1;
+    })
+    .await;
+    server.backend.delay_create(Duration::from_millis(200));
+    let mut first = server.connect_with_token("key-a").await;
+    let mut second = server.connect_with_token("key-a").await;
+
+    for client in [&mut first, &mut second] {
+        client
+            .send(ClientPacket::ProcessOpen(ProcessOpenPacket {
+                runtime: None,
+                snapshot: None,
+            }))
+            .await;
+    }
+
+    let mut opened = 0;
+    for client in [&mut first, &mut second] {
+        match client.recv().await {
+            ServerPacket::ProcessOpenResult(_) => opened += 1,
+            ServerPacket::ServerError(error) => {
+                assert!(matches!(error.err, ServerError::QuotaExceeded), "{error:?}");
+            }
+            packet => panic!("expected an open result or error, got {packet:?}"),
+        }
+    }
+    assert_eq!(opened, 1);
+    assert_eq!(server.backend.container_count(), 1);
+    let status = server.state.lock().await.quota_status("alice");
+    assert_eq!(status.containers.used, 1);
+}
+
+#[tokio::test]
+async fn failed_open_releases_its_quota_slot() {
+    let server = TestServer::with_config(|config| {
+        config.quotas.identities.insert(
+            "anonymous".to_string(),
+            QuotaLimits {
+                max_containers: 1,
+                ..QuotaLimits::default()
+            },
+        );
+    })
+    .await;
+    server.backend.fail("create", "no space left on device");
+    let mut client = server.connect().await;
+
+    for _ in 0..2 {
+        client
+            .send(ClientPacket::ProcessOpen(ProcessOpenPacket {
+                runtime: None,
+                snapshot: None,
+            }))
+            .await;
+        client.expect_error(ServerError::ProcessStartFailed).await;
+    }
+}
+
+#[tokio::test]
+async fn default_quotas_do_not_apply_without_auth() {
+    let server = TestServer::with_config(|config| config.quotas.default.max_containers = 1).await;
+    let mut first = server.connect().await;
+    let mut second = server.connect().await;
+
+    first.open().await;
+    second.open().await;
+
+    assert_eq!(server.backend.container_count(), 2);
+    let status = server.state.lock().await.quota_status("anonymous");
+    assert_eq!(status.containers.limit, None);
+    assert_eq!(status.execs.limit, None);
+}
+
+#[tokio::test]
+async fn anonymous_quota_status_reports_the_enforced_limits() {
+    let server = TestServer::with_config(|config| {
+        config.quotas.identities.insert(
+            "anonymous".to_string(),
+            QuotaLimits {
+                max_containers: 1,
+                ..QuotaLimits::default()
+            },
+        );
+    })
+    .await;
+    let mut client = server.connect().await;
+
+    let status = server.state.lock().await.quota_status("anonymous");
+    assert_eq!(status.containers.limit, Some(1));
+    assert_eq!(status.execs.limit, Some(QuotaLimits::default().max_execs));
+
+    client.open().await;
+    let status = server.state.lock().await.quota_status("anonymous");
+    assert!(status.containers.is_exhausted(), "{status:?}");
+
+    client
+        .send(ClientPacket::ProcessOpen(ProcessOpenPacket {
+            runtime: None,
+            snapshot: None,
+        }))
+        .await;
+    client.expect_error(ServerError::QuotaExceeded).await;
+}
@@ -0,0 +1,194 @@
+use std::{
+    fs, io,
+    net::SocketAddr,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use axum::serve::Listener;
+use rustls::{
+    ServerConfig,
+    crypto::ring,
+    pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject},
+};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+    time,
+};
+use tokio_rustls::{TlsAcceptor, server::TlsStream};
+use tracing::{debug, error, info, instrument};
+
+use crate::config::TlsConfig;
+
+/// Connections that have not finished their handshake by then are dropped
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often the certificate and key files are checked for changes
+const WATCH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Certificate and key new connections are accepted with, replaced when the files change.
+/// Open connections keep the certificate they were accepted with.
+pub struct TlsCertificate {
+    paths: TlsConfig,
+    server_config: RwLock<Arc<ServerConfig>>,
+}
+
+impl TlsCertificate {
+    pub fn load(paths: &TlsConfig) -> Result<TlsCertificate, String> {
+        Ok(TlsCertificate {
+            paths: paths.clone(),
+            server_config: RwLock::new(server_config(paths)?),
+        })
+    }
+
+    fn acceptor(&self) -> TlsAcceptor {
+        let server_config = self.server_config.read().expect("tls lock poisoned");
+
+        TlsAcceptor::from(Arc::clone(&server_config))
+    }
+
+    fn reload(&self) -> Result<(), String> {
+        let server_config = server_config(&self.paths)?;
+        *self.server_config.write().expect("tls lock poisoned") = server_config;
+
+        Ok(())
+    }
+
+    fn modified(&self) -> Option<(SystemTime, SystemTime)> {
+        let modified = |path| fs::metadata(path).and_then(|metadata| metadata.modified());
+
+        Some((
+            modified(&self.paths.cert_path).ok()?,
+            modified(&self.paths.key_path).ok()?,
+        ))
+    }
+}
+
+fn server_config(paths: &TlsConfig) -> Result<Arc<ServerConfig>, String> {
+    let certs = CertificateDer::pem_file_iter(&paths.cert_path)
+        .and_then(Iterator::collect::<Result<Vec<_>, _>>)
+        .map_err(|e| {
+            format!(
+                "failed to read tls certificate {}: {e}",
+                paths.cert_path.display()
+            )
+        })?;
+
+    if certs.is_empty() {
+        return Err(format!(
+            "no certificates found in {}",
+            paths.cert_path.display()
+        ));
+    }
+
+    let key = PrivateKeyDer::from_pem_file(&paths.key_path).map_err(|e| {
+        format!(
+            "failed to read tls private key {}: {e}",
+            paths.key_path.display()
+        )
+    })?;
+
+    let mut server_config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| format!("failed to configure tls: {e}"))?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| format!("invalid tls certificate or key: {e}"))?;
+
+    // WebSockets are upgraded from HTTP/1.1 requests
+    server_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+    Ok(Arc::new(server_config))
+}
+
+/// Reloads the certificate when its files are modified, keeping the old one when the new
+/// files are invalid, e.g. while only one of them has been replaced yet
+#[instrument(name = "tls_reload", skip_all)]
+pub async fn reload_on_change(certificate: Arc<TlsCertificate>) {
+    let mut loaded = certificate.modified();
+    let mut interval = time::interval(WATCH_INTERVAL);
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+
+        let modified = certificate.modified();
+        if modified.is_none() || modified == loaded {
+            continue;
+        }
+
+        // invalid files are retried once they are modified again
+        loaded = modified;
+
+        match certificate.reload() {
+            Ok(()) => info!("reloaded tls certificate"),
+            Err(e) => error!("keeping the old tls certificate: {e}"),
+        }
+    }
+}
+
+/// Accepts TCP connections and hands them to axum once their TLS handshake is done. Handshakes
+/// run in their own tasks, so a slow client cannot hold up the ones behind it.
+pub struct TlsListener {
+    connections: mpsc::Receiver<(TlsStream<TcpStream>, SocketAddr)>,
+    local_addr: SocketAddr,
+}
+
+impl TlsListener {
+    pub fn new(listener: TcpListener, certificate: Arc<TlsCertificate>) -> io::Result<TlsListener> {
+        let local_addr = listener.local_addr()?;
+        let (tx, connections) = mpsc::channel(64);
+
+        tokio::spawn(accept(listener, certificate, tx));
+
+        Ok(TlsListener {
+            connections,
+            local_addr,
+        })
+    }
+}
+
+async fn accept(
+    mut listener: TcpListener,
+    certificate: Arc<TlsCertificate>,
+    tx: mpsc::Sender<(TlsStream<TcpStream>, SocketAddr)>,
+) {
+    loop {
+        // stops listening once axum drops the `TlsListener` on shutdown
+        let (stream, remote_addr) = tokio::select! {
+            accepted = Listener::accept(&mut listener) => accepted,
+            () = tx.closed() => return,
+        };
+
+        let acceptor = certificate.acceptor();
+        let tx = tx.clone();
+
+        tokio::spawn(async move {
+            match time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                Ok(Ok(stream)) => {
+                    tx.send((stream, remote_addr)).await.ok();
+                }
+                Ok(Err(e)) => debug!(%remote_addr, "tls handshake failed: {e}"),
+                Err(_) => debug!(%remote_addr, "tls handshake timed out"),
+            }
+        });
+    }
+}
+
+impl Listener for TlsListener {
+    type Io = TlsStream<TcpStream>;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match self.connections.recv().await {
+            Some(connection) => connection,
+            // the accept task only ends after the listener is dropped
+            None => std::future::pending().await,
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        Ok(self.local_addr)
+    }
+}
@@ -6,10 +6,18 @@ pub enum ProcessControlSignal {
     Start,
     /// Send a exec request to the container
     Exec(String),
+    /// Install the dependencies from an uploaded requirements file with pip
+    InstallRequirements(String),
     /// Send a SIGINT to the container
     Interrupt,
     /// Send a SIGTERM to the container
     Terminate,
+    /// Freeze every process in the container, keeping its memory
+    Pause,
+    /// Unfreeze a paused container
+    Resume,
+    /// Commit the container to a snapshot image with the given tag
+    Snapshot(String),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
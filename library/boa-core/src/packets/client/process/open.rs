@@ -1,4 +1,11 @@
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
-pub struct ProcessOpenPacket {}
+pub struct ProcessOpenPacket {
+    /// Name of a configured runtime, the server's default runtime is used when unset
+    #[serde(default)]
+    pub runtime: Option<String>,
+    /// Tag of a snapshot to open the container from, instead of the default image
+    #[serde(default)]
+    pub snapshot: Option<String>,
+}
@@ -1,13 +1,18 @@
 pub mod error;
+pub mod event;
 pub mod process;
+pub mod quota;
 
 use serde::{Deserialize, Serialize};
 
 use crate::packets::server::{
     error::ServerErrorPacket,
+    event::ServerEventPacket,
     process::{
         ProcessCloseResultPacket, ProcessEventPacket, ProcessOpenResultPacket, ProcessOutputPacket,
+        ProcessSnapshotResultPacket,
     },
+    quota::QuotaStatusPacket,
 };
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -17,6 +22,10 @@ pub enum ServerPacket {
     ProcessCloseResult(ProcessCloseResultPacket),
     ProcessOutput(ProcessOutputPacket),
     ProcessEvent(ProcessEventPacket),
+    ProcessSnapshotResult(ProcessSnapshotResultPacket),
 
+    QuotaStatus(QuotaStatusPacket),
+
+    ServerEvent(ServerEventPacket),
     ServerError(ServerErrorPacket),
 }
@@ -9,6 +9,26 @@ pub enum ServerError {
     UploadAlreadyInProgress,
     UploadFailed,
     UnexpectedBinaryFrame,
+    ServerDraining,
+    SnapshotFailed,
+    SnapshotNotFound,
+    InvalidRuntime,
+    QuotaExceeded,
+    TooManyConnections,
+    RateLimited,
+    MessageTooLarge,
+    /// The backend could not be reached, e.g. the docker daemon is down
+    BackendUnavailable,
+    /// The container has to be started first
+    NotStarted,
+    /// The container has to be resumed first
+    Paused,
+    /// A file, container or image does not exist
+    NotFound,
+    /// The backend did not answer in time
+    Timeout,
+    /// Anything else that went wrong on the server
+    Internal,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
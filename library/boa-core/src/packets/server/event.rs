@@ -0,0 +1,7 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ServerEventPacket {
+    /// The server is shutting down, open processes are removed after `deadline_secs`
+    Draining { deadline_secs: u64 },
+}
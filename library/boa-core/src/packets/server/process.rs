@@ -2,8 +2,10 @@ mod close_result;
 mod event;
 mod open_result;
 mod output;
+mod snapshot_result;
 
 pub use close_result::ProcessCloseResultPacket;
 pub use event::ProcessEventPacket;
 pub use open_result::ProcessOpenResultPacket;
 pub use output::ProcessOutputPacket;
+pub use snapshot_result::ProcessSnapshotResultPacket;
@@ -5,4 +5,6 @@ pub enum ProcessEventPacket {
     Started,
     Finished { exit_code: i64 },
     TimedOut,
+    Paused,
+    Resumed,
 }
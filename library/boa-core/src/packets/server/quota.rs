@@ -0,0 +1,41 @@
+use serde::{Deserialize, Serialize};
+
+/// Usage of one limit, `limit` and `remaining` are `None` when it is unlimited
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuotaUsage {
+    pub used: u64,
+    pub limit: Option<u64>,
+    pub remaining: Option<u64>,
+}
+
+impl QuotaUsage {
+    #[must_use]
+    pub fn new(used: u64, limit: u64) -> QuotaUsage {
+        let limit = Some(limit).filter(|limit| *limit > 0);
+
+        QuotaUsage {
+            used,
+            limit,
+            remaining: limit.map(|limit| limit.saturating_sub(used)),
+        }
+    }
+
+    #[must_use]
+    pub fn is_exhausted(&self) -> bool {
+        self.remaining == Some(0)
+    }
+}
+
+/// Quota of the identity a connection authenticated as, shared by all of its connections
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuotaStatusPacket {
+    pub identity: String,
+    /// Open containers
+    pub containers: QuotaUsage,
+    /// Running execs
+    pub execs: QuotaUsage,
+    /// Execs started in the last hour
+    pub execs_per_hour: QuotaUsage,
+    /// CPU seconds used by execs in the last 24 hours
+    pub cpu_secs_per_day: QuotaUsage,
+}